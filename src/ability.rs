@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::hooks::Trigger;
+use crate::monster;
+
+pub enum Condition {
+    Always,
+    HealthBelow(f32),
+    HealthAbove(f32),
+    MoveType(String),
+}
+
+pub enum Effect {
+    Damage(f32),
+    SelfAttack(i32),
+    SelfDefense(i32),
+    OppAttack(i32),
+    OppDefense(i32),
+    Heal(f32),
+}
+
+// A rule fires its effect at its trigger when every one of its conditions holds
+pub struct Rule {
+    pub trigger: Trigger,
+    pub conditions: Vec<Condition>,
+    pub effect: Effect,
+}
+
+pub struct Ability {
+    pub name: String,
+    pub description: String,
+    pub rules: Vec<Rule>,
+}
+
+fn parse_trigger(trigger: &str) -> Result<Trigger, String> {
+    match trigger {
+        "Entry" => Ok(Trigger::Entry),
+        "Attacking" => Ok(Trigger::Attacking),
+        "Defending" => Ok(Trigger::Defending),
+        "HealthDropped" => Ok(Trigger::HealthDropped),
+        "EndOfTurn" => Ok(Trigger::EndOfTurn),
+        _ => Err(format!("Unknown ability trigger {}", trigger)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<&&str>, of: &str) -> Result<T, String> {
+    match value {
+        Some(d) => d.parse::<T>().map_err(|_| format!("{} should be a number in {}", d, of)),
+        None => Err(format!("Missing a value in {}", of)),
+    }
+}
+
+// Conditions are joined with '&', e.g. "HealthBelow 34&MoveType Fire"
+fn parse_conditions(conditions: &str) -> Result<Vec<Condition>, String> {
+    conditions
+        .split("&")
+        .map(|d| {
+            let v = d.splitn(2, " ").collect::<Vec<&str>>();
+            match v[0] {
+                "Always" => Ok(Condition::Always),
+                "HealthBelow" => parse_number(v.get(1), d).map(Condition::HealthBelow),
+                "HealthAbove" => parse_number(v.get(1), d).map(Condition::HealthAbove),
+                "MoveType" => match v.get(1) {
+                    Some(t) => Ok(Condition::MoveType(String::from(*t))),
+                    None => Err(format!("Missing a type in {}", d)),
+                },
+                _ => Err(format!("Unknown ability condition {}", d)),
+            }
+        })
+        .collect()
+}
+
+fn parse_effect(effect: &str, value: &str) -> Result<Effect, String> {
+    let of = format!("{} {}", effect, value);
+    let value = Some(&value);
+    match effect {
+        "Damage" => parse_number(value, &of).map(Effect::Damage),
+        "SelfAttack" => parse_number(value, &of).map(Effect::SelfAttack),
+        "SelfDefense" => parse_number(value, &of).map(Effect::SelfDefense),
+        "OppAttack" => parse_number(value, &of).map(Effect::OppAttack),
+        "OppDefense" => parse_number(value, &of).map(Effect::OppDefense),
+        "Heal" => parse_number(value, &of).map(Effect::Heal),
+        _ => Err(format!("Unknown ability effect {}", effect)),
+    }
+}
+
+// Reads one line of abilities.txt into the ability's name and description and the rule it adds
+fn parse_rule(line: &str) -> Result<(String, String, Rule), String> {
+    let v = line.split(",").collect::<Vec<&str>>();
+    if v.len() < 6 {
+        return Err(format!("Expected 6 fields but found {}", v.len()));
+    }
+    let rule = Rule {
+        trigger: parse_trigger(v[1])?,
+        conditions: parse_conditions(v[2])?,
+        effect: parse_effect(v[3], v[4])?,
+    };
+    Ok((String::from(v[0]), String::from(v[5]), rule))
+}
+
+// An ability may span several lines of abilities.txt, each adding one rule
+pub fn load_abilities() -> HashMap<String, Ability> {
+    let reader =
+        BufReader::new(File::open("./data/abilities.txt").expect("Cannot open abilities.txt"));
+    let mut abilities: HashMap<String, Ability> = HashMap::new();
+    for (n, line) in reader.lines().skip(1).enumerate() {
+        let line = line.unwrap();
+        // Line numbers count the header
+        let (name, description, rule) = parse_rule(&line)
+            .unwrap_or_else(|e| panic!("abilities.txt line {}: {} ({})", n + 2, e, line));
+        abilities
+            .entry(name.clone())
+            .or_insert_with(|| Ability {
+                name,
+                description,
+                rules: Vec::new(),
+            })
+            .rules
+            .push(rule);
+    }
+    abilities
+}
+
+fn condition_met(condition: &Condition, health: f32, attack: Option<&monster::Move>) -> bool {
+    match condition {
+        Condition::Always => true,
+        Condition::HealthBelow(h) => health < *h,
+        Condition::HealthAbove(h) => health > *h,
+        Condition::MoveType(t) => match attack {
+            Some(a) => a.damage > 0 && a.attack_type == *t,
+            None => false,
+        },
+    }
+}
+
+/// Returns the effects of every rule of an ability that fire at the given trigger
+///
+/// * `ability` - The ability being evaluated
+/// * `trigger` - The point of the battle being checked
+/// * `health` - The health (0-100) of the monster with the ability
+/// * `attack` - The move being used, for damage triggers
+pub fn triggered_effects<'a>(
+    ability: &'a Ability,
+    trigger: Trigger,
+    health: f32,
+    attack: Option<&monster::Move>,
+) -> Vec<&'a Effect> {
+    ability
+        .rules
+        .iter()
+        .filter(|d| d.trigger == trigger)
+        .filter(|d| d.conditions.iter().all(|c| condition_met(c, health, attack)))
+        .map(|d| &d.effect)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_move(attack_type: &str, damage: u32) -> monster::Move {
+        monster::Move {
+            name: String::from("Test"),
+            damage,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+            attack_type: String::from(attack_type),
+            effect: String::new(),
+            move_effect: monster::MoveEffect::None,
+            spread: false,
+        }
+    }
+
+    fn ability(lines: &[&str]) -> Ability {
+        let mut rules = Vec::new();
+        for line in lines.iter() {
+            rules.push(parse_rule(line).unwrap().2);
+        }
+        Ability {
+            name: String::from("Test"),
+            description: String::new(),
+            rules,
+        }
+    }
+
+    #[test]
+    fn rules_parse_from_lines() {
+        let (name, description, rule) =
+            parse_rule("Blaze,Attacking,HealthBelow 34&MoveType Fire,Damage,1.5,Fire moves hit harder").unwrap();
+        assert_eq!(name, "Blaze");
+        assert_eq!(description, "Fire moves hit harder");
+        assert!(rule.trigger == Trigger::Attacking);
+        assert_eq!(rule.conditions.len(), 2);
+        match rule.effect {
+            Effect::Damage(mult) => assert_eq!(mult, 1.5),
+            _ => panic!("Expected a damage effect"),
+        }
+    }
+
+    #[test]
+    fn bad_rules_are_errors() {
+        assert!(parse_rule("Blaze,Attacking,Always,Damage").is_err());
+        assert!(parse_rule("Blaze,Sometimes,Always,Damage,1.5,x").is_err());
+        assert!(parse_rule("Blaze,Attacking,HealthBelow,Damage,1.5,x").is_err());
+        assert!(parse_rule("Blaze,Attacking,HealthBelow half,Damage,1.5,x").is_err());
+        assert!(parse_rule("Blaze,Attacking,Raining,Damage,1.5,x").is_err());
+        assert!(parse_rule("Blaze,Attacking,Always,Damage,lots,x").is_err());
+        assert!(parse_rule("Blaze,Attacking,Always,Explode,1,x").is_err());
+    }
+
+    #[test]
+    fn effects_need_every_condition() {
+        let blaze = ability(&["Blaze,Attacking,HealthBelow 34&MoveType Fire,Damage,1.5,x"]);
+        let fire = test_move("Fire", 40);
+        let water = test_move("Water", 40);
+        let fire_status = test_move("Fire", 0);
+
+        assert_eq!(triggered_effects(&blaze, Trigger::Attacking, 20.0, Some(&fire)).len(), 1);
+        assert!(triggered_effects(&blaze, Trigger::Attacking, 50.0, Some(&fire)).is_empty());
+        assert!(triggered_effects(&blaze, Trigger::Attacking, 20.0, Some(&water)).is_empty());
+        // Type conditions only match damaging moves
+        assert!(triggered_effects(&blaze, Trigger::Attacking, 20.0, Some(&fire_status)).is_empty());
+        assert!(triggered_effects(&blaze, Trigger::Defending, 20.0, Some(&fire)).is_empty());
+    }
+
+    #[test]
+    fn effects_only_fire_at_their_trigger() {
+        let guard = ability(&[
+            "Guard,Entry,Always,SelfDefense,1,x",
+            "Guard,EndOfTurn,HealthAbove 50,Heal,10,x",
+        ]);
+        assert_eq!(triggered_effects(&guard, Trigger::Entry, 100.0, None).len(), 1);
+        assert_eq!(triggered_effects(&guard, Trigger::EndOfTurn, 80.0, None).len(), 1);
+        assert!(triggered_effects(&guard, Trigger::EndOfTurn, 30.0, None).is_empty());
+        assert!(triggered_effects(&guard, Trigger::Attacking, 100.0, None).is_empty());
+    }
+
+    #[test]
+    fn abilities_load_from_data() {
+        let abilities = load_abilities();
+        assert!(abilities.contains_key("Levitate"));
+        assert!(abilities.values().all(|d| !d.rules.is_empty()));
+    }
+}
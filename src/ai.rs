@@ -1,36 +1,515 @@
-use crate::battle;
-use crate::monster;
+use crate::hooks;
+use crate::monster::{self, TeamMember};
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Battles played to compare evaluation weights are this many on this many
+const TUNING_TEAM_SIZE: usize = 3;
+// Turns before a tuning battle is called a draw
+const TUNING_MAX_TURNS: u32 = 200;
+
+// How hard the enemy tries, picked on the difficulty screen
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+// The difficulty screen lists easy, normal and hard from the top
+pub fn difficulty_from_choice(choice: usize) -> Difficulty {
+    match choice {
+        0 => Difficulty::Easy,
+        2 => Difficulty::Hard,
+        _ => Difficulty::Normal,
+    }
+}
+
+pub fn difficulty_name(difficulty: Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Easy => "Easy",
+        Difficulty::Normal => "Normal",
+        Difficulty::Hard => "Hard",
+    }
+}
+
+pub fn parse_difficulty(difficulty: &str) -> Result<Difficulty, String> {
+    match difficulty {
+        "Easy" => Ok(Difficulty::Easy),
+        "Normal" => Ok(Difficulty::Normal),
+        "Hard" => Ok(Difficulty::Hard),
+        _ => Err(format!("Unknown difficulty {}", difficulty)),
+    }
+}
+
+// How a trainer battles, set for each trainer in data/trainers.txt
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Profile {
+    // Searches the way the difficulty says
+    Standard,
+    // Goes for the most damage
+    Aggressive,
+    // Sets up stat stages and switches into better matchups
+    Defensive,
+    // Sometimes acts at random
+    Erratic,
+    // Searches in full, even on easy
+    GymLeader,
+}
+
+pub fn profile_name(profile: Profile) -> &'static str {
+    match profile {
+        Profile::Standard => "standard",
+        Profile::Aggressive => "aggressive",
+        Profile::Defensive => "defensive",
+        Profile::Erratic => "erratic",
+        Profile::GymLeader => "gym_leader",
+    }
+}
+
+pub fn parse_profile(profile: &str) -> Result<Profile, String> {
+    match profile {
+        "standard" => Ok(Profile::Standard),
+        "aggressive" => Ok(Profile::Aggressive),
+        "defensive" => Ok(Profile::Defensive),
+        "erratic" => Ok(Profile::Erratic),
+        "gym_leader" => Ok(Profile::GymLeader),
+        _ => Err(format!("Unknown AI profile {}", profile)),
+    }
+}
+
+// The set in data/ai_weights.txt a profile evaluates the battle with
+pub fn profile_weights(profile: Profile) -> &'static str {
+    match profile {
+        Profile::Aggressive => "aggressive",
+        Profile::Defensive => "defensive",
+        _ => "default",
+    }
+}
+
+// How often an erratic trainer acts at random; less often on harder difficulties
+fn erratic_chance(difficulty: Difficulty) -> f64 {
+    match difficulty {
+        Difficulty::Easy => 0.4,
+        Difficulty::Normal => 0.25,
+        Difficulty::Hard => 0.1,
+    }
+}
+
+// How much more a defensive trainer values setting up or switching than its search says,
+// in the same units as the evaluation function
+const DEFENSIVE_BONUS: f64 = 20.0;
+
+// How MCTS plays out a position once it leaves the tree
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rollout {
+    // Any action, at random
+    Random,
+    // The move expected to deal the most damage
+    Greedy,
+}
+
+// When a search stops; a fixed number of iterations always picks the same action for the same seed,
+// while a time limit depends on how fast the machine is
+#[derive(Clone, Copy, PartialEq)]
+pub enum Budget {
+    // MCTS iterations, or positions visited by α-β
+    Iterations(u32),
+    Millis(u64),
+}
+
+#[derive(Clone, Copy)]
+pub struct MctsConfig {
+    pub budget: Budget,
+    pub rollout: Rollout,
+    // Weight of the exploration term in UCT; higher tries less visited actions more often
+    pub exploration: f64,
+    // Most actions played in a rollout before the position is evaluated
+    pub rollout_depth: u32,
+}
+
+// How much each feature of a battle state counts towards its evaluation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Weights {
+    // Per point of summed health percentage
+    pub health: f64,
+    // Per monster that can still fight
+    pub alive: f64,
+    // Per step of type effectiveness the lead's best move has over the other lead's
+    pub matchup: f64,
+    // Per attack or defense stage
+    pub stages: f64,
+    // For being the side that moves next
+    pub tempo: f64,
+}
+
+// Only counts health, like the evaluation before the other features were added
+pub const HEALTH_ONLY: Weights = Weights {
+    health: 1.0,
+    alive: 0.0,
+    matchup: 0.0,
+    stages: 0.0,
+    tempo: 0.0,
+};
+
+// Maps each named set of weights onto its weights
+pub fn load_weights() -> HashMap<String, Weights> {
+    let reader = BufReader::new(File::open("./data/ai_weights.txt").expect("Cannot open ai_weights.txt"));
+    let mut weights = HashMap::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+        let weight = |i: usize| v[i].parse::<f64>().expect("AI weight should be a number");
+
+        weights.insert(
+            String::from(v[0]),
+            Weights {
+                health: weight(1),
+                alive: weight(2),
+                matchup: weight(3),
+                stages: weight(4),
+                tempo: weight(5),
+            },
+        );
+    }
+    weights
+}
+
+// What trainers search with, bundled so each battle can pass its own
+#[derive(Clone, Copy)]
+pub struct SearchSettings {
+    pub difficulty: Difficulty,
+    pub profile: Profile,
+    // The deepest α-β search to try
+    pub depth: i32,
+    // How much α-β may search before it stops going deeper
+    pub budget: Budget,
+    // How MCTS searches on hard
+    pub hard_search: MctsConfig,
+    pub weights: Weights,
+}
+
+// How many positions each of a battle's deepening α-β searches visited, in the order they ran.
+// Battles search against the clock and record here; a replay gives each search the recorded
+// count as its budget instead, so it picks the same actions however fast the machine is.
+#[derive(Clone)]
+pub struct SearchLog {
+    pub visited: Vec<u32>,
+    // How many of the recorded searches a replay has used up; None while recording
+    replayed: Option<usize>,
+}
+
+impl SearchLog {
+    pub fn new() -> SearchLog {
+        SearchLog {
+            visited: Vec::new(),
+            replayed: None,
+        }
+    }
+
+    pub fn replay(visited: Vec<u32>) -> SearchLog {
+        SearchLog {
+            visited,
+            replayed: Some(0),
+        }
+    }
+
+    // The budget for the next search: where it stopped when replaying, otherwise the given one.
+    // Replays from before searches were recorded run out of counts and fall back to the given budget.
+    fn next_budget(&mut self, budget: Budget) -> Budget {
+        match self.replayed {
+            Some(index) if index < self.visited.len() => {
+                self.replayed = Some(index + 1);
+                Budget::Iterations(self.visited[index])
+            }
+            _ => budget,
+        }
+    }
+
+    fn record(&mut self, visited: u32) {
+        if self.replayed.is_none() {
+            self.visited.push(visited);
+        }
+    }
+}
+
+impl Default for SearchLog {
+    fn default() -> SearchLog {
+        SearchLog::new()
+    }
+}
 
 /// Returns the sum of health percentages (0-100) for all team monsters
 ///
-/// * `team` - The team *(as vector of (str monster, health))*
-fn total_team_health(team: &Vec<(String, f32)>) -> f32 {
-    return team.iter().map(|d| d.1).sum();
+/// * `team` - The team, either side's
+fn total_team_health<T: TeamMember>(team: &[T]) -> f32 {
+    team.iter().map(|d| d.health()).sum()
+}
+
+// The best type effectiveness any of the attacker's damaging moves has against the defender
+fn best_effectiveness(attacker: &monster::Monster, defender: &monster::Monster) -> f64 {
+    attacker
+        .moves
+        .iter()
+        .filter(|d| d.damage > 0)
+        .map(|d| monster::effectiveness(d, &defender.monster_type) as f64)
+        .fold(0.0, f64::max)
 }
 
 /// Returns a numeric evaluation of the current battle state
 ///
 /// Used in the α-β algorithm to give an estimated payoff for non-terminal states.
 /// In our case, the maximizing player is our AI; and the minimizing player is the player.
+/// Each feature is the AI's side minus the player's, scaled by its weight.
 ///
-/// * `min_team` - The team *(as vector of (str monster, health))* of the minimizing player
-/// * `max_team` - The team *(as vector of (str monster, health))* of the maximizing player
-fn evaluation_function(min_team: &Vec<(String, f32)>, max_team: &Vec<(String, f32)>) -> f64 {
-    let min_team_health: f32 = total_team_health(min_team);
-    let max_team_health: f32 = total_team_health(max_team);
-    return (max_team_health - min_team_health) as f64;
+/// * `monsters` - Maps strings onto their Monster objects; needed for type matchups
+/// * `state` - The battle state to evaluate
+/// * `maximizing_player` - Whether the AI moves next
+/// * `weights` - How much each feature counts
+pub fn evaluation_function(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> f64 {
+    let min_team_health: f32 = total_team_health(&state.player_team);
+    let max_team_health: f32 = total_team_health(&state.enemy_team);
+    let mut value = weights.health * (max_team_health - min_team_health) as f64;
+
+    if weights.alive != 0.0 {
+        let max_alive = state.enemy_team.iter().filter(|d| d.health() > 0.0).count() as f64;
+        let min_alive = state.player_team.iter().filter(|d| d.health() > 0.0).count() as f64;
+        value += weights.alive * (max_alive - min_alive);
+    }
+
+    // Only matters while both leads can fight
+    if weights.matchup != 0.0 && state.enemy_team[0].health() > 0.0 && state.player_team[0].health() > 0.0 {
+        let max_lead = &monsters[state.enemy_team[0].name()];
+        let min_lead = &monsters[state.player_team[0].name()];
+        value += weights.matchup * (best_effectiveness(max_lead, min_lead) - best_effectiveness(min_lead, max_lead));
+    }
+
+    // The opp stages are the enemy's and the self stages the player's
+    let max_stages = state.opp_attack_stages + state.opp_defense_stages;
+    let min_stages = state.self_attack_stages + state.self_defense_stages;
+    value += weights.stages * (max_stages - min_stages) as f64;
+
+    value += if maximizing_player { weights.tempo } else { -weights.tempo };
+    value
 }
 
 /// Returns the number of monsters that can be switched into battle
 ///
-/// * `team` - The team *(as vector of (str monster, health))*
-fn num_switchable_mons(team: &Vec<(String, f32)>) -> usize {
-    let alive_mons = team.iter().filter(|d| d.1 > 0.0).count();
+/// * `team` - The team, either side's
+fn num_switchable_mons<T: TeamMember>(team: &[T]) -> usize {
+    let alive_mons = team.iter().filter(|d| d.health() > 0.0).count();
     return if alive_mons == 0 { 0 } else { alive_mons - 1 };
 }
 
+// Damage of a move as the search sees it, with both active monsters' abilities applied.
+// The search has no items map, so held items aren't modelled and it may misjudge their boosts.
+fn search_damage(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &mut monster::BattleState,
+    action: usize,
+    player_turn: bool,
+) -> f32 {
+    let damage = monster::calculate_damage(monsters, state, action, player_turn);
+    let attacker = if player_turn {
+        state.player_team[0].name()
+    } else {
+        state.enemy_team[0].name()
+    };
+    let attack = monsters[attacker].moves[action];
+    hooks::ability_damage(monsters, state, attack, player_turn, (0, 0), damage).0
+}
+
+/// Returns the team index of the monster a switch action sends out
+///
+/// Actions 4 and up switch to the first, second, ... monster behind the lead that can still fight.
+///
+/// * `team` - The team, either side's
+/// * `action` - A switch action (4 or more)
+pub fn switch_target<T: TeamMember>(team: &[T], action: usize) -> usize {
+    team.iter()
+        .enumerate()
+        .skip(1)
+        .filter(|d| d.1.health() > 0.0)
+        .nth(action - 4)
+        .map(|d| d.0)
+        .unwrap_or(0)
+}
+
+/// Returns the action the enemy takes this turn, encoded as in `alphabeta`
+///
+/// Wild monsters attack at random, while trainers search for the best action: only
+/// their own move on easy, α-β deepened for as long as the budget allows on normal,
+/// and MCTS on hard. A trainer's profile changes that: aggressive trainers take the
+/// hardest hitting move unless on hard, defensive trainers lean towards setting up and
+/// switching unless on hard, erratic trainers sometimes act at random, and gym leaders
+/// search as if on at least normal. Profiles also pick the evaluation weights, so
+/// defensive trainers still value stat stages and matchups on hard.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `settings` - The difficulty and profile, depth and budgets to search with, and the
+///   profile's evaluation weights
+/// * `log` - Records how far α-β got within the budget, or when replaying, stops it there again
+/// * `rng` - Picks wild moves and the fallback, and drives MCTS; the battle's own RNG so replays match
+pub fn choose_action(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    settings: &SearchSettings,
+    log: &mut SearchLog,
+    rng: &mut impl Rng,
+) -> usize {
+    let random_move = rng.gen_range(0..4);
+    if state.wild {
+        return random_move;
+    }
+    let difficulty = settings.difficulty;
+    let result = match (settings.profile, difficulty) {
+        (Profile::Erratic, _) if rng.gen_bool(erratic_chance(difficulty)) => {
+            Some(rng.gen_range(0..num_actions(state, true)))
+        }
+        (Profile::Aggressive, Difficulty::Easy) | (Profile::Aggressive, Difficulty::Normal) => {
+            Some(greedy_action(monsters, state, true))
+        }
+        (Profile::Defensive, Difficulty::Easy) => defensive_action(monsters, state, 1, &settings.weights),
+        (Profile::Defensive, Difficulty::Normal) => defensive_action(monsters, state, settings.depth, &settings.weights),
+        (Profile::GymLeader, Difficulty::Easy) | (_, Difficulty::Normal) => {
+            let budget = log.next_budget(settings.budget);
+            let (_, action, visited) = iterative_deepening(monsters, state, settings.depth, budget, true, &settings.weights);
+            log.record(visited);
+            action
+        }
+        (_, Difficulty::Easy) => {
+            let mut new_state = state.clone();
+            alphabeta(monsters, &mut new_state, 1, -f64::INFINITY, f64::INFINITY, true, &settings.weights).1
+        }
+        (_, Difficulty::Hard) => mcts(monsters, state, true, &settings.hard_search, &settings.weights, rng),
+    };
+    result.unwrap_or(random_move)
+}
+
+/// Returns the move and the foe targeted by one of the enemy's monsters in a double battle
+///
+/// The searches only know single battles, so the monster plays a one-on-one against each
+/// foe with `choose_action`, neither side able to switch. It then targets the foe its
+/// chosen move hurts most, preferring any it knocks out.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the double battle
+/// * `slot` - The enemy team index of the monster acting
+/// * `foes` - The player team indices of the monsters it may target
+/// * `settings` - As for `choose_action`
+/// * `log` - As for `choose_action`
+/// * `rng` - As for `choose_action`
+pub fn choose_double_action(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    slot: usize,
+    foes: &[usize],
+    settings: &SearchSettings,
+    log: &mut SearchLog,
+    rng: &mut impl Rng,
+) -> (usize, usize) {
+    let mut best: Option<((bool, f32), usize, usize)> = None;
+    for &foe in foes.iter() {
+        let mut single = state.clone();
+        single.double = false;
+        single.player_turn = false;
+        single.player_team = vec![state.player_team[foe].clone()];
+        single.enemy_team = vec![state.enemy_team[slot].clone()];
+
+        let action = choose_action(monsters, &single, settings, log, rng);
+        let health = single.player_team[0].health();
+        let dealt = search_damage(monsters, &mut single, action, false).min(health);
+        let score = (dealt >= health, dealt);
+        if best.is_none_or(|b| score > b.0) {
+            best = Some((score, action, foe));
+        }
+    }
+    best.map_or((0, 0), |b| (b.1, b.2))
+}
+
+/// Returns the index of the enemy team member to send out after its lead faints
+///
+/// Each monster that can still fight is tried in front, and the one the α-β search
+/// rates best for the AI is picked. Returns None if the enemy has no one left.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle, with the fainted lead still in front
+/// * `depth` - How many actions ahead to search
+/// * `weights` - How the search evaluates states
+pub fn choose_replacement(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    depth: i32,
+    weights: &Weights,
+) -> Option<usize> {
+    let mut best: Option<(f64, usize)> = None;
+    for (index, mon) in state.enemy_team.iter().enumerate().skip(1) {
+        if mon.1 <= 0.0 {
+            continue;
+        }
+        let mut new_state = state.clone();
+        new_state.enemy_team.swap(0, index);
+
+        // The player gets to act first against the new lead
+        let value = alphabeta(monsters, &mut new_state, depth, -f64::INFINITY, f64::INFINITY, false, weights).0;
+        if best.is_none_or(|b| value > b.0) {
+            best = Some((value, index));
+        }
+    }
+    best.map(|b| b.1)
+}
+
+/// Returns the enemy bench member to send into a fainted slot of a double battle
+///
+/// Like `choose_replacement`, but the search only knows single battles, so each candidate
+/// is searched one-on-one against every foe still fighting and rated by its average value.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the double battle
+/// * `candidates` - Enemy team indices of the bench members that can still fight
+/// * `foes` - Player team indices of the active monsters still fighting
+/// * `depth` - How many actions ahead to search
+/// * `weights` - How the search evaluates states
+pub fn choose_double_replacement(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    candidates: &[usize],
+    foes: &[usize],
+    depth: i32,
+    weights: &Weights,
+) -> Option<usize> {
+    let mut best: Option<(f64, usize)> = None;
+    for &index in candidates.iter() {
+        let mut value = 0.0;
+        for &foe in foes.iter() {
+            let mut single = state.clone();
+            single.double = false;
+            single.player_team = vec![state.player_team[foe].clone()];
+            single.enemy_team = vec![state.enemy_team[index].clone()];
+            value += alphabeta(monsters, &mut single, depth, -f64::INFINITY, f64::INFINITY, false, weights).0;
+        }
+        value /= foes.len().max(1) as f64;
+        if best.is_none_or(|b| value > b.0) {
+            best = Some((value, index));
+        }
+    }
+    best.map(|b| b.1)
+}
+
 /// Runs the α-β algorithm and returns the payoff and action for the optimal path of play
 ///
 /// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
@@ -38,142 +517,1607 @@ fn num_switchable_mons(team: &Vec<(String, f32)>) -> usize {
 /// * `alpha` - Best available payoff for the max agent (AI) so far
 /// * `beta` - Best available payoff for the min agent (player) so far
 /// * `maximizing_player` - Determines which player we are optimizing for (max = AI; min = player)
+/// * `weights` - How states at the depth limit are evaluated
 pub fn alphabeta(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &mut monster::BattleState,
+    depth: i32,
+    alpha: f64,
+    beta: f64,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> (f64, Option<usize>) {
+    let mut search = Search::new(None, *weights);
+    alphabeta_search(monsters, state, depth, alpha, beta, maximizing_player, &mut search)
+}
+
+/// Runs α-β at increasing depths until `max_depth` is done or the budget runs out
+///
+/// Returns the payoff and action of the deepest search that finished, and how many positions
+/// were visited before stopping; that many as a `Budget::Iterations` stops the same search
+/// in the same place. Depth 1 always finishes, so there is always an action. The
+/// transposition table is kept between depths, so each search tries the best action of the
+/// one before it first.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `max_depth` - The deepest search to try
+/// * `budget` - How many positions the searches past depth 1 may visit, or how long they may take
+/// * `maximizing_player` - Determines which player we are optimizing for (max = AI; min = player)
+/// * `weights` - How states at the depth limit are evaluated
+pub fn iterative_deepening(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    max_depth: i32,
+    budget: Budget,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> (f64, Option<usize>, u32) {
+    let mut search = Search::new(None, *weights);
+    let mut new_state = state.clone();
+    let mut best = alphabeta_search(
+        monsters,
+        &mut new_state,
+        1,
+        -f64::INFINITY,
+        f64::INFINITY,
+        maximizing_player,
+        &mut search,
+    );
+
+    search.budget = Some(budget);
+    search.start = Instant::now();
+    for depth in 2..=max_depth {
+        let mut new_state = state.clone();
+        let result = alphabeta_search(
+            monsters,
+            &mut new_state,
+            depth,
+            -f64::INFINITY,
+            f64::INFINITY,
+            maximizing_player,
+            &mut search,
+        );
+        // A search cut short by the budget hasn't looked at every action, so it is thrown away
+        if search.timed_out {
+            break;
+        }
+        best = result;
+    }
+    // The position that ran out of budget wasn't visited
+    let visited = if search.timed_out { search.visited - 1 } else { search.visited };
+    (best.0, best.1, visited)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    // The payoff is at least the stored value (a β cutoff)
+    Lower,
+    // The payoff is at most the stored value (an α cutoff)
+    Upper,
+}
+
+// What an α-β search found for a state
+#[derive(Clone, Copy)]
+struct TableEntry {
+    depth: i32,
+    value: f64,
+    bound: Bound,
+    action: Option<usize>,
+}
+
+// The transposition table, budget and evaluation weights shared by every node of a search
+struct Search {
+    table: HashMap<u64, TableEntry>,
+    budget: Option<Budget>,
+    // When the budget started, and the positions visited since
+    start: Instant,
+    visited: u32,
+    // Set once the budget runs out; every node returns straight away after that
+    timed_out: bool,
+    weights: Weights,
+}
+
+impl Search {
+    fn new(budget: Option<Budget>, weights: Weights) -> Search {
+        Search {
+            table: HashMap::new(),
+            budget,
+            start: Instant::now(),
+            visited: 0,
+            timed_out: false,
+            weights,
+        }
+    }
+
+    // Counts a position visited, returning true once the budget has run out
+    fn spend(&mut self) -> bool {
+        self.visited += 1;
+        match self.budget {
+            None => false,
+            Some(Budget::Iterations(limit)) => self.visited > limit,
+            Some(Budget::Millis(limit)) => self.start.elapsed() >= Duration::from_millis(limit),
+        }
+    }
+}
+
+// A hash of everything α-β can change about a state, and who is to move
+fn state_hash(state: &monster::BattleState, maximizing_player: bool) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    maximizing_player.hash(&mut hasher);
+    for mon in state.player_team.iter() {
+        mon.name().hash(&mut hasher);
+        mon.health().to_bits().hash(&mut hasher);
+    }
+    for mon in state.enemy_team.iter() {
+        mon.name().hash(&mut hasher);
+        mon.health().to_bits().hash(&mut hasher);
+    }
+    state.weather.hash(&mut hasher);
+    state.self_attack_stages.hash(&mut hasher);
+    state.self_defense_stages.hash(&mut hasher);
+    state.opp_attack_stages.hash(&mut hasher);
+    state.opp_defense_stages.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn alphabeta_search(
     monsters: &HashMap<String, monster::Monster>,
     state: &mut monster::BattleState,
     depth: i32,
     mut alpha: f64,
     mut beta: f64,
     maximizing_player: bool,
+    search: &mut Search,
 ) -> (f64, Option<usize>) {
-    // Value will store the payoff of any actions an agent takes
-    let mut value: f64;
-
     // We will return the payoff, but more importantly the action taken to get that payoff
     let mut ret: (f64, Option<usize>) = (0.0, None);
 
-    // Terminal test: if one team has no alive monsters
-    let battle_end =
-        total_team_health(&state.player_team) == 0.0 && total_team_health(&state.enemy_team) == 0.0;
+    if search.timed_out || search.spend() {
+        search.timed_out = true;
+        return ret;
+    }
 
     // If depth limit is reached or battle has ended, return the evaluation function of the game state
-    if depth == 0 || battle_end {
+    if depth == 0 || battle_over(state) {
         return (
-            evaluation_function(&state.player_team, &state.enemy_team),
+            evaluation_function(monsters, state, maximizing_player, &search.weights),
             None,
         );
     }
 
+    // A state already searched to the same depth, reached by another order of actions, needn't
+    // be searched again. Results from other depths are only used to order the actions.
+    let key = state_hash(state, maximizing_player);
+    let (original_alpha, original_beta) = (alpha, beta);
+    let mut first_action = None;
+    if let Some(entry) = search.table.get(&key) {
+        first_action = entry.action;
+        if entry.depth == depth {
+            match entry.bound {
+                Bound::Exact => return (entry.value, entry.action),
+                Bound::Lower => alpha = alpha.max(entry.value),
+                Bound::Upper => beta = beta.min(entry.value),
+            }
+            if alpha >= beta {
+                return (entry.value, entry.action);
+            }
+        }
+    }
+
+    let mut actions: Vec<usize> = (0..num_actions(state, maximizing_player)).collect();
+    // Try the best action found last time first, since it most likely prunes the rest
+    if let Some(index) = first_action.and_then(|a| actions.iter().position(|d| *d == a)) {
+        let action = actions.remove(index);
+        actions.insert(0, action);
+    }
+
     // Execute a search of the game tree for the given player
     //   In our case, maximizing player is the AI (against the player)
-    if maximizing_player {
-        // Initialize the payoff as the WORST possible case for the maximizing player
-        value = -f64::INFINITY;
-
-        // Go thru all actions for the AI/opponent player
-        //   0..=3 being one of the current lead's 4 moves
-        //   4..=(up to 8) being one of the possible (up to 5) other monsters to switch into
-        for action in 0..=(3 + num_switchable_mons(&state.enemy_team)) {
-            let temp = value;
-
-            // Create a new state to update based upon the action taken
-            let mut new_state = monster::BattleState {
-                player_turn: !state.player_turn,
-                player_team: state.player_team.clone(),
-                enemy_team: state.enemy_team.clone(),
-                self_attack_stages: state.self_attack_stages,
-                self_defense_stages: state.self_defense_stages,
-                opp_attack_stages: state.opp_attack_stages,
-                opp_defense_stages: state.opp_defense_stages,
-            };
+    for action in actions {
+        // Following our move, find out which one leads to the best payoff by traversing the game tree
+        let mut new_state = search_step(monsters, state, action, maximizing_player);
+        let value = alphabeta_search(monsters, &mut new_state, depth - 1, alpha, beta, !maximizing_player, search).0;
+
+        // Keep the first action, and any that does strictly better for the side to move
+        let better = if maximizing_player { value > ret.0 } else { value < ret.0 };
+        if ret.1.is_none() || better {
+            ret = (value, Some(action));
+        }
+
+        // Prune remaining actions if possible, otherwise narrow the window for the side to move
+        if maximizing_player {
+            if ret.0 >= beta {
+                break; // (* β cutoff *)
+            }
+            alpha = alpha.max(ret.0);
+        } else {
+            if ret.0 <= alpha {
+                break; // (* α cutoff *)
+            }
+            beta = beta.min(ret.0);
+        }
+    }
+
+    // Results of a search cut short are incomplete, so they aren't kept
+    if !search.timed_out {
+        let bound = if ret.0 <= original_alpha {
+            Bound::Upper
+        } else if ret.0 >= original_beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        search.table.insert(
+            key,
+            TableEntry {
+                depth,
+                value: ret.0,
+                bound,
+                action: ret.1,
+            },
+        );
+    }
+    ret
+}
+
+/// Returns the state after one action in the deterministic tree `alphabeta` and `minimax` search
+///
+/// Actions 0 to 3 use one of the lead's moves, dealing its usual damage once; actions 4 and
+/// up switch as in `switch_target`.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The state of the battle before the action
+/// * `action` - The action, encoded as in `alphabeta`
+/// * `maximizing_player` - Whether the AI takes the action, rather than the player
+fn search_step(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    action: usize,
+    maximizing_player: bool,
+) -> monster::BattleState {
+    let mut new_state = state.clone();
+    new_state.player_turn = !state.player_turn;
+
+    if action >= 4 {
+        if maximizing_player {
+            let index = switch_target(&new_state.enemy_team, action);
+            new_state.enemy_team.swap(0, index);
+        } else {
+            let index = switch_target(&new_state.player_team, action);
+            new_state.player_team.swap(0, index);
+        }
+    } else if maximizing_player {
+        // Calculate the new health of the opponent (player)
+        let new_health = new_state.player_team[0].health()
+            - search_damage(monsters, &mut new_state, action, false);
+        new_state.player_team[0].set_health(new_health.clamp(0.0, 100.0));
+
+        // Makes sure an active monster is still in front after attack
+        new_state.player_team = monster::verify_team(&new_state.player_team);
+    } else {
+        // Calculate the new health of the opponent (AI)
+        let new_health = new_state.enemy_team[0].health()
+            - search_damage(monsters, &mut new_state, action, true);
+        new_state.enemy_team[0].set_health(new_health.clamp(0.0, 100.0));
+
+        // Makes sure an active monster is still in front after attack
+        new_state.enemy_team = monster::verify_team(&new_state.enemy_team);
+    }
+    new_state
+}
+
+/// Runs plain minimax and returns the payoff and action for the optimal path of play
+///
+/// Searches exactly the tree `alphabeta` does, visiting every node with no pruning and no
+/// table, so it is only fast enough for small states. It is the reference α-β is checked
+/// against.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `depth` - How many actions ahead to search
+/// * `maximizing_player` - Determines which player we are optimizing for (max = AI; min = player)
+/// * `weights` - How states at the depth limit are evaluated
+pub fn minimax(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    depth: i32,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> (f64, Option<usize>) {
+    if depth == 0 || battle_over(state) {
+        return (evaluation_function(monsters, state, maximizing_player, weights), None);
+    }
 
-            // Change the new state based upon the action (attack or switch in another monster)
-            if action < 4 {
-                // Action corresponding to a move
+    let mut best: Option<(f64, usize)> = None;
+    for action in 0..num_actions(state, maximizing_player) {
+        let new_state = search_step(monsters, state, action, maximizing_player);
+        let value = minimax(monsters, &new_state, depth - 1, !maximizing_player, weights).0;
+        let better = match best {
+            Some((best_value, _)) if maximizing_player => value > best_value,
+            Some((best_value, _)) => value < best_value,
+            None => true,
+        };
+        if better {
+            best = Some((value, action));
+        }
+    }
+    match best {
+        Some((value, action)) => (value, Some(action)),
+        None => (evaluation_function(monsters, state, maximizing_player, weights), None),
+    }
+}
+
+// Applies a landed move to the health of the monster using it and the one it hit
+fn land_move<A: TeamMember, D: TeamMember>(user: &mut A, opponent: &mut D, result: &monster::MoveResult) {
+    opponent.set_health((opponent.health() - result.damage).clamp(0.0, 100.0));
+    user.set_health((user.health() + result.user_health_change).clamp(0.0, 100.0));
+}
+
+/// Returns every state an action can lead to, along with how likely it is
+///
+/// Moves that hit a random number of times lead to one state per number of hits;
+/// every other action leads to a single state.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The state of the battle before the action
+/// * `action` - The action, encoded as in `alphabeta`
+/// * `maximizing_player` - Whether the AI takes the action, rather than the player
+fn action_outcomes(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    action: usize,
+    maximizing_player: bool,
+) -> Vec<(f64, monster::BattleState)> {
+    let mut new_state = state.clone();
+    new_state.player_turn = !state.player_turn;
+
+    if action >= 4 {
+        if maximizing_player {
+            let index = switch_target(&new_state.enemy_team, action);
+            new_state.enemy_team.swap(0, index);
+        } else {
+            let index = switch_target(&new_state.player_team, action);
+            new_state.player_team.swap(0, index);
+        }
+        return vec![(1.0, new_state)];
+    }
 
-                // Calculate the new health of the opponent (player)
-                let mut new_health = new_state.player_team[0].1
-                    - monster::calculate_damage(monsters, &mut new_state, action, false);
-                new_health = new_health.clamp(0.0, 100.0);
-                new_state.player_team[0].1 = new_health;
+    let user = if maximizing_player {
+        &monsters[state.enemy_team[0].name()]
+    } else {
+        &monsters[state.player_team[0].name()]
+    };
+    let attack = user.moves[action];
+    let damage = search_damage(monsters, &mut new_state, action, !maximizing_player);
 
-                // Makes sure an active monster is still in front after attack
-                new_state.player_team = battle::verify_team(&new_state.player_team);
+    monster::hit_chances(attack)
+        .into_iter()
+        .map(|(hits, chance)| {
+            let mut outcome = new_state.clone();
+            if maximizing_player {
+                let result = monster::resolve_hits(
+                    attack,
+                    damage,
+                    hits,
+                    outcome.enemy_team[0].health(),
+                    outcome.player_team[0].health(),
+                );
+                land_move(&mut outcome.enemy_team[0], &mut outcome.player_team[0], &result);
             } else {
-                // Action corresponding to a switch
-                new_state.enemy_team.swap(0, action - 3);
-                new_state.enemy_team = battle::verify_team(&new_state.enemy_team);
+                let result = monster::resolve_hits(
+                    attack,
+                    damage,
+                    hits,
+                    outcome.player_team[0].health(),
+                    outcome.enemy_team[0].health(),
+                );
+                land_move(&mut outcome.player_team[0], &mut outcome.enemy_team[0], &result);
             }
 
-            // Following our move, find out which one leads to the best payoff by traversing the game tree
-            value = value.max(alphabeta(monsters, &mut new_state, depth - 1, alpha, beta, false).0);
+            // Makes sure an active monster is still in front after the attack
+            outcome.player_team = monster::verify_team(&outcome.player_team);
+            outcome.enemy_team = monster::verify_team(&outcome.enemy_team);
+            (chance, outcome)
+        })
+        .collect()
+}
 
-            // Update the return value if value is updated
-            if value != temp {
-                ret = (value, Some(action))
-            }
+/// Runs expectiminimax and returns the expected payoff and action for the best path of play
+///
+/// Searches the same tree as `alphabeta`, with the same action encoding, but each move is
+/// a chance node: its payoff is the payoff of every way it can turn out, weighed by how
+/// likely that is. Moves that hit a random number of times are valued by all their hits
+/// rather than a single one. Nothing is pruned, so it is slower at the same depth.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `depth` - How many actions ahead to search
+/// * `maximizing_player` - Determines which player we are optimizing for (max = AI; min = player)
+/// * `weights` - How states at the depth limit are evaluated
+pub fn expectiminimax(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    depth: i32,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> (f64, Option<usize>) {
+    if depth == 0 || battle_over(state) {
+        return (evaluation_function(monsters, state, maximizing_player, weights), None);
+    }
 
-            // Prune remaining actions if possible
-            if value >= beta {
-                break; // (* β cutoff *)
+    let mut best: Option<(f64, usize)> = None;
+    for action in 0..num_actions(state, maximizing_player) {
+        // The chance node: average the payoff over everything the action can lead to
+        let value: f64 = action_outcomes(monsters, state, action, maximizing_player)
+            .iter()
+            .map(|(chance, outcome)| {
+                chance * expectiminimax(monsters, outcome, depth - 1, !maximizing_player, weights).0
+            })
+            .sum();
+
+        let better = match best {
+            Some((best_value, _)) if maximizing_player => value > best_value,
+            Some((best_value, _)) => value < best_value,
+            None => true,
+        };
+        if better {
+            best = Some((value, action));
+        }
+    }
+    match best {
+        Some((value, action)) => (value, Some(action)),
+        None => (evaluation_function(monsters, state, maximizing_player, weights), None),
+    }
+}
+
+// Whether either team has no alive monsters
+fn battle_over(state: &monster::BattleState) -> bool {
+    total_team_health(&state.player_team) == 0.0 || total_team_health(&state.enemy_team) == 0.0
+}
+
+// How many actions the side to move has: its lead's 4 moves, then a switch to each other monster that can fight
+pub fn num_actions(state: &monster::BattleState, maximizing_player: bool) -> usize {
+    if maximizing_player {
+        4 + num_switchable_mons(&state.enemy_team)
+    } else {
+        4 + num_switchable_mons(&state.player_team)
+    }
+}
+
+// Picks one of the outcomes of an action by its probability
+fn sample(outcomes: Vec<(f64, monster::BattleState)>, rng: &mut impl Rng) -> monster::BattleState {
+    let mut roll: f64 = rng.gen();
+    let last = outcomes.len() - 1;
+    for (index, (chance, outcome)) in outcomes.into_iter().enumerate() {
+        if roll < chance || index == last {
+            return outcome;
+        }
+        roll -= chance;
+    }
+    unreachable!()
+}
+
+/// Returns the lead's move expected to deal the most damage right now
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `maximizing_player` - Whether to pick for the AI, rather than the player
+pub fn greedy_action(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+) -> usize {
+    let user = if maximizing_player {
+        &monsters[state.enemy_team[0].name()]
+    } else {
+        &monsters[state.player_team[0].name()]
+    };
+    let mut best = (0, -f64::INFINITY);
+    for action in 0..4 {
+        let mut new_state = state.clone();
+        let damage = search_damage(monsters, &mut new_state, action, !maximizing_player) as f64;
+        let hits: f64 = monster::hit_chances(user.moves[action])
+            .iter()
+            .map(|d| d.0 as f64 * d.1)
+            .sum();
+        if damage * hits > best.1 {
+            best = (action, damage * hits);
+        }
+    }
+    best.0
+}
+
+// Whether an action switches out or is a move that raises the user's stages or lowers the foe's
+fn is_setup_or_switch(monsters: &HashMap<String, monster::Monster>, state: &monster::BattleState, action: usize) -> bool {
+    if action >= 4 {
+        return true;
+    }
+    let attack = monsters[state.enemy_team[0].name()].moves[action];
+    attack.self_attack_stages > 0
+        || attack.self_defense_stages > 0
+        || attack.opp_attack_stages < 0
+        || attack.opp_defense_stages < 0
+}
+
+/// Returns the AI's action with setting up and switching favoured over what the search says
+///
+/// Every action is searched with α-β, and those that set up or switch have `DEFENSIVE_BONUS`
+/// added to their payoff before the best is picked. Returns None if the AI has no actions.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle, with the AI to move
+/// * `depth` - How many actions ahead to search, counting the AI's own
+/// * `weights` - How states at the depth limit are evaluated
+fn defensive_action(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    depth: i32,
+    weights: &Weights,
+) -> Option<usize> {
+    let mut best: Option<(f64, usize)> = None;
+    for action in 0..num_actions(state, true) {
+        let mut new_state = search_step(monsters, state, action, true);
+        let mut value = alphabeta(monsters, &mut new_state, depth - 1, -f64::INFINITY, f64::INFINITY, false, weights).0;
+        if is_setup_or_switch(monsters, state, action) {
+            value += DEFENSIVE_BONUS;
+        }
+        if best.is_none_or(|b| value > b.0) {
+            best = Some((value, action));
+        }
+    }
+    best.map(|b| b.1)
+}
+
+// Payoff of a position for the AI between 0 (lost) and 1 (won), from the evaluation function if it isn't over
+fn mcts_payoff(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+    weights: &Weights,
+) -> f64 {
+    if total_team_health(&state.player_team) == 0.0 {
+        return 1.0;
+    }
+    if total_team_health(&state.enemy_team) == 0.0 {
+        return 0.0;
+    }
+    let most = 100.0 * state.player_team.len().max(state.enemy_team.len()) as f64;
+    let value = evaluation_function(monsters, state, maximizing_player, weights);
+    (0.5 + value / (2.0 * most)).clamp(0.0, 1.0)
+}
+
+fn mcts_rollout(
+    monsters: &HashMap<String, monster::Monster>,
+    mut state: monster::BattleState,
+    mut maximizing_player: bool,
+    config: &MctsConfig,
+    weights: &Weights,
+    rng: &mut impl Rng,
+) -> f64 {
+    for _ in 0..config.rollout_depth {
+        if battle_over(&state) {
+            break;
+        }
+        let action = match config.rollout {
+            Rollout::Random => rng.gen_range(0..num_actions(&state, maximizing_player)),
+            Rollout::Greedy => greedy_action(monsters, &state, maximizing_player),
+        };
+        state = sample(action_outcomes(monsters, &state, action, maximizing_player), rng);
+        maximizing_player = !maximizing_player;
+    }
+    mcts_payoff(monsters, &state, maximizing_player, weights)
+}
+
+// A node of the MCTS tree. States aren't kept, since the same action can turn out
+// differently each time; every iteration replays the actions from the root instead.
+struct MctsNode {
+    // Whether the AI picks the action at this node, rather than the player
+    maximizing_player: bool,
+    // The action that leads to each child tried so far, and the child's index
+    children: Vec<(usize, usize)>,
+    visits: u32,
+    // Sum of the payoffs for the AI of every rollout through this node
+    payoff: f64,
+}
+
+impl MctsNode {
+    fn new(maximizing_player: bool) -> MctsNode {
+        MctsNode {
+            maximizing_player,
+            children: Vec::new(),
+            visits: 0,
+            payoff: 0.0,
+        }
+    }
+}
+
+/// Runs Monte Carlo Tree Search and returns the action it tried most
+///
+/// Actions in the tree are picked by UCT, and positions past the tree are played out
+/// with `config.rollout`. Uses the same action encoding as `alphabeta`. Returns None
+/// if the battle is already over.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The current state of the battle
+/// * `maximizing_player` - Whether to search for the AI, rather than the player
+/// * `config` - The search budget, rollout policy and exploration weight
+/// * `weights` - How positions are evaluated when a rollout stops before the battle is over
+/// * `rng` - Picks rollout actions and how moves turn out
+pub fn mcts(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+    config: &MctsConfig,
+    weights: &Weights,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    if battle_over(state) {
+        return None;
+    }
+
+    let start = Instant::now();
+    let nodes = mcts_tree(monsters, state, maximizing_player, config, weights, rng, &mut || start.elapsed());
+    nodes[0]
+        .children
+        .iter()
+        .max_by_key(|d| nodes[d.1].visits)
+        .map(|d| d.0)
+}
+
+// Grows the search tree until the budget runs out; `elapsed` is the time since the search started
+fn mcts_tree(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+    config: &MctsConfig,
+    weights: &Weights,
+    rng: &mut impl Rng,
+    elapsed: &mut dyn FnMut() -> Duration,
+) -> Vec<MctsNode> {
+    let mut nodes = vec![MctsNode::new(maximizing_player)];
+    let mut iterations = 0;
+    while match config.budget {
+        Budget::Iterations(limit) => iterations < limit,
+        Budget::Millis(limit) => elapsed() < Duration::from_millis(limit),
+    } {
+        iterations += 1;
+        let mut current = state.clone();
+        let mut node = 0;
+        let mut path = vec![0];
+
+        // Selection and expansion: walk down by UCT until an action hasn't been tried yet
+        while !battle_over(&current) {
+            let maximizing_player = nodes[node].maximizing_player;
+            let actions = num_actions(&current, maximizing_player);
+            let untried: Vec<usize> = (0..actions)
+                .filter(|a| !nodes[node].children.iter().any(|c| c.0 == *a))
+                .collect();
+
+            let (action, child) = if !untried.is_empty() {
+                let action = untried[rng.gen_range(0..untried.len())];
+                nodes.push(MctsNode::new(!maximizing_player));
+                let child = nodes.len() - 1;
+                nodes[node].children.push((action, child));
+                (action, child)
+            } else {
+                let parent_visits = (nodes[node].visits.max(1) as f64).ln();
+                let mut best = (0, 0, -f64::INFINITY);
+                // Switches a previous visit could make may not be possible this time
+                for &(action, child) in nodes[node].children.iter().filter(|c| c.0 < actions) {
+                    let mean = nodes[child].payoff / nodes[child].visits as f64;
+                    let exploit = if maximizing_player { mean } else { 1.0 - mean };
+                    let score =
+                        exploit + config.exploration * (parent_visits / nodes[child].visits as f64).sqrt();
+                    if score > best.2 {
+                        best = (action, child, score);
+                    }
+                }
+                (best.0, best.1)
+            };
+
+            current = sample(action_outcomes(monsters, &current, action, maximizing_player), rng);
+            path.push(child);
+            let expanded = nodes[child].visits == 0;
+            node = child;
+            if expanded {
+                break;
             }
+        }
 
-            // Update alpha (the best option so far for maximizing player)
-            alpha = alpha.max(value);
+        // Simulation, then backpropagation of the payoff up the path
+        let payoff = mcts_rollout(monsters, current, nodes[node].maximizing_player, config, weights, rng);
+        for index in path {
+            nodes[index].visits += 1;
+            nodes[index].payoff += payoff;
         }
-        return ret;
+    }
+    nodes
+}
+
+/// Plays a battle out between two policies without drawing anything, like the game's turns
+///
+/// Fainted leads are replaced by the next monster that can fight. Returns whether the
+/// AI won, or None if neither side won within `max_turns`, along with the turns taken.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for damage calculation
+/// * `state` - The state the battle starts in; `player_turn` decides who goes first
+/// * `player` - Picks the player's actions
+/// * `enemy` - Picks the AI's actions
+/// * `rng` - Decides how moves turn out, and is passed on to the policies
+/// * `max_turns` - Most actions, by either side, before the battle is called off
+pub fn play_match<R: Rng>(
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    player: &mut dyn FnMut(&monster::BattleState, &mut R) -> usize,
+    enemy: &mut dyn FnMut(&monster::BattleState, &mut R) -> usize,
+    rng: &mut R,
+    max_turns: u32,
+) -> (Option<bool>, u32) {
+    let mut state = state.clone();
+    for turn in 0..max_turns {
+        if battle_over(&state) {
+            return (Some(total_team_health(&state.player_team) == 0.0), turn);
+        }
+        let maximizing_player = !state.player_turn;
+        let action = if maximizing_player {
+            enemy(&state, rng)
+        } else {
+            player(&state, rng)
+        };
+        // A policy can't pick an action that isn't there
+        let action = action.min(num_actions(&state, maximizing_player) - 1);
+        state = sample(action_outcomes(monsters, &state, action, maximizing_player), rng);
+    }
+    if battle_over(&state) {
+        (Some(total_team_health(&state.player_team) == 0.0), max_turns)
     } else {
-        // Initialize the payoff as the WORST possible case for the minimizing player
-        value = f64::INFINITY;
-
-        // Go thru all actions for the player
-        //   0..=3 being one of the current lead's 4 moves
-        //   4..=(up to 8) being one of the possible (up to 5) other monsters to switch into
-        for action in 0..=(3 + num_switchable_mons(&state.player_team)) {
-            let temp = value;
-
-            // Create a new state to update based upon the action taken
-            let mut new_state = monster::BattleState {
-                player_turn: !state.player_turn,
-                player_team: state.player_team.clone(),
-                enemy_team: state.enemy_team.clone(),
-                self_attack_stages: state.self_attack_stages,
-                self_defense_stages: state.self_defense_stages,
-                opp_attack_stages: state.opp_attack_stages,
-                opp_defense_stages: state.opp_defense_stages,
+        (None, max_turns)
+    }
+}
+
+
+/// Starts a battle between two random teams, for pitting searches against each other
+///
+/// * `names` - The species teams are picked from; sorted so a seed always picks the same teams
+/// * `team_size` - How many monsters each side has
+/// * `level` - The level every monster on both sides fights at
+/// * `rng` - Picks the teams and rolls the player's monsters
+pub fn random_battle(names: &[String], team_size: usize, level: u32, rng: &mut impl Rng) -> monster::BattleState {
+    let mut pick = || names[rng.gen_range(0..names.len())].clone();
+    let enemy_team: Vec<String> = (0..team_size).map(|_| pick()).collect();
+    let player_team: Vec<String> = (0..team_size).map(|_| pick()).collect();
+    team_battle(&player_team, level, &enemy_team, level, rng)
+}
+
+/// Starts a battle between two given teams at full health, with the player to move
+///
+/// * `player_team` - The player's species, lead first
+/// * `player_level` - The level the player's monsters are at
+/// * `enemy_team` - The enemy's species, lead first
+/// * `enemy_level` - The level the enemy's monsters fight at
+/// * `rng` - Rolls the player's monsters
+pub fn team_battle(
+    player_team: &[String],
+    player_level: u32,
+    enemy_team: &[String],
+    enemy_level: u32,
+    rng: &mut impl Rng,
+) -> monster::BattleState {
+    monster::BattleState {
+        player_turn: true,
+        player_team: player_team.iter().map(|d| monster::roll_instance(d, player_level, rng)).collect(),
+        enemy_team: enemy_team.iter().map(|d| (d.clone(), 100.0)).collect(),
+        enemy_level,
+        wild: false,
+        weather: None,
+        double: false,
+        self_attack_stages: 0,
+        self_defense_stages: 0,
+        opp_attack_stages: 0,
+        opp_defense_stages: 0,
+    }
+}
+
+/// Plays α-β with one set of weights against α-β with another, for tuning the weights
+///
+/// Every seeded random battle is played twice with the sides swapped, since the player's
+/// and the enemy's monsters aren't quite even. Returns how many games each set won; games
+/// nobody won within the turn limit count for neither.
+///
+/// * `monsters` - Maps strings onto their Monster objects; teams are picked from these
+/// * `a` - The first set of weights
+/// * `b` - The second set of weights
+/// * `depth` - How many actions ahead both sides search
+/// * `battles` - How many random battles to play, each twice
+/// * `seed` - Seeds the first battle; the rest count up from it
+pub fn compare_weights(
+    monsters: &HashMap<String, monster::Monster>,
+    a: &Weights,
+    b: &Weights,
+    depth: i32,
+    battles: u32,
+    seed: u64,
+) -> (u32, u32) {
+    let mut names: Vec<String> = monsters.keys().cloned().collect();
+    names.sort();
+
+    let mut wins = (0, 0);
+    for battle in 0..battles as u64 {
+        let state = random_battle(&names, TUNING_TEAM_SIZE, monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(seed + battle));
+        for (enemy, player, a_is_enemy) in [(a, b, true), (b, a, false)].iter() {
+            let mut rng = StdRng::seed_from_u64(seed + battle);
+            let mut player_policy = |state: &monster::BattleState, _: &mut StdRng| {
+                alphabeta(monsters, &mut state.clone(), depth, -f64::INFINITY, f64::INFINITY, false, player)
+                    .1
+                    .unwrap_or(0)
+            };
+            let mut enemy_policy = |state: &monster::BattleState, _: &mut StdRng| {
+                alphabeta(monsters, &mut state.clone(), depth, -f64::INFINITY, f64::INFINITY, true, enemy)
+                    .1
+                    .unwrap_or(0)
             };
+            let (result, _) =
+                play_match(monsters, &state, &mut player_policy, &mut enemy_policy, &mut rng, TUNING_MAX_TURNS);
+            match result {
+                Some(enemy_won) if enemy_won == *a_is_enemy => wins.0 += 1,
+                Some(_) => wins.1 += 1,
+                None => {}
+            }
+        }
+    }
+    wins
+}
 
-            // Change the new state based upon the action (attack or switch in another monster)
-            if action < 4 {
-                // Action corresponding to a move
-                // Calculate the new health of the opponent (AI)
-                let mut new_health = new_state.enemy_team[0].1
-                    - monster::calculate_damage(monsters, &mut new_state, action, true);
-                new_health = new_health.clamp(0.0, 100.0);
-                new_state.enemy_team[0].1 = new_health;
-
-                // Makes sure an active monster is still in front after attack
-                new_state.enemy_team = battle::verify_team(&new_state.enemy_team);
-            } else {
-                // Action corresponding to a switch
-                new_state.player_team.swap(0, action - 3);
-                new_state.player_team = battle::verify_team(&new_state.player_team);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ActiveMons;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_move(name: &str, damage: u32, move_effect: monster::MoveEffect) -> monster::Move {
+        monster::Move {
+            name: String::from(name),
+            damage,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+            attack_type: String::from("Normal"),
+            effect: String::new(),
+            move_effect,
+            spread: false,
+        }
+    }
+
+    fn test_monster<'a>(moves: Vec<&'a monster::Move>) -> monster::Monster<'a> {
+        monster::Monster {
+            attack_stat: 100,
+            defense_stat: 100,
+            moves,
+            monster_type: String::from("Normal"),
+            ability: None,
+        }
+    }
+
+    // A one on one battle with the player to move, both monsters at full health
+    fn test_state() -> monster::BattleState {
+        monster::BattleState {
+            player_turn: true,
+            player_team: vec![ActiveMons {
+                name: String::from("Hero"),
+                hp: 100.0,
+                held_item: None,
+                level: monster::STARTING_LEVEL,
+                exp: 0,
+                attack_iv: 0,
+                defense_iv: 0,
+                nature: monster::Nature::Hardy,
+            }],
+            enemy_team: vec![(String::from("Foe"), 100.0)],
+            enemy_level: monster::STARTING_LEVEL,
+            wild: false,
+            weather: None,
+            double: false,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        }
+    }
+
+    // Plays a battle out with the player always using their first move, returning whether the AI won
+    fn play(
+        monsters: &HashMap<String, monster::Monster>,
+        search: &dyn Fn(&monster::BattleState) -> usize,
+        rng: &mut StdRng,
+    ) -> bool {
+        let mut state = test_state();
+        loop {
+            state = sample(action_outcomes(monsters, &state, 0, false), rng);
+            if total_team_health(&state.enemy_team) == 0.0 {
+                return false;
+            }
+            let action = search(&state);
+            state = sample(action_outcomes(monsters, &state, action, true), rng);
+            if total_team_health(&state.player_team) == 0.0 {
+                return true;
             }
-            // Following our move, find out which one leads to the best payoff by traversing the game tree
-            value = value.min(alphabeta(monsters, &mut new_state, depth - 1, alpha, beta, true).0);
-            // Update the return value if value is updated
-            if value != temp {
-                ret = (value, Some(action))
+        }
+    }
+
+    #[test]
+    fn matches_alphabeta_without_chance() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&jab, &tackle, &jab, &jab]));
+
+        let mut state = test_state();
+        state.player_turn = false;
+        for depth in 1..=3 {
+            let expected = expectiminimax(&monsters, &state, depth, true, &HEALTH_ONLY);
+            let mut ab_state = state.clone();
+            let pruned = alphabeta(&monsters, &mut ab_state, depth, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY);
+            assert!((expected.0 - pruned.0).abs() < 1e-3, "depth {}: {} != {}", depth, expected.0, pruned.0);
+            assert_eq!(expected.1, Some(1));
+        }
+    }
+
+    #[test]
+    fn chance_node_weighs_every_hit_count() {
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&flurry, &flurry, &flurry, &flurry]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&flurry, &flurry, &flurry, &flurry]));
+
+        let mut state = test_state();
+        state.player_turn = false;
+        let outcomes = action_outcomes(&monsters, &state, 0, true);
+        assert_eq!(outcomes.len(), 4);
+        assert!((outcomes.iter().map(|d| d.0).sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // The payoff is the average of the 2, 3, 4 and 5 hit outcomes
+        let mut single = state.clone();
+        let hit = monster::calculate_damage(&monsters, &mut single, 0, false) as f64;
+        let value = expectiminimax(&monsters, &state, 1, true, &HEALTH_ONLY).0;
+        assert!((value - 3.5 * hit).abs() < 1e-3, "{} != {}", value, 3.5 * hit);
+    }
+
+    #[test]
+    fn prefers_multi_hit_move_on_average() {
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&jab, &jab, &jab, &jab]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&flurry, &jab, &jab, &jab]));
+
+        let mut state = test_state();
+        state.player_turn = false;
+        // A single hit of Flurry is weaker than Jab, but all of its hits together are stronger
+        assert_eq!(expectiminimax(&monsters, &state, 2, true, &HEALTH_ONLY).1, Some(0));
+        assert_eq!(alphabeta(&monsters, &mut state.clone(), 2, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY).1, Some(1));
+    }
+
+    #[test]
+    fn search_sees_abilities() {
+        let abilities = crate::ability::load_abilities();
+        let mut quake = test_move("Quake", 40, monster::MoveEffect::None);
+        quake.attack_type = String::from("Ground");
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        let mut hero = test_monster(vec![&jab, &jab, &jab, &jab]);
+        hero.ability = Some(&abilities["Levitate"]);
+        monsters.insert(String::from("Hero"), hero);
+        monsters.insert(String::from("Foe"), test_monster(vec![&quake, &jab, &jab, &jab]));
+
+        let mut state = test_state();
+        state.player_turn = false;
+        // Quake is stronger, but does nothing to a levitating monster
+        assert_eq!(greedy_action(&monsters, &state, true), 1);
+        assert_eq!(alphabeta(&monsters, &mut state.clone(), 1, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY).1, Some(1));
+    }
+
+    #[test]
+    fn beats_minimax_in_stochastic_battles() {
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let tackle = test_move("Tackle", 32, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&flurry, &jab, &jab, &jab]));
+
+        let battles = 200;
+        let mut rng = StdRng::seed_from_u64(44);
+        let expecti_wins = (0..battles)
+            .filter(|_| play(&monsters, &|state| expectiminimax(&monsters, state, 2, true, &HEALTH_ONLY).1.unwrap(), &mut rng))
+            .count();
+        let mut rng = StdRng::seed_from_u64(44);
+        let minimax_wins = (0..battles)
+            .filter(|_| {
+                play(
+                    &monsters,
+                    &|state| {
+                        alphabeta(&monsters, &mut state.clone(), 2, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY)
+                            .1
+                            .unwrap()
+                    },
+                    &mut rng,
+                )
+            })
+            .count();
+        assert!(
+            expecti_wins > minimax_wins,
+            "expectiminimax won {} and minimax won {} of {}",
+            expecti_wins,
+            minimax_wins,
+            battles
+        );
+    }
+
+    fn test_config(budget: Budget) -> MctsConfig {
+        MctsConfig {
+            budget,
+            rollout: Rollout::Greedy,
+            exploration: 1.4,
+            rollout_depth: 20,
+        }
+    }
+
+    #[test]
+    fn mcts_takes_the_knockout() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let stare = test_move("Stare", 0, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&stare, &stare, &tackle, &stare]));
+
+        // Both leads are nearly down, so anything but knocking the player out loses
+        let mut state = test_state();
+        state.player_turn = false;
+        state.player_team[0].hp = 10.0;
+        state.enemy_team[0].1 = 10.0;
+        let mut rng = StdRng::seed_from_u64(45);
+        for rollout in [Rollout::Random, Rollout::Greedy].iter() {
+            let mut config = test_config(Budget::Iterations(300));
+            config.rollout = *rollout;
+            assert_eq!(mcts(&monsters, &state, true, &config, &HEALTH_ONLY, &mut rng), Some(2));
+        }
+    }
+
+    #[test]
+    fn mcts_stops_at_its_budget() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&tackle, &flurry, &tackle, &flurry]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&flurry, &tackle, &flurry, &tackle]));
+
+        let mut state = test_state();
+        state.player_turn = false;
+        let mut rng = StdRng::seed_from_u64(45);
+        let mut stopped = || Duration::from_millis(0);
+        let config = test_config(Budget::Iterations(37));
+        let nodes = mcts_tree(&monsters, &state, true, &config, &HEALTH_ONLY, &mut rng, &mut stopped);
+        assert_eq!(nodes[0].visits, 37);
+
+        // A clock that moves on 10ms each time it is read leaves time for five iterations
+        let mut now = Duration::from_millis(0);
+        let mut ticking = || {
+            now += Duration::from_millis(10);
+            now - Duration::from_millis(10)
+        };
+        let config = test_config(Budget::Millis(50));
+        let nodes = mcts_tree(&monsters, &state, true, &config, &HEALTH_ONLY, &mut rng, &mut ticking);
+        assert_eq!(nodes[0].visits, 5);
+
+        // With no time at all nothing is tried, so there is no action to pick
+        assert_eq!(mcts(&monsters, &state, true, &test_config(Budget::Millis(0)), &HEALTH_ONLY, &mut rng), None);
+    }
+
+    #[test]
+    fn mcts_holds_up_against_alphabeta() {
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut hard = test_move("Hard Stare", 0, monster::MoveEffect::None);
+        hard.opp_defense_stages = -1;
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&flurry, &jab, &hard, &jab]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&flurry, &jab, &hard, &jab]));
+        // Makes up for the player going first
+        monsters.get_mut("Hero").unwrap().attack_stat = 120;
+
+        // The same seeded matches with each search playing the enemy against α-β playing the player
+        let battles = 40;
+        let mut wins = Vec::new();
+        for use_mcts in [true, false].iter() {
+            let mut rng = StdRng::seed_from_u64(45);
+            let config = test_config(Budget::Iterations(400));
+            let mut won = 0;
+            for _ in 0..battles {
+                let mut player = |state: &monster::BattleState, _: &mut StdRng| {
+                    alphabeta(&monsters, &mut state.clone(), 2, -f64::INFINITY, f64::INFINITY, false, &HEALTH_ONLY)
+                        .1
+                        .unwrap_or(0)
+                };
+                let mut enemy = |state: &monster::BattleState, rng: &mut StdRng| {
+                    if *use_mcts {
+                        mcts(&monsters, state, true, &config, &HEALTH_ONLY, rng).unwrap_or(0)
+                    } else {
+                        alphabeta(&monsters, &mut state.clone(), 2, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY)
+                            .1
+                            .unwrap_or(0)
+                    }
+                };
+                let (result, _) = play_match(&monsters, &test_state(), &mut player, &mut enemy, &mut rng, 100);
+                if result == Some(true) {
+                    won += 1;
+                }
             }
+            wins.push(won);
+        }
+        assert!(wins[0] >= wins[1], "MCTS won {} and α-β won {} of {}", wins[0], wins[1], battles);
+    }
 
-            // Prune remaining actions if possible
-            if value <= alpha {
-                break; // (* α cutoff *)
+    // Three on three, so there are switches and transpositions to find
+    fn team_state() -> monster::BattleState {
+        let mut state = test_state();
+        for name in ["Sidekick", "Rookie"].iter() {
+            let mut mon = state.player_team[0].clone();
+            mon.name = String::from(*name);
+            state.player_team.push(mon);
+        }
+        state.enemy_team.push((String::from("Minion"), 100.0));
+        state.enemy_team.push((String::from("Grunt"), 100.0));
+        state.player_turn = false;
+        state
+    }
+
+    #[test]
+    fn deepening_matches_alphabeta_at_full_depth() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut stare = test_move("Stare", 0, monster::MoveEffect::None);
+        stare.opp_defense_stages = -1;
+        let mut monsters = HashMap::new();
+        for name in ["Hero", "Sidekick", "Rookie", "Foe", "Minion", "Grunt"].iter() {
+            monsters.insert(String::from(*name), test_monster(vec![&tackle, &jab, &stare, &jab]));
+        }
+        monsters.get_mut("Minion").unwrap().attack_stat = 130;
+
+        let state = team_state();
+        for depth in 1..=4 {
+            let plain = alphabeta(&monsters, &mut state.clone(), depth, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY);
+            let deepened = iterative_deepening(&monsters, &state, depth, Budget::Iterations(u32::MAX), true, &HEALTH_ONLY);
+            assert!((plain.0 - deepened.0).abs() < 1e-3, "depth {}: {} != {}", depth, plain.0, deepened.0);
+            assert!(deepened.1.is_some());
+        }
+    }
+
+    #[test]
+    fn deepening_stops_when_the_budget_runs_out() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let mut stare = test_move("Stare", 0, monster::MoveEffect::None);
+        stare.opp_defense_stages = -1;
+        let mut monsters = HashMap::new();
+        for name in ["Hero", "Sidekick", "Rookie", "Foe", "Minion", "Grunt"].iter() {
+            monsters.insert(String::from(*name), test_monster(vec![&tackle, &stare, &stare, &tackle]));
+        }
+
+        // Far too deep to finish, but there is still an action once the budget runs out, and the
+        // same budget always stops at the same place
+        let budget = Budget::Iterations(2000);
+        let result = iterative_deepening(&monsters, &team_state(), 50, budget, true, &HEALTH_ONLY);
+        assert!(result.1.is_some());
+        assert_eq!(result, iterative_deepening(&monsters, &team_state(), 50, budget, true, &HEALTH_ONLY));
+
+        // A search stopped by the clock stops in the same place when given the positions it visited
+        let timed = iterative_deepening(&monsters, &team_state(), 50, Budget::Millis(5), true, &HEALTH_ONLY);
+        let counted = iterative_deepening(&monsters, &team_state(), 50, Budget::Iterations(timed.2), true, &HEALTH_ONLY);
+        assert_eq!(timed, counted);
+
+        // Depth 1 is always searched, even with no budget at all
+        let depth_one = alphabeta(&monsters, &mut team_state(), 1, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY).1;
+        for budget in [Budget::Iterations(0), Budget::Millis(0)].iter() {
+            let result = iterative_deepening(&monsters, &team_state(), 50, *budget, true, &HEALTH_ONLY);
+            assert_eq!(result.1, depth_one);
+        }
+    }
+
+    #[test]
+    fn search_logs_replay_where_searches_stopped() {
+        let clock = Budget::Millis(5);
+        let mut log = SearchLog::new();
+        assert!(log.next_budget(clock) == clock);
+        log.record(120);
+        log.record(80);
+
+        let mut replay = SearchLog::replay(log.visited.clone());
+        assert!(replay.next_budget(clock) == Budget::Iterations(120));
+        assert!(replay.next_budget(clock) == Budget::Iterations(80));
+        // Replays from before searches were recorded fall back to the clock
+        assert!(replay.next_budget(clock) == clock);
+        // Nothing new is recorded while replaying
+        replay.record(999);
+        assert_eq!(replay.visited, vec![120, 80]);
+    }
+
+    #[test]
+    fn table_finds_transpositions() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        for name in ["Hero", "Sidekick", "Rookie", "Foe", "Minion", "Grunt"].iter() {
+            monsters.insert(String::from(*name), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        }
+
+        // Every move is the same, so states repeat across the tree
+        let mut search = Search::new(None, HEALTH_ONLY);
+        let state = team_state();
+        alphabeta_search(&monsters, &mut state.clone(), 3, -f64::INFINITY, f64::INFINITY, true, &mut search);
+        assert!(search.table.contains_key(&state_hash(&state, true)));
+        // Four identical moves lead to the same state, so far fewer states than paths are stored
+        assert!(search.table.len() < 1 + 6 + 36);
+    }
+
+    #[test]
+    fn difficulties_round_trip_through_their_names() {
+        for difficulty in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard].iter() {
+            assert_eq!(parse_difficulty(difficulty_name(*difficulty)), Ok(*difficulty));
+        }
+        assert!(parse_difficulty("Nightmare").is_err());
+    }
+
+    #[test]
+    fn weights_load_from_data() {
+        let weights = load_weights();
+        assert_eq!(weights["health_only"], HEALTH_ONLY);
+        assert!(weights.contains_key("default"));
+        for profile in [Profile::Standard, Profile::Aggressive, Profile::Defensive, Profile::Erratic, Profile::GymLeader].iter() {
+            assert!(weights.contains_key(profile_weights(*profile)));
+            assert_eq!(parse_profile(profile_name(*profile)), Ok(*profile));
+        }
+    }
+
+    #[test]
+    fn evaluation_counts_each_feature() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let mut splash = test_move("Splash", 30, monster::MoveEffect::None);
+        splash.attack_type = String::from("Water");
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&splash, &tackle, &tackle, &tackle]));
+        monsters.get_mut("Hero").unwrap().monster_type = String::from("Fire");
+
+        let only = |feature: fn(&mut Weights)| {
+            let mut weights = Weights {
+                health: 0.0,
+                alive: 0.0,
+                matchup: 0.0,
+                stages: 0.0,
+                tempo: 0.0,
+            };
+            feature(&mut weights);
+            weights
+        };
+        let mut state = team_state();
+        state.player_team[0].name = String::from("Hero");
+        state.enemy_team[0].0 = String::from("Foe");
+        for name in ["Sidekick", "Rookie", "Minion", "Grunt"].iter() {
+            monsters.insert(String::from(*name), test_monster(vec![&tackle, &tackle, &tackle, &tackle]));
+        }
+        state.player_team[2].hp = 0.0;
+        state.enemy_team[1].1 = 40.0;
+        state.opp_attack_stages = 2;
+        state.self_defense_stages = -1;
+
+        let eval = |weights: Weights, maximizing_player: bool| evaluation_function(&monsters, &state, maximizing_player, &weights);
+        assert_eq!(eval(only(|w| w.health = 1.0), true), 40.0);
+        assert_eq!(eval(only(|w| w.alive = 1.0), true), 1.0);
+        // Water is super effective on Fire, while Normal is even against Normal
+        assert_eq!(eval(only(|w| w.matchup = 1.0), true), 1.0);
+        assert_eq!(eval(only(|w| w.stages = 1.0), true), 3.0);
+        assert_eq!(eval(only(|w| w.tempo = 1.0), true), 1.0);
+        assert_eq!(eval(only(|w| w.tempo = 1.0), false), -1.0);
+    }
+
+    #[test]
+    fn same_weights_split_the_games() {
+        let tackle = test_move("Tackle", 30, monster::MoveEffect::None);
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        let mut monsters = HashMap::new();
+        for name in ["Hero", "Sidekick", "Rookie", "Foe"].iter() {
+            monsters.insert(String::from(*name), test_monster(vec![&tackle, &flurry, &tackle, &flurry]));
+        }
+        monsters.get_mut("Foe").unwrap().attack_stat = 80;
+
+        // Each battle is played from both sides with the same rolls, so equal weights win equally often
+        let (a, b) = compare_weights(&monsters, &HEALTH_ONLY, &HEALTH_ONLY, 1, 10, 47);
+        assert_eq!(a, b);
+        assert!(a + b > 0);
+    }
+
+    // A small random battle between the given monsters: one to three on each side, some fainted,
+    // with random health, stages and side to move
+    fn random_state(names: &[String], rng: &mut StdRng) -> (monster::BattleState, bool) {
+        let pick = |rng: &mut StdRng| -> Vec<String> {
+            (0..rng.gen_range(1..=3)).map(|_| names[rng.gen_range(0..names.len())].clone()).collect()
+        };
+        let player_team = pick(rng);
+        let enemy_team = pick(rng);
+        let mut state = team_battle(&player_team, monster::STARTING_LEVEL, &enemy_team, monster::STARTING_LEVEL, rng);
+        for mon in state.player_team.iter_mut() {
+            mon.hp = if rng.gen_bool(0.2) { 0.0 } else { rng.gen_range(1..=100) as f32 };
+        }
+        for mon in state.enemy_team.iter_mut() {
+            mon.1 = if rng.gen_bool(0.2) { 0.0 } else { rng.gen_range(1..=100) as f32 };
+        }
+        state.player_team = monster::verify_team(&state.player_team);
+        state.enemy_team = monster::verify_team(&state.enemy_team);
+        state.self_attack_stages = rng.gen_range(-2..=2);
+        state.opp_defense_stages = rng.gen_range(-2..=2);
+        let maximizing_player = rng.gen_bool(0.5);
+        state.player_turn = !maximizing_player;
+        (state, maximizing_player)
+    }
+
+    // Monsters of a few types with damaging, stage and multi-hit moves
+    fn random_monsters<'a>(moves: &'a [monster::Move]) -> HashMap<String, monster::Monster<'a>> {
+        let mut monsters = HashMap::new();
+        for (index, (name, monster_type)) in
+            [("Sprout", "Grass"), ("Ember", "Fire"), ("Drip", "Water"), ("Pebble", "Normal")].iter().enumerate()
+        {
+            let mut mon = test_monster((0..4).map(|d| &moves[(index + d) % moves.len()]).collect());
+            mon.monster_type = String::from(*monster_type);
+            mon.attack_stat = 80 + 10 * index as u32;
+            monsters.insert(String::from(*name), mon);
+        }
+        monsters
+    }
+
+    fn random_moves() -> Vec<monster::Move> {
+        let mut leaf = test_move("Leaf", 35, monster::MoveEffect::None);
+        leaf.attack_type = String::from("Grass");
+        let mut flame = test_move("Flame", 40, monster::MoveEffect::None);
+        flame.attack_type = String::from("Fire");
+        let mut splash = test_move("Splash", 30, monster::MoveEffect::None);
+        splash.attack_type = String::from("Water");
+        let mut growl = test_move("Growl", 0, monster::MoveEffect::None);
+        growl.opp_attack_stages = -1;
+        let flurry = test_move("Flurry", 10, monster::MoveEffect::MultiHit(2, 5));
+        vec![leaf, flame, splash, growl, flurry]
+    }
+
+    #[test]
+    fn alphabeta_matches_minimax_on_random_states() {
+        let moves = random_moves();
+        let monsters = random_monsters(&moves);
+        let mut names: Vec<String> = monsters.keys().cloned().collect();
+        names.sort();
+
+        let mut rng = StdRng::seed_from_u64(49);
+        for case in 0..300 {
+            let (state, maximizing_player) = random_state(&names, &mut rng);
+            let depth = rng.gen_range(1..=4);
+            let weights = if case % 2 == 0 { HEALTH_ONLY } else { load_weights()["default"] };
+
+            let expected = minimax(&monsters, &state, depth, maximizing_player, &weights);
+            let pruned = alphabeta(
+                &monsters,
+                &mut state.clone(),
+                depth,
+                -f64::INFINITY,
+                f64::INFINITY,
+                maximizing_player,
+                &weights,
+            );
+            assert_eq!(pruned.0, expected.0, "case {} at depth {}", case, depth);
+
+            // Ties can be broken either way, but α-β's action has to be worth what it says
+            match pruned.1 {
+                Some(action) => {
+                    let next = search_step(&monsters, &state, action, maximizing_player);
+                    assert_eq!(minimax(&monsters, &next, depth - 1, !maximizing_player, &weights).0, expected.0);
+                }
+                None => assert_eq!(expected.1, None),
             }
+        }
+    }
+
+    #[test]
+    fn terminal_states_are_not_searched() {
+        let moves = random_moves();
+        let monsters = random_monsters(&moves);
+        let mut state = team_battle(
+            &[String::from("Sprout"), String::from("Drip")],
+            monster::STARTING_LEVEL,
+            &[String::from("Ember")],
+            monster::STARTING_LEVEL,
+            &mut StdRng::seed_from_u64(49),
+        );
 
-            // Update alpha (the best option so far for minimizing player)
-            beta = beta.min(value);
+        // Only one side being out of monsters ends the battle
+        state.enemy_team[0].1 = 0.0;
+        for maximizing_player in [true, false].iter() {
+            let result = alphabeta(&monsters, &mut state.clone(), 3, -f64::INFINITY, f64::INFINITY, *maximizing_player, &HEALTH_ONLY);
+            assert_eq!(result.1, None);
+            assert_eq!(result.0, evaluation_function(&monsters, &state, *maximizing_player, &HEALTH_ONLY));
+            assert_eq!(result, minimax(&monsters, &state, 3, *maximizing_player, &HEALTH_ONLY));
         }
-        return ret;
+
+        let mut state = team_battle(
+            &[String::from("Sprout")],
+            monster::STARTING_LEVEL,
+            &[String::from("Ember"), String::from("Drip")],
+            monster::STARTING_LEVEL,
+            &mut StdRng::seed_from_u64(49),
+        );
+        state.player_team[0].hp = 0.0;
+        let result = alphabeta(&monsters, &mut state.clone(), 3, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY);
+        assert_eq!(result.1, None);
+    }
+
+    #[test]
+    fn single_survivor_can_only_use_moves() {
+        let moves = random_moves();
+        let monsters = random_monsters(&moves);
+        let team = vec![String::from("Sprout"), String::from("Drip"), String::from("Pebble")];
+        let mut state = team_battle(&team, monster::STARTING_LEVEL, &team, monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(49));
+        state.enemy_team[0].1 = 0.0;
+        state.enemy_team[1].1 = 0.0;
+        state.enemy_team = monster::verify_team(&state.enemy_team);
+        state.player_team[2].hp = 0.0;
+        state.player_turn = false;
+
+        assert_eq!(num_actions(&state, true), 4);
+        assert_eq!(num_actions(&state, false), 5);
+        for depth in 1..=4 {
+            let result = alphabeta(&monsters, &mut state.clone(), depth, -f64::INFINITY, f64::INFINITY, true, &HEALTH_ONLY);
+            assert!(result.1.unwrap() < 4);
+            assert_eq!(result.0, minimax(&monsters, &state, depth, true, &HEALTH_ONLY).0);
+        }
+    }
+
+    #[test]
+    fn switches_send_out_the_right_monster() {
+        let moves = random_moves();
+        let monsters = random_monsters(&moves);
+        let team = vec![String::from("Sprout"), String::from("Ember"), String::from("Drip")];
+        let mut state = team_battle(&team, monster::STARTING_LEVEL, &team, monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(49));
+        // The monster right behind each lead has fainted, so the only switch is to the last one
+        state.player_team[1].hp = 0.0;
+        state.enemy_team[1].1 = 0.0;
+
+        let next = search_step(&monsters, &state, 4, false);
+        assert_eq!(next.player_team[0].name, "Drip");
+        assert_eq!(next.player_team[2].name, "Sprout");
+        assert_eq!(next.player_team[1].hp, 0.0);
+        let next = search_step(&monsters, &state, 4, true);
+        assert_eq!(next.enemy_team[0].0, "Drip");
+        assert_eq!(next.enemy_team[2].0, "Sprout");
+    }
+
+    #[test]
+    fn switches_out_of_a_bad_matchup() {
+        let moves = random_moves();
+        let mut monsters = random_monsters(&moves);
+        monsters.get_mut("Ember").unwrap().moves = vec![&moves[1], &moves[1], &moves[1], &moves[1]];
+        monsters.get_mut("Sprout").unwrap().moves = vec![&moves[3], &moves[3], &moves[3], &moves[3]];
+
+        // Fire knocks the Grass lead out, but barely scratches Water
+        let team = vec![String::from("Sprout"), String::from("Pebble"), String::from("Drip")];
+        let mut state = team_battle(&team, monster::STARTING_LEVEL, &[String::from("Ember")], monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(49));
+        state.player_team[0].hp = 20.0;
+        state.player_team[1].hp = 0.0;
+
+        let result = alphabeta(&monsters, &mut state.clone(), 2, -f64::INFINITY, f64::INFINITY, false, &HEALTH_ONLY);
+        assert_eq!(result.1, Some(4));
+        assert_eq!(result, minimax(&monsters, &state, 2, false, &HEALTH_ONLY));
+    }
+
+    #[test]
+    fn double_battles_target_the_weaker_foe() {
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&jab, &jab, &jab, &jab]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&jab, &jab, &jab, &jab]));
+
+        let mut state = test_state();
+        state.double = true;
+        state.player_turn = false;
+        state.player_team.push(state.player_team[0].clone());
+        state.enemy_team.push(state.enemy_team[0].clone());
+        state.player_team[1].hp = 5.0;
+
+        let settings = SearchSettings {
+            difficulty: Difficulty::Normal,
+            profile: Profile::Standard,
+            depth: 2,
+            budget: Budget::Iterations(u32::MAX),
+            hard_search: test_config(Budget::Iterations(200)),
+            weights: HEALTH_ONLY,
+        };
+        let mut rng = StdRng::seed_from_u64(31);
+        for slot in 0..2 {
+            // Every move knocks out the foe at 5 health
+            assert_eq!(choose_double_action(&monsters, &state, slot, &[0, 1], &settings, &mut SearchLog::new(), &mut rng).1, 1);
+        }
+        // With only one foe left it is the target
+        assert_eq!(choose_double_action(&monsters, &state, 0, &[0], &settings, &mut SearchLog::new(), &mut rng).1, 0);
+    }
+
+    #[test]
+    fn double_replacements_favour_the_matchup() {
+        let jab = test_move("Jab", 25, monster::MoveEffect::None);
+        let slam = test_move("Slam", 60, monster::MoveEffect::None);
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(vec![&jab, &jab, &jab, &jab]));
+        monsters.insert(String::from("Foe"), test_monster(vec![&jab, &jab, &jab, &jab]));
+        monsters.insert(String::from("Brute"), test_monster(vec![&slam, &slam, &slam, &slam]));
+
+        let mut state = test_state();
+        state.double = true;
+        state.player_team.push(state.player_team[0].clone());
+        state.enemy_team = vec![
+            (String::from("Foe"), 0.0),
+            (String::from("Foe"), 100.0),
+            (String::from("Foe"), 100.0),
+            (String::from("Brute"), 100.0),
+        ];
+
+        // Brute hits both foes far harder than Foe does
+        assert_eq!(choose_double_replacement(&monsters, &state, &[2, 3], &[0, 1], 2, &HEALTH_ONLY), Some(3));
+        assert_eq!(choose_double_replacement(&monsters, &state, &[2], &[0, 1], 2, &HEALTH_ONLY), Some(2));
+        assert_eq!(choose_double_replacement(&monsters, &state, &[], &[0, 1], 2, &HEALTH_ONLY), None);
+    }
+
+    #[test]
+    fn profiles_pick_their_policy() {
+        let moves = random_moves();
+        let monsters = random_monsters(&moves);
+        let team = vec![String::from("Sprout"), String::from("Ember"), String::from("Drip")];
+        let mut state = team_battle(&team, monster::STARTING_LEVEL, &team, monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(50));
+        state.player_turn = false;
+        let settings = SearchSettings {
+            difficulty: Difficulty::Normal,
+            profile: Profile::Standard,
+            depth: 3,
+            budget: Budget::Iterations(u32::MAX),
+            hard_search: test_config(Budget::Iterations(200)),
+            weights: HEALTH_ONLY,
+        };
+        let choose = |difficulty: Difficulty, profile: Profile, seed: u64| {
+            let settings = SearchSettings { difficulty, profile, ..settings };
+            choose_action(&monsters, &state, &settings, &mut SearchLog::new(), &mut StdRng::seed_from_u64(seed))
+        };
+
+        // Aggressive trainers only search on hard
+        for difficulty in [Difficulty::Easy, Difficulty::Normal].iter() {
+            assert_eq!(choose(*difficulty, Profile::Aggressive, 50), greedy_action(&monsters, &state, true));
+        }
+        // Gym leaders search in full even on easy
+        let full = iterative_deepening(&monsters, &state, settings.depth, settings.budget, true, &settings.weights).1;
+        assert_eq!(Some(choose(Difficulty::Easy, Profile::GymLeader, 50)), full);
+        assert_eq!(Some(choose(Difficulty::Normal, Profile::Standard, 50)), full);
+        assert_eq!(choose(Difficulty::Hard, Profile::GymLeader, 50), choose(Difficulty::Hard, Profile::Standard, 50));
+
+        // Defensive trainers set up or switch where the search alone would attack, unless on hard
+        for difficulty in [Difficulty::Easy, Difficulty::Normal].iter() {
+            assert!(!is_setup_or_switch(&monsters, &state, choose(*difficulty, Profile::Standard, 50)));
+            assert!(is_setup_or_switch(&monsters, &state, choose(*difficulty, Profile::Defensive, 50)));
+        }
+        assert_eq!(choose(Difficulty::Hard, Profile::Defensive, 50), choose(Difficulty::Hard, Profile::Standard, 50));
+        // Erratic trainers stray from the search now and then, more often on easier difficulties
+        let strays = |difficulty: Difficulty| {
+            let standard = choose(difficulty, Profile::Standard, 0);
+            (0..200).filter(|seed| choose(difficulty, Profile::Erratic, *seed) != standard).count()
+        };
+        let (easy, normal) = (strays(Difficulty::Easy), strays(Difficulty::Normal));
+        assert!(normal > 0, "never strayed on normal");
+        assert!(easy > normal, "strayed {} times on easy and {} on normal", easy, normal);
     }
 }
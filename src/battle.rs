@@ -7,9 +7,16 @@ use std::time::Duration;
 use std::thread;
 use std::collections::HashMap;
 
-use rand::{self, Rng};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
+use crate::ai;
 use crate::monster;
+use crate::inventory;
+use crate::hooks;
+use crate::replay;
+use crate::transcript::{self, Event};
+use crate::weather::{self, Weather};
 
 pub enum Map {
     Intro,
@@ -21,11 +28,31 @@ pub enum Map {
     GymTwo,
     GymThree,
     GymFour,
+    Shop,
 }
 
 const CAM_W: u32 = 1280;
 const CAM_H: u32 = 720;
 const MESSAGE_TIME: u64 = 500;
+// Blacking out costs the player this fraction of their money
+const BLACKOUT_LOSS: u32 = 2;
+const BAG_ROWS: usize = 10;
+pub const LOG_ROWS: usize = 15;
+// How far ahead trainers look when picking a replacement or an action
+const REPLACEMENT_DEPTH: i32 = 2;
+const ACTION_DEPTH: i32 = 6;
+// Trainers stop searching deeper once a turn's search has taken this long, so an enemy turn never
+// stalls. Replays stop each search where the battle did instead, since they save how far it got.
+const ACTION_MILLIS: u64 = 200;
+// How trainers search on hard; a fixed number of iterations rather than a time limit so replays match
+const HARD_SEARCH: ai::MctsConfig = ai::MctsConfig {
+    budget: ai::Budget::Iterations(2000),
+    rollout: ai::Rollout::Greedy,
+    exploration: 1.4,
+    rollout_depth: 20,
+};
+// Monsters active per side in a double battle
+pub const DOUBLE_ACTIVE: usize = 2;
 
 fn center(r1: Rect, w: u32, h: u32) -> Rect {
     let mut x = r1.x();
@@ -154,6 +181,45 @@ pub struct Battle<'a> {
     pub monster_text_map: &'a HashMap<String, sdl2::render::Texture<'a>>,
     pub moves: &'a HashMap<String, monster::Move>,
     pub monsters: &'a HashMap<String, monster::Monster<'a>>,
+    pub items: &'a HashMap<String, inventory::Item>,
+    pub weather: Option<weather::Weather>,
+    pub double: Option<DoubleDraw>,
+    pub transcript: transcript::Transcript,
+    // Every random roll in the battle comes from here, so a replay can reproduce them
+    pub rng: StdRng,
+    // Likewise how far each of the trainer's searches got before the clock ran out
+    pub searches: ai::SearchLog,
+    pub replay: replay::Replay,
+    // Team members that gained a level in the last battle, checked for evolution afterwards
+    pub level_ups: Vec<usize>,
+    // Set while a saved replay is played back, so it isn't saved again
+    pub replaying: bool,
+    // Skips all drawing and pauses, for verifying replays
+    pub headless: bool,
+    // The player's money, which battles pay out and take away
+    pub money: u32,
+    // What winning the current battle pays; only trainers pay anything
+    pub prize: u32,
+    // Decides how trainers pick their actions
+    pub difficulty: ai::Difficulty,
+    // How the current trainer picks their actions on top of the difficulty
+    pub profile: ai::Profile,
+    // How trainers evaluate the battle when searching
+    pub weights: ai::Weights,
+    // Index in the player's team of the monster that's out, followed through the monster menu
+    pub lead: usize,
+}
+
+// The second active monster on each side of a double battle, mirrored like the first
+pub struct DoubleDraw {
+    pub player_name: String,
+    pub player_health: f32,
+    pub enemy_name: String,
+    pub enemy_health: f32,
+    // Which of the player's active monsters is choosing a move
+    pub acting: usize,
+    // Which foe is highlighted while a target is being chosen
+    pub target: Option<usize>,
 }
 
 impl<'a> Battle<'a> {
@@ -167,15 +233,35 @@ impl<'a> Battle<'a> {
     }
 }
 
+// Overlay drawn on top of the battle background while the weather lasts
+fn weather_tint(weather: Weather) -> Color {
+    match weather {
+        Weather::Rain => Color::RGBA(0x20, 0x40, 0xa0, 70),
+        Weather::Sun => Color::RGBA(0xff, 0xc0, 0x40, 60),
+        Weather::Sandstorm => Color::RGBA(0xc2, 0x9a, 0x5b, 90),
+        Weather::Storm => Color::RGBA(0x20, 0x20, 0x40, 110),
+    }
+}
+
 pub fn draw_battle(
     wincan: &mut sdl2::render::WindowCanvas,
     battle_init: &Battle,
     choice: Option<usize>,
     message: Option<String>,
 ) -> Result<(), String> {
+    if battle_init.headless {
+        return Ok(());
+    }
+
     // Load the battle scene background
     wincan.copy(&battle_init.background_texture, None, Rect::new(0,0,CAM_W,CAM_H))?;
 
+    // Tint the scene while there is weather
+    if let Some(w) = battle_init.weather {
+        wincan.set_draw_color(weather_tint(w));
+        wincan.fill_rect(Rect::new(0, 0, CAM_W, CAM_H))?;
+    }
+
     let move_rects: Vec<_> = (0..4)
         .map(|i| 180 + i * (200 + 40))
         .map(|i| Rect::new(i, 560 as i32, 200, 100))
@@ -200,6 +286,12 @@ pub fn draw_battle(
         None => {}
     };
 
+    // Show the moves of whichever monster is choosing
+    let acting = match &battle_init.double {
+        Some(double) if double.acting == 1 => &double.player_name,
+        _ => &battle_init.player_name,
+    };
+
     // For all moves
     for (index, item) in move_rects.into_iter().enumerate() {
         // Create the background for each move
@@ -207,7 +299,7 @@ pub fn draw_battle(
         wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
         wincan.fill_rect(r)?;
 
-        let attack_name = &battle_init.monsters[&battle_init.player_name].moves[index].name;
+        let attack_name = &battle_init.monsters[acting].moves[index].name;
         let texture = &battle_init.attack_map[attack_name];
         
         // Add the names of each attack
@@ -218,7 +310,7 @@ pub fn draw_battle(
         
         wincan.copy(&texture, None, text_rect)?;
         
-        let effect_name = &battle_init.monsters[&battle_init.player_name].moves[index].effect;
+        let effect_name = &battle_init.monsters[acting].moves[index].effect;
         let texture = &battle_init.effect_map[effect_name];
         
         // Add the names of each effect
@@ -239,6 +331,11 @@ pub fn draw_battle(
 
     // Calculate and add health bars for each monster
     health_bars(wincan, battle_init.player_health, battle_init.enemy_health)?;
+
+    // Add the partners in a double battle
+    if let Some(double) = &battle_init.double {
+        draw_partners(wincan, battle_init, double)?;
+    }
     
     // Print out a message if needed
     match message {
@@ -285,6 +382,54 @@ pub fn health_bars(wincan: &mut sdl2::render::WindowCanvas, player_health: f32,
     Ok(())
 }
 
+fn draw_partners(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &Battle,
+    double: &DoubleDraw,
+) -> Result<(), String> {
+    let player_rect = Rect::new(1030, 300, 170, 170);
+    let enemy_rect = Rect::new(80, 45, 170, 170);
+    wincan.copy(&battle_init.monster_text_map[&double.player_name], None, player_rect)?;
+    wincan.copy_ex(&battle_init.monster_text_map[&double.enemy_name], None, enemy_rect, 0 as f64, None, true, false)?;
+
+    for (name, health, r) in [
+        (&double.player_name, double.player_health, Rect::new(1010, 480, 210, 50)),
+        (&double.enemy_name, double.enemy_health, Rect::new(60, 225, 210, 50)),
+    ]
+    .iter()
+    {
+        wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
+        wincan.fill_rect(*r)?;
+        draw_text(wincan, battle_init.font, name, Color::RGB(0xbd, 0xcd, 0xde), Rect::new(r.x() + 5, r.y() + 2, r.width() - 10, 26))?;
+        wincan.set_draw_color(Color::BLACK);
+        wincan.fill_rect(Rect::new(r.x() + 5, r.y() + 30, r.width() - 10, 14))?;
+        if *health > 0.0 {
+            menu_health_bars(
+                wincan,
+                *health,
+                r.x() + 5,
+                r.y() + 30,
+                (health * (r.width() - 10) as f32 / 100.0).ceil() as u32,
+                14,
+            )?;
+        }
+    }
+
+    // Outline the foe being targeted
+    let target = match double.target {
+        Some(0) => Some(Rect::new(280, 25, 200, 200)),
+        Some(_) => Some(enemy_rect),
+        None => None,
+    };
+    if let Some(r) = target {
+        wincan.set_draw_color(Color::YELLOW);
+        for i in 0..4 {
+            wincan.draw_rect(Rect::new(r.x() - i, r.y() - i, r.width() + 2 * i as u32, r.height() + 2 * i as u32))?;
+        }
+    }
+    Ok(())
+}
+
 fn message_box<'a>(
     wincan: &mut sdl2::render::WindowCanvas, 
     font: &'a sdl2::ttf::Font,
@@ -355,7 +500,7 @@ pub fn player_battle_turn(
     let player_monster = battle_draw.player_name.clone();
 
     // Message for what move was used
-    thread::sleep(Duration::from_millis(100));
+    pause(battle_draw, 100);
 
     let f = format!(
         "{} used {}!",
@@ -364,9 +509,32 @@ pub fn player_battle_turn(
     draw_battle(wincan, &battle_draw, None, Some(f))?;
 
     // Apply the damage internally and to the drawing
+    let before = stages(battle_state);
     let d = monster::calculate_damage(battle_draw.monsters, battle_state, current_choice, true);
-    battle_draw.apply_enemy_damage(d);
+    battle_draw.weather = battle_state.weather.map(|d| d.0);
+    let (d, hook_messages) = hooks::modify_damage(
+        battle_draw.items,
+        battle_draw.monsters,
+        battle_state,
+        monsters_map[&player_monster].moves[current_choice],
+        true,
+        (0, 0),
+        d,
+    );
+    let result = monster::resolve_move_effect(
+        monsters_map[&player_monster].moves[current_choice],
+        d,
+        battle_state.player_team[0].hp,
+        battle_state.enemy_team[0].1,
+        &mut battle_draw.rng,
+    );
+    battle_draw.apply_enemy_damage(result.damage);
+    let dealt = battle_state.enemy_team[0].1 - battle_draw.enemy_health;
     battle_state.enemy_team[0].1 = battle_draw.enemy_health;
+    battle_state.player_team[0].hp =
+        (battle_state.player_team[0].hp + result.user_health_change).clamp(0.0, 100.0);
+    battle_draw.player_health = battle_state.player_team[0].hp;
+    log_move(battle_state, battle_draw, monsters_map[&player_monster].moves[current_choice], &player_monster, &enemy_monster, dealt, before);
 
     // Check effectiveness, and message based upon it
     let effectiveness = monster::str_effectiveness(
@@ -375,44 +543,65 @@ pub fn player_battle_turn(
     );
     match effectiveness {
         Some(s) => {
-            thread::sleep(Duration::from_millis(300));
+            pause(battle_draw, 300);
             draw_battle(wincan, &battle_draw, None, Some(s))?;
         }
         None => {
             draw_battle(wincan, &battle_draw, None, None)?;
         }
     }
+    if let Some(s) = monster::str_move_effect(
+        monsters_map[&player_monster].moves[current_choice],
+        &result,
+        &player_monster,
+        &enemy_monster,
+    ) {
+        pause(battle_draw, 300);
+        announce(wincan, battle_draw, s)?;
+    }
+    show_hook_messages(wincan, battle_state, battle_draw, hook_messages)?;
+
+    // Let the enemy's ability react to the lost health
+    if result.damage > 0.0 {
+        let messages = hooks::run_hooks(battle_draw.items, battle_draw.monsters, battle_state, hooks::Trigger::HealthDropped, false, 0);
+        show_hook_messages(wincan, battle_state, battle_draw, messages)?;
+    }
 
-    thread::sleep(Duration::from_millis(300));
+    pause(battle_draw, 300);
 
     if battle_draw.enemy_health == 0.0 {
         // Write message that enemy is KO'd
-        thread::sleep(Duration::from_millis(200));
+        pause(battle_draw, 200);
         let f = format!("{} KO'd {}!", &player_monster, &enemy_monster);
         draw_battle(wincan, &battle_draw, None, Some(f))?;
+        battle_draw.transcript.log(Event::Faint(enemy_monster.clone()));
 
-        if battle_state.enemy_team.len() > 1 && battle_state.enemy_team[1].1 > 0.0 {
-            battle_state.enemy_team = verify_team(&battle_state.enemy_team);
+        // The AI decides who comes out next
+        if let Some(index) = ai::choose_replacement(monsters_map, battle_state, REPLACEMENT_DEPTH, &battle_draw.weights) {
+            battle_state.enemy_team.swap(0, index);
+            reset_stages(battle_state, false);
             battle_draw.enemy_health = battle_state.enemy_team[0].1;
             battle_draw.enemy_name = battle_state.enemy_team[0].0.clone();
             
-            thread::sleep(Duration::from_millis(200));
+            pause(battle_draw, 200);
             let f = format!("Enemy sent out {}!", battle_state.enemy_team[0].0);
             draw_battle(wincan, &battle_draw, None, Some(f))?;
-            thread::sleep(Duration::from_millis(200));
+            battle_draw.transcript.log(Event::Switch {
+                withdrawn: enemy_monster.clone(),
+                sent_out: battle_state.enemy_team[0].0.clone(),
+            });
+            on_entry(wincan, battle_state, battle_draw, false, 0)?;
+            pause(battle_draw, 200);
             battle_state.player_turn = !battle_state.player_turn;
         } else {
-            thread::sleep(Duration::from_millis(200));
+            pause(battle_draw, 200);
             let f = format!("You defeated the enemy!");
             draw_battle(wincan, &battle_draw, None, Some(f))?;
+            reward_winners(wincan, battle_state, battle_draw)?;
+            finish_battle(battle_draw, transcript::PLAYER_WON);
 
             // Fade out back to the overworld
-            let screen = Rect::new(0, 0, CAM_W, CAM_H);
-            wincan.set_draw_color(Color::RGBA(0, 0, 0, 15));
-            for _i in 0..50 {
-                wincan.fill_rect(screen)?;
-                wincan.present();
-            }
+            fade_out(wincan, battle_draw)?;
             return Ok(Map::Overworld);
         }
     }
@@ -429,11 +618,28 @@ pub fn enemy_battle_turn(
     let enemy_monster = battle_draw.enemy_name.clone();
     let player_monster = battle_draw.player_name.clone();
 
-    let enemy_choice = rand::thread_rng().gen_range(0..4) as usize;
+    let settings = search_settings(battle_draw);
+    let enemy_choice = ai::choose_action(
+        monsters_map,
+        battle_state,
+        &settings,
+        &mut battle_draw.searches,
+        &mut battle_draw.rng,
+    );
+
+    // Switching in another monster uses up the enemy's turn
+    if enemy_choice >= 4 {
+        let index = ai::switch_target(&battle_state.enemy_team, enemy_choice);
+        battle_state.enemy_team.swap(0, index);
+        pause(battle_draw, 300);
+        switched_in(wincan, battle_state, battle_draw, false, &enemy_monster)?;
+        battle_state.player_turn = !battle_state.player_turn;
+        return Ok(Map::Battle);
+    }
 
     // Message for what move was used
 
-    thread::sleep(Duration::from_millis(300));
+    pause(battle_draw, 300);
 
     let f = format!(
         "{} used {}!",
@@ -442,10 +648,33 @@ pub fn enemy_battle_turn(
     draw_battle(wincan, &battle_draw, None, Some(f))?;
 
     // Apply the damage internally and to the drawing
+    let before = stages(battle_state);
     let d = monster::calculate_damage(battle_draw.monsters, battle_state, enemy_choice, false);
-    battle_draw.apply_player_damage(d);
-    battle_state.player_team[0].1 = battle_draw.player_health;
-    
+    battle_draw.weather = battle_state.weather.map(|d| d.0);
+    let (d, hook_messages) = hooks::modify_damage(
+        battle_draw.items,
+        battle_draw.monsters,
+        battle_state,
+        monsters_map[&enemy_monster].moves[enemy_choice],
+        false,
+        (0, 0),
+        d,
+    );
+    let result = monster::resolve_move_effect(
+        monsters_map[&enemy_monster].moves[enemy_choice],
+        d,
+        battle_state.enemy_team[0].1,
+        battle_state.player_team[0].hp,
+        &mut battle_draw.rng,
+    );
+    battle_draw.apply_player_damage(result.damage);
+    let dealt = battle_state.player_team[0].hp - battle_draw.player_health;
+    battle_state.player_team[0].hp = battle_draw.player_health;
+    battle_state.enemy_team[0].1 =
+        (battle_state.enemy_team[0].1 + result.user_health_change).clamp(0.0, 100.0);
+    battle_draw.enemy_health = battle_state.enemy_team[0].1;
+    log_move(battle_state, battle_draw, monsters_map[&enemy_monster].moves[enemy_choice], &enemy_monster, &player_monster, dealt, before);
+
     // Check effectiveness, and message based upon it
     let effectiveness = monster::str_effectiveness(
         &monsters_map[&enemy_monster].moves[enemy_choice],
@@ -453,7 +682,7 @@ pub fn enemy_battle_turn(
     );
     match effectiveness {
         Some(s) => {
-            thread::sleep(Duration::from_millis(300));
+            pause(battle_draw, 300);
             draw_battle(wincan, &battle_draw, None, Some(s))?;
         }
         None => {
@@ -461,39 +690,50 @@ pub fn enemy_battle_turn(
         }
     }
 
-    thread::sleep(Duration::from_millis(300));
+    if let Some(s) = monster::str_move_effect(
+        monsters_map[&enemy_monster].moves[enemy_choice],
+        &result,
+        &enemy_monster,
+        &player_monster,
+    ) {
+        pause(battle_draw, 300);
+        announce(wincan, battle_draw, s)?;
+    }
+    show_hook_messages(wincan, battle_state, battle_draw, hook_messages)?;
+
+    // Let the player's held item and ability react to the lost health
+    if result.damage > 0.0 {
+        let messages = hooks::run_hooks(battle_draw.items, battle_draw.monsters, battle_state, hooks::Trigger::HealthDropped, true, 0);
+        show_hook_messages(wincan, battle_state, battle_draw, messages)?;
+    }
+
+    pause(battle_draw, 300);
 
     if battle_draw.player_health == 0.0 {
         // Write message that player is KO'd
-        thread::sleep(Duration::from_millis(200));
+        pause(battle_draw, 200);
         let f = format!("{} KO'd {}!", &enemy_monster, &player_monster);
         draw_battle(wincan, &battle_draw, None, Some(f))?;
+        battle_draw.transcript.log(Event::Faint(player_monster.clone()));
         
-        if battle_state.player_team.len() > 1 && battle_state.player_team[1].1 > 0.0 {
-            battle_state.player_team = verify_team(&battle_state.player_team);
-            battle_draw.player_health = battle_state.player_team[0].1;
-            battle_draw.player_name = battle_state.player_team[0].0.clone();
-            
-            thread::sleep(Duration::from_millis(200));
-            let f = format!("Player sent out {}!", battle_state.player_team[0].0);
+        // The player picks the replacement from the monster menu before the battle goes on
+        if battle_state.player_team.iter().any(|d| d.hp > 0.0) {
+            pause(battle_draw, 200);
+            let f = String::from("Choose who to send out next!");
             draw_battle(wincan, &battle_draw, None, Some(f))?;
-            thread::sleep(Duration::from_millis(200));
             battle_state.player_turn = !battle_state.player_turn;
         } else {
-            thread::sleep(Duration::from_millis(200));
+            pause(battle_draw, 200);
             let f = format!("You blacked out!");
             draw_battle(wincan, &battle_draw, None, Some(f))?;
+            lose_money(wincan, battle_draw)?;
+            finish_battle(battle_draw, transcript::BLACKED_OUT);
 
             // Fade out back to the overworld
-            let screen = Rect::new(0, 0, CAM_W, CAM_H);
-            wincan.set_draw_color(Color::RGBA(0, 0, 0, 15));
-            for _i in 0..50 {
-                wincan.fill_rect(screen)?;
-                wincan.present();
-            }
+            fade_out(wincan, battle_draw)?;
 
             for item in battle_state.player_team.iter_mut() {
-                (*item).1 = 100.0;
+                item.hp = 100.0;
             }
             return Ok(Map::Overworld);
         }
@@ -502,6 +742,784 @@ pub fn enemy_battle_turn(
     Ok(Map::Battle)
 }
 
+/// Plays out one turn of a double battle, with every active monster acting in order of attack
+///
+/// * `picks` - The slot, move and target chosen for each of the player's monsters that can fight
+pub fn double_battle_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+    picks: &[(usize, usize, usize)],
+) -> Result<Map, String> {
+    // Each action is the side, the slot acting, the move and the foe targeted
+    let mut actions: Vec<(bool, usize, usize, usize)> =
+        picks.iter().map(|&(slot, m, target)| (true, slot, m, target)).collect();
+    let foes = alive_slots(battle_state, true);
+    let settings = search_settings(battle_draw);
+    for slot in alive_slots(battle_state, false) {
+        let (m, target) = ai::choose_double_action(
+            monsters_map,
+            battle_state,
+            slot,
+            &foes,
+            &settings,
+            &mut battle_draw.searches,
+            &mut battle_draw.rng,
+        );
+        actions.push((false, slot, m, target));
+    }
+    // Ties go to the player, whose actions come first
+    actions.sort_by_key(|&(side, slot, _, _)| {
+        std::cmp::Reverse(monster::active_stats(monsters_map, battle_state, side, slot).0)
+    });
+
+    for (side, slot, move_index, target) in actions {
+        if slot_health(battle_state, side, slot) <= 0.0 {
+            continue;
+        }
+        let foes = alive_slots(battle_state, !side);
+        if foes.is_empty() {
+            break;
+        }
+
+        let attacker = slot_name(battle_state, side, slot);
+        let attack = monsters_map[&attacker].moves[move_index];
+        let targets = if attack.spread {
+            foes
+        } else if foes.contains(&target) {
+            vec![target]
+        } else {
+            // The chosen foe already fainted, so hit the other one
+            vec![foes[0]]
+        };
+
+        pause(battle_draw, 300);
+        let f = format!("{} used {}!", &attacker, attack.name);
+        draw_battle(wincan, battle_draw, None, Some(f))?;
+
+        let before = stages(battle_state);
+        for t in targets {
+            let defender = slot_name(battle_state, !side, t);
+            let d = monster::damage_between(battle_draw.monsters, battle_state, attack, (slot, t), side);
+            let (d, hook_messages) = hooks::modify_damage(
+                battle_draw.items,
+                battle_draw.monsters,
+                battle_state,
+                attack,
+                side,
+                (slot, t),
+                d,
+            );
+            let result = monster::resolve_move_effect(
+                attack,
+                d,
+                slot_health(battle_state, side, slot),
+                slot_health(battle_state, !side, t),
+                &mut battle_draw.rng,
+            );
+            let hp = slot_health(battle_state, !side, t);
+            set_slot_health(battle_state, !side, t, hp - result.damage);
+            let dealt = hp - slot_health(battle_state, !side, t);
+            let hp = slot_health(battle_state, side, slot);
+            set_slot_health(battle_state, side, slot, hp + result.user_health_change);
+            sync_draw(battle_state, battle_draw);
+            // Stage changes are applied once the move has hit everyone, so they're logged then
+            log_move(battle_state, battle_draw, attack, &attacker, &defender, dealt, stages(battle_state));
+
+            match monster::str_effectiveness(attack, &monsters_map[&defender].monster_type) {
+                Some(s) => {
+                    pause(battle_draw, 300);
+                    draw_battle(wincan, battle_draw, None, Some(s))?;
+                }
+                None => {
+                    draw_battle(wincan, battle_draw, None, None)?;
+                }
+            }
+            if let Some(s) = monster::str_move_effect(attack, &result, &attacker, &defender) {
+                pause(battle_draw, 300);
+                announce(wincan, battle_draw, s)?;
+            }
+            show_hook_messages(wincan, battle_state, battle_draw, hook_messages)?;
+
+            if result.damage > 0.0 {
+                let messages = hooks::run_hooks(battle_draw.items, battle_draw.monsters, battle_state, hooks::Trigger::HealthDropped, !side, t);
+                show_hook_messages(wincan, battle_state, battle_draw, messages)?;
+            }
+
+            if slot_health(battle_state, !side, t) == 0.0 {
+                pause(battle_draw, 200);
+                let f = format!("{} KO'd {}!", &attacker, &defender);
+                draw_battle(wincan, battle_draw, None, Some(f))?;
+                battle_draw.transcript.log(Event::Faint(defender.clone()));
+                // The player picks their replacements from the monster menu once the turn is over
+                if side {
+                    replace_fainted(wincan, battle_state, battle_draw, t)?;
+                }
+            }
+        }
+        monster::apply_move_stages(battle_state, attack, side);
+        log_stage_changes(battle_state, battle_draw, before);
+        sync_draw(battle_state, battle_draw);
+    }
+
+    let won = alive_slots(battle_state, false).is_empty();
+    // The player may still have monsters on the bench to send out
+    let lost = battle_state.player_team.iter().all(|d| d.hp <= 0.0);
+    if won || lost {
+        pause(battle_draw, 200);
+        let f = if won {
+            String::from("You defeated the enemy!")
+        } else {
+            String::from("You blacked out!")
+        };
+        draw_battle(wincan, battle_draw, None, Some(f))?;
+        if won {
+            reward_winners(wincan, battle_state, battle_draw)?;
+        } else {
+            lose_money(wincan, battle_draw)?;
+        }
+        finish_battle(battle_draw, if won { transcript::PLAYER_WON } else { transcript::BLACKED_OUT });
+
+        // Fade out back to the overworld
+        fade_out(wincan, battle_draw)?;
+
+        if lost {
+            for item in battle_state.player_team.iter_mut() {
+                item.hp = 100.0;
+            }
+        }
+        return Ok(Map::Overworld);
+    }
+    Ok(Map::Battle)
+}
+
+/// Plays out a turn where the player's lead uses one of its moves
+///
+/// Whoever has the higher attack goes first, and the turn finishes with the end of turn effects.
+///
+/// * `choice` - Index of the move the player picked
+pub fn move_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+    choice: usize,
+) -> Result<Map, String> {
+    battle_draw.replay.record(replay::Action::Move(choice));
+    battle_state.player_turn = turn_calc(monsters_map, battle_state);
+    if battle_state.player_turn {
+        if let Map::Overworld = player_battle_turn(wincan, battle_state, battle_draw, monsters_map, choice)? {
+            return Ok(Map::Overworld);
+        }
+        if !battle_state.player_turn {
+            if let Map::Overworld = enemy_battle_turn(wincan, battle_state, battle_draw, monsters_map)? {
+                return Ok(Map::Overworld);
+            }
+        }
+    } else {
+        if let Map::Overworld = enemy_battle_turn(wincan, battle_state, battle_draw, monsters_map)? {
+            return Ok(Map::Overworld);
+        }
+        // A lead that was just knocked out doesn't get to attack
+        if battle_state.player_turn && battle_draw.player_health > 0.0 {
+            if let Map::Overworld = player_battle_turn(wincan, battle_state, battle_draw, monsters_map, choice)? {
+                return Ok(Map::Overworld);
+            }
+        }
+    }
+    end_of_turn(wincan, battle_state, battle_draw)?;
+    Ok(Map::Battle)
+}
+
+// Swaps two of the player's monsters in the monster menu, which doesn't use up a turn
+pub fn reorder_team(battle_state: &mut monster::BattleState, battle_draw: &mut Battle, a: usize, b: usize) {
+    battle_draw.replay.record(replay::Action::Reorder(a, b));
+    battle_state.player_team.swap(a, b);
+    if battle_draw.lead == a {
+        battle_draw.lead = b;
+    } else if battle_draw.lead == b {
+        battle_draw.lead = a;
+    }
+}
+
+/// Confirms the team order picked in the monster menu
+///
+/// A fainted lead is replaced for free, while switching out a healthy lead uses up the player's turn.
+/// Double battles only open the menu to replace fainted monsters, which is always free.
+pub fn confirm_team_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+) -> Result<Map, String> {
+    battle_draw.replay.record(replay::Action::ConfirmTeam);
+    if battle_state.double {
+        return confirm_double_replacements(wincan, battle_state, battle_draw);
+    }
+    // A fainted lead is being replaced rather than switched out
+    let replacing = battle_draw.player_health <= 0.0;
+
+    // Monsters that can still fight go to the front, following where the lead ends up
+    let mut order: Vec<usize> = (0..battle_state.player_team.len()).collect();
+    order.sort_by_key(|&i| battle_state.player_team[i].hp <= 0.0);
+    battle_state.player_team = order.iter().map(|&i| battle_state.player_team[i].clone()).collect();
+    let lead = order.iter().position(|&i| i == battle_draw.lead).unwrap_or(0);
+    battle_draw.lead = 0;
+    if lead == 0 {
+        return Ok(Map::Battle);
+    }
+    let switched_front = battle_state.player_team[0].clone();
+
+    if replacing {
+        battle_draw.transcript.log(Event::Switch {
+            withdrawn: battle_draw.player_name.clone(),
+            sent_out: switched_front.name.clone(),
+        });
+        reset_stages(battle_state, true);
+        battle_draw.player_name = switched_front.name.clone();
+        battle_draw.player_health = switched_front.hp;
+        let f = format!("Player sent out {}!", switched_front.name);
+        draw_battle(wincan, battle_draw, None, Some(f))?;
+        on_entry(wincan, battle_state, battle_draw, true, 0)?;
+        return Ok(Map::Battle);
+    }
+
+    let withdrawn = battle_draw.player_name.clone();
+    switched_in(wincan, battle_state, battle_draw, true, &withdrawn)?;
+    if let Map::Overworld = enemy_battle_turn(wincan, battle_state, battle_draw, monsters_map)? {
+        return Ok(Map::Overworld);
+    }
+    end_of_turn(wincan, battle_state, battle_draw)?;
+    Ok(Map::Battle)
+}
+
+/// Finishes the player's turn after an item from the bag was used
+///
+/// * `item` - The item that was used
+/// * `target` - Index into the player's team the item was used on
+/// * `outcome` - What `inventory::use_item` reported
+pub fn item_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+    item: &inventory::Item,
+    target: usize,
+    outcome: inventory::ItemUse,
+) -> Result<Map, String> {
+    battle_draw.replay.record(replay::Action::Item(item.name.clone(), target));
+    battle_draw.player_health = battle_state.player_team[0].hp;
+    announce(wincan, battle_draw, format!("Player used {}!", item.name))?;
+
+    match outcome {
+        inventory::ItemUse::Caught(f) => {
+            finish_battle(battle_draw, &f);
+            draw_battle(wincan, battle_draw, None, Some(f))?;
+
+            // Fade out back to the overworld
+            fade_out(wincan, battle_draw)?;
+            return Ok(Map::Overworld);
+        }
+        inventory::ItemUse::Used(f)
+        | inventory::ItemUse::Failed(f)
+        | inventory::ItemUse::Equipped(f, _) => {
+            announce(wincan, battle_draw, f)?;
+        }
+        // Stones and repels can't be used in battle, so there is nothing to show
+        inventory::ItemUse::Evolve(_) | inventory::ItemUse::Repel(_) => {}
+    }
+
+    if let Map::Overworld = enemy_battle_turn(wincan, battle_state, battle_draw, monsters_map)? {
+        return Ok(Map::Overworld);
+    }
+    end_of_turn(wincan, battle_state, battle_draw)?;
+    Ok(Map::Battle)
+}
+
+// Plays out a double battle turn once every monster the player has out has its pick
+pub fn double_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+    picks: &[(usize, usize, usize)],
+) -> Result<Map, String> {
+    battle_draw.replay.record(replay::Action::Double(picks.to_vec()));
+    if let Map::Overworld = double_battle_turn(wincan, battle_state, battle_draw, monsters_map, picks)? {
+        return Ok(Map::Overworld);
+    }
+    end_of_turn(wincan, battle_state, battle_draw)?;
+    Ok(Map::Battle)
+}
+
+// Every monster still standing gains experience for each foe beaten
+fn reward_winners(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+) -> Result<(), String> {
+    let amount = monster::EXP_PER_FOE * battle_state.enemy_team.len() as u32;
+    for i in 0..battle_state.player_team.len() {
+        if battle_state.player_team[i].hp <= 0.0 {
+            continue;
+        }
+        if monster::gain_exp(&mut battle_state.player_team[i], amount) > 0 {
+            battle_draw.level_ups.push(i);
+            let f = format!(
+                "{} grew to level {}!",
+                battle_state.player_team[i].name, battle_state.player_team[i].level
+            );
+            pause(battle_draw, 200);
+            announce(wincan, battle_draw, f)?;
+        }
+    }
+    if battle_draw.prize > 0 {
+        battle_draw.money += battle_draw.prize;
+        let f = format!("You got ${} for winning!", battle_draw.prize);
+        pause(battle_draw, 200);
+        announce(wincan, battle_draw, f)?;
+    }
+    Ok(())
+}
+
+fn lose_money(wincan: &mut sdl2::render::WindowCanvas, battle_draw: &mut Battle) -> Result<(), String> {
+    let lost = battle_draw.money / BLACKOUT_LOSS;
+    if lost == 0 {
+        return Ok(());
+    }
+    battle_draw.money -= lost;
+    let f = format!("You dropped ${} in the panic!", lost);
+    pause(battle_draw, 200);
+    announce(wincan, battle_draw, f)
+}
+
+// Records how the battle ended, and saves its replay unless one is being played back.
+// Where the replay went is noted in the transcript.
+pub fn finish_battle(battle_draw: &mut Battle, result: &str) {
+    battle_draw.transcript.finish(result);
+    // A prize only counts for the battle it was offered in
+    battle_draw.prize = 0;
+    if battle_draw.replaying {
+        return;
+    }
+    battle_draw.replay.result = Some(String::from(result));
+    battle_draw.replay.searches = battle_draw.searches.visited.clone();
+    let f = match battle_draw.replay.save() {
+        Ok(path) => format!("Saved replay to {}", path),
+        Err(e) => format!("Could not save replay: {}", e),
+    };
+    battle_draw.transcript.log(Event::Message(f));
+}
+
+// Enemy team indices of the bench members that can still fight in a double battle
+fn enemy_bench(battle_state: &monster::BattleState) -> Vec<usize> {
+    (DOUBLE_ACTIVE..battle_state.enemy_team.len())
+        .filter(|&i| battle_state.enemy_team[i].1 > 0.0)
+        .collect()
+}
+
+// Sends the trainer's pick off the bench into a fainted enemy slot, if there is anyone left
+fn replace_fainted(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    slot: usize,
+) -> Result<(), String> {
+    let bench = enemy_bench(battle_state);
+    let index = if battle_state.wild {
+        bench.first().copied()
+    } else {
+        let foes = alive_slots(battle_state, true);
+        ai::choose_double_replacement(
+            battle_draw.monsters,
+            battle_state,
+            &bench,
+            &foes,
+            REPLACEMENT_DEPTH,
+            &battle_draw.weights,
+        )
+    };
+    let index = match index {
+        Some(i) => i,
+        None => return Ok(()),
+    };
+
+    let withdrawn = slot_name(battle_state, false, slot);
+    battle_state.enemy_team.swap(slot, index);
+    reset_stages(battle_state, false);
+    let f = format!("Enemy sent out {}!", battle_state.enemy_team[slot].0);
+    battle_draw.transcript.log(Event::Switch {
+        withdrawn,
+        sent_out: slot_name(battle_state, false, slot),
+    });
+    sync_draw(battle_state, battle_draw);
+    pause(battle_draw, 200);
+    draw_battle(wincan, battle_draw, None, Some(f))?;
+    on_entry(wincan, battle_state, battle_draw, false, slot)
+}
+
+// Name and health the drawing shows for one of the player's active slots in a double battle
+fn shown_player_slot(battle_draw: &Battle, slot: usize) -> (String, f32) {
+    match (&battle_draw.double, slot) {
+        (Some(double), 1) => (double.player_name.clone(), double.player_health),
+        _ => (battle_draw.player_name.clone(), battle_draw.player_health),
+    }
+}
+
+/// Whether the player has to send out a monster from the monster menu before the battle goes on
+///
+/// In a single battle that's when the lead fainted, and in a double battle when either active
+/// monster fainted while a monster on the bench can still fight.
+pub fn needs_replacement(battle_state: &monster::BattleState, battle_draw: &Battle) -> bool {
+    if !battle_state.double {
+        return battle_draw.player_health <= 0.0;
+    }
+    let team = &battle_state.player_team;
+    let active = active_count(battle_state, true);
+    (0..active).any(|i| team[i].hp <= 0.0) && team.iter().skip(active).any(|d| d.hp > 0.0)
+}
+
+// Whether two of the player's monsters may swap places in the monster menu. While replacing in
+// a double battle, only a fainted active monster may swap, with a bench member that can fight.
+pub fn can_reorder(battle_state: &monster::BattleState, battle_draw: &Battle, a: usize, b: usize) -> bool {
+    if !battle_state.double || !needs_replacement(battle_state, battle_draw) {
+        return true;
+    }
+    let active = active_count(battle_state, true);
+    let (slot, bench) = if a < b { (a, b) } else { (b, a) };
+    slot < active
+        && bench >= active
+        && battle_state.player_team[slot].hp <= 0.0
+        && battle_state.player_team[bench].hp > 0.0
+}
+
+// Sends out the monsters the player swapped into fainted slots of a double battle, which is free
+fn confirm_double_replacements(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+) -> Result<Map, String> {
+    for slot in 0..active_count(battle_state, true) {
+        let (withdrawn, shown_health) = shown_player_slot(battle_draw, slot);
+        if shown_health > 0.0 || battle_state.player_team[slot].hp <= 0.0 {
+            continue;
+        }
+        battle_draw.transcript.log(Event::Switch {
+            withdrawn,
+            sent_out: slot_name(battle_state, true, slot),
+        });
+        reset_stages(battle_state, true);
+        sync_draw(battle_state, battle_draw);
+        let f = format!("Player sent out {}!", battle_state.player_team[slot].name);
+        draw_battle(wincan, battle_draw, None, Some(f))?;
+        on_entry(wincan, battle_state, battle_draw, true, slot)?;
+    }
+    // The next turn starts with the first monster that can fight
+    let first = alive_slots(battle_state, true);
+    if let Some(double) = &mut battle_draw.double {
+        double.acting = *first.first().unwrap_or(&0);
+    }
+    Ok(Map::Battle)
+}
+
+/// Announces a side's new lead after a switch, which clears that side's stat changes
+///
+/// * `player_side` - Whether the player or the enemy switched
+/// * `withdrawn` - Name of the monster that was taken out
+pub fn switched_in(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    player_side: bool,
+    withdrawn: &str,
+) -> Result<(), String> {
+    reset_stages(battle_state, player_side);
+    let f = if player_side {
+        battle_draw.player_name = battle_state.player_team[0].name.clone();
+        battle_draw.player_health = battle_state.player_team[0].hp;
+        format!("Player withdrew {}, sent out {}!", withdrawn, battle_draw.player_name)
+    } else {
+        battle_draw.enemy_name = battle_state.enemy_team[0].0.clone();
+        battle_draw.enemy_health = battle_state.enemy_team[0].1;
+        format!("Enemy withdrew {}, sent out {}!", withdrawn, battle_draw.enemy_name)
+    };
+    battle_draw.transcript.log(Event::Switch {
+        withdrawn: String::from(withdrawn),
+        sent_out: slot_name(battle_state, player_side, 0),
+    });
+    draw_battle(wincan, battle_draw, None, Some(f))?;
+    on_entry(wincan, battle_state, battle_draw, player_side, 0)
+}
+
+// Mirrors the active monsters of the battle state into the drawing
+pub fn sync_draw(battle_state: &monster::BattleState, battle_draw: &mut Battle) {
+    battle_draw.player_health = battle_state.player_team[0].hp;
+    battle_draw.enemy_health = battle_state.enemy_team[0].1;
+    battle_draw.weather = battle_state.weather.map(|d| d.0);
+    if battle_state.double {
+        battle_draw.player_name = battle_state.player_team[0].name.clone();
+        battle_draw.enemy_name = battle_state.enemy_team[0].0.clone();
+        if let Some(double) = &mut battle_draw.double {
+            if battle_state.player_team.len() > 1 {
+                double.player_name = battle_state.player_team[1].name.clone();
+                double.player_health = battle_state.player_team[1].hp;
+            }
+            if battle_state.enemy_team.len() > 1 {
+                double.enemy_name = battle_state.enemy_team[1].0.clone();
+                double.enemy_health = battle_state.enemy_team[1].1;
+            }
+        }
+    }
+}
+
+// Keeps the drawing in sync with any health the hooks restored, then shows their messages
+fn show_hook_messages(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &monster::BattleState,
+    battle_draw: &mut Battle,
+    messages: Vec<String>,
+) -> Result<(), String> {
+    sync_draw(battle_state, battle_draw);
+    for f in messages {
+        pause(battle_draw, 300);
+        announce(wincan, battle_draw, f)?;
+    }
+    Ok(())
+}
+
+// Shows a battle message and records it in the transcript
+fn announce(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_draw: &mut Battle,
+    message: String,
+) -> Result<(), String> {
+    battle_draw.transcript.log(Event::Message(message.clone()));
+    draw_battle(wincan, battle_draw, None, Some(message))
+}
+
+// Clears one side's stat changes whenever it sends out a monster. Double battles share the
+// stages of both active monsters on a side, so a replacement clears its partner's as well.
+fn reset_stages(battle_state: &mut monster::BattleState, player_side: bool) {
+    if player_side {
+        battle_state.self_attack_stages = 0;
+        battle_state.self_defense_stages = 0;
+    } else {
+        battle_state.opp_attack_stages = 0;
+        battle_state.opp_defense_stages = 0;
+    }
+}
+
+// Attack and defense stages of the player's side, then the enemy's
+fn stages(battle_state: &monster::BattleState) -> [i32; 4] {
+    [
+        battle_state.self_attack_stages,
+        battle_state.self_defense_stages,
+        battle_state.opp_attack_stages,
+        battle_state.opp_defense_stages,
+    ]
+}
+
+// Records every stage that changed since `before` against the monster it belongs to
+fn log_stage_changes(battle_state: &monster::BattleState, battle_draw: &mut Battle, before: [i32; 4]) {
+    let after = stages(battle_state);
+    for i in 0..4 {
+        if after[i] != before[i] {
+            battle_draw.transcript.log(Event::StatChange {
+                monster: slot_name(battle_state, i < 2, 0),
+                stat: String::from(if i % 2 == 0 { "attack" } else { "defense" }),
+                stages: after[i] - before[i],
+            });
+        }
+    }
+}
+
+fn log_move(
+    battle_state: &monster::BattleState,
+    battle_draw: &mut Battle,
+    attack: &monster::Move,
+    user: &str,
+    target: &str,
+    dealt: f32,
+    before: [i32; 4],
+) {
+    battle_draw.transcript.log(Event::Move {
+        user: String::from(user),
+        attack: attack.name.clone(),
+        target: String::from(target),
+        damage: dealt,
+        effectiveness: monster::effectiveness(attack, &battle_draw.monsters[target].monster_type),
+    });
+    log_stage_changes(battle_state, battle_draw, before);
+}
+
+pub fn on_entry(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    player_side: bool,
+    slot: usize,
+) -> Result<(), String> {
+    let messages = hooks::run_hooks(battle_draw.items, battle_draw.monsters, battle_state, hooks::Trigger::Entry, player_side, slot);
+    show_hook_messages(wincan, battle_state, battle_draw, messages)
+}
+
+// Number of monsters fighting at once on one side
+fn active_count(battle_state: &monster::BattleState, player_side: bool) -> usize {
+    let len = if player_side {
+        battle_state.player_team.len()
+    } else {
+        battle_state.enemy_team.len()
+    };
+    if battle_state.double {
+        len.min(DOUBLE_ACTIVE)
+    } else {
+        1
+    }
+}
+
+fn slot_name(battle_state: &monster::BattleState, player_side: bool, slot: usize) -> String {
+    if player_side {
+        battle_state.player_team[slot].name.clone()
+    } else {
+        battle_state.enemy_team[slot].0.clone()
+    }
+}
+
+fn slot_health(battle_state: &monster::BattleState, player_side: bool, slot: usize) -> f32 {
+    if player_side {
+        battle_state.player_team[slot].hp
+    } else {
+        battle_state.enemy_team[slot].1
+    }
+}
+
+fn set_slot_health(battle_state: &mut monster::BattleState, player_side: bool, slot: usize, health: f32) {
+    if player_side {
+        battle_state.player_team[slot].hp = health.clamp(0.0, 100.0);
+    } else {
+        battle_state.enemy_team[slot].1 = health.clamp(0.0, 100.0);
+    }
+}
+
+// Active slots on one side whose monster can still fight
+pub fn alive_slots(battle_state: &monster::BattleState, player_side: bool) -> Vec<usize> {
+    (0..active_count(battle_state, player_side))
+        .filter(|&i| slot_health(battle_state, player_side, i) > 0.0)
+        .collect()
+}
+
+// The leads enter at the start of a battle, with the seed every random roll in it comes from
+pub fn start_battle(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+    seed: u64,
+) -> Result<(), String> {
+    battle_draw.transcript = transcript::Transcript::start(battle_state);
+    battle_draw.rng = StdRng::seed_from_u64(seed);
+    battle_draw.searches = ai::SearchLog::new();
+    battle_draw.replay = replay::Replay::start(seed, battle_draw.difficulty, battle_draw.profile, battle_state);
+    battle_draw.level_ups.clear();
+    battle_draw.lead = 0;
+    battle_draw.player_name = battle_state.player_team[0].name.clone();
+    battle_draw.enemy_name = battle_state.enemy_team[0].0.clone();
+    sync_draw(battle_state, battle_draw);
+    for player_side in [true, false].iter() {
+        for slot in 0..active_count(battle_state, *player_side) {
+            on_entry(wincan, battle_state, battle_draw, *player_side, slot)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn end_of_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+) -> Result<(), String> {
+    weather_end_of_turn(wincan, battle_state, battle_draw)?;
+    for player_side in [true, false].iter() {
+        for slot in 0..active_count(battle_state, *player_side) {
+            let messages = hooks::run_hooks(battle_draw.items, battle_draw.monsters, battle_state, hooks::Trigger::EndOfTurn, *player_side, slot);
+            show_hook_messages(wincan, battle_state, battle_draw, messages)?;
+        }
+    }
+    battle_draw.transcript.next_turn();
+    Ok(())
+}
+
+// Deals the weather's damage to both sides and counts it down
+fn weather_end_of_turn(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut Battle,
+) -> Result<(), String> {
+    let (w, turns) = match battle_state.weather {
+        Some(current) => current,
+        None => return Ok(()),
+    };
+
+    let mut messages = Vec::new();
+    for player_side in [true, false].iter() {
+        for slot in 0..active_count(battle_state, *player_side) {
+            let name = slot_name(battle_state, *player_side, slot);
+            let hp = slot_health(battle_state, *player_side, slot);
+            // Like recoil, the weather never knocks out a monster
+            let damage = weather::residual_damage(w, &battle_draw.monsters[&name].monster_type)
+                .min(hp - 1.0)
+                .max(0.0);
+            if damage > 0.0 {
+                set_slot_health(battle_state, *player_side, slot, hp - damage);
+                messages.push(weather::damage_message(w, &name));
+            }
+        }
+    }
+    show_hook_messages(wincan, battle_state, battle_draw, messages)?;
+
+    if turns <= 1 {
+        battle_state.weather = None;
+        battle_draw.weather = None;
+        pause(battle_draw, 300);
+        announce(wincan, battle_draw, weather::end_message(w))?;
+    } else {
+        battle_state.weather = Some((w, turns - 1));
+    }
+    Ok(())
+}
+
+// How the trainer searches for its actions
+fn search_settings(battle_draw: &Battle) -> ai::SearchSettings {
+    ai::SearchSettings {
+        difficulty: battle_draw.difficulty,
+        profile: battle_draw.profile,
+        depth: ACTION_DEPTH,
+        budget: ai::Budget::Millis(ACTION_MILLIS),
+        hard_search: HARD_SEARCH,
+        weights: battle_draw.weights,
+    }
+}
+
+// Waits between battle messages, unless nothing is being drawn
+fn pause(battle_draw: &Battle, millis: u64) {
+    if !battle_draw.headless {
+        thread::sleep(Duration::from_millis(millis));
+    }
+}
+
+fn fade_out(wincan: &mut sdl2::render::WindowCanvas, battle_draw: &Battle) -> Result<(), String> {
+    if battle_draw.headless {
+        return Ok(());
+    }
+    let screen = Rect::new(0, 0, CAM_W, CAM_H);
+    wincan.set_draw_color(Color::RGBA(0, 0, 0, 15));
+    for _i in 0..50 {
+        wincan.fill_rect(screen)?;
+        wincan.present();
+    }
+    Ok(())
+}
+
 fn menu_health_bars(
     wincan: &mut sdl2::render::WindowCanvas,
     health: f32,
@@ -646,14 +1664,16 @@ pub fn draw_monster_menu(
         wincan.copy(&texture, None, text_rect)?;
     } else {
         // Draw focused monster image
+        let mon = &player_team[choice];
         wincan.copy(
-            &battle_init.monster_text_map[&player_team[choice].0],
+            &battle_init.monster_text_map[&mon.name],
             None,
             Rect::new(100 + s, 80 + s, 350 - 2 * s as u32, 350 - 2 * s as u32),
         )?;
+        let f = format!("{}  Lv. {}", mon.name, mon.level);
         let surface = battle_init
             .font
-            .render(&player_team[choice].0)
+            .render(&f)
             .blended(Color::RGB(0xbd, 0xcd, 0xde))
             .map_err(|e| e.to_string())?;
         let texture = texture_creator
@@ -664,11 +1684,13 @@ pub fn draw_monster_menu(
         let text_rect = center(fit(text_rect, width, height), 330, 50);
         wincan.copy(&texture, None, text_rect)?;
 
-        // Add stats
+        // Add the stats this monster ends up with, and the nature that shaped them
+        let (attack, defense) = monster::instance_stats(&battle_init.monsters[&mon.name], mon);
         let f = format!(
-            "Attack: {} | Defense: {}",
-            &battle_init.monsters[&player_team[choice].0].attack_stat,
-            &battle_init.monsters[&player_team[choice].0].defense_stat
+            "Attack: {} | Defense: {} | {}",
+            attack,
+            defense,
+            monster::nature_name(mon.nature)
         );
         let surface = battle_init
             .font
@@ -685,7 +1707,7 @@ pub fn draw_monster_menu(
 
         // Add each move
         for i in 0..4 {
-            let attack_name = &battle_init.monsters[&player_team[choice].0].moves[i].name;
+            let attack_name = &battle_init.monsters[&mon.name].moves[i].name;
             let texture = &battle_init.attack_map[attack_name];
 
             // Add the names of each attack
@@ -705,14 +1727,19 @@ pub fn draw_monster_menu(
     for index in 0..6 {
         let item = rects[index];
         if index < player_team.len() {
-            let health = player_team[index].1;
-            let name_texture = &battle_init.name_text_map[&player_team[index].0].0;
+            let health = player_team[index].hp;
+            let name_texture = &battle_init.name_text_map[&player_team[index].name].0;
             let TextureQuery { width, height, .. } = name_texture.query();
             let text_rect = Rect::new(item.x + 5, item.y + 5, item.width() - 10, 40);
             let text_rect = center(fit(text_rect, width, height), 290, 40);
 
             wincan.copy(name_texture, None, text_rect)?;
 
+            if let Some(held) = &player_team[index].held_item {
+                let f = format!("Holding {}", held);
+                draw_text(wincan, battle_init.font, &f, Color::RGB(0xbd, 0xcd, 0xde), Rect::new(item.x + 10, item.y + 45, 280, 22))?;
+            }
+
             wincan.set_draw_color(Color::BLACK);
             wincan.fill_rect(Rect::new(item.x + 10, item.y + 105 - 10 - 25, 280, 25))?;
             if health > 0.0 {
@@ -749,21 +1776,173 @@ pub fn draw_monster_menu(
     Ok(())
 }
 
-pub fn verify_team(v: &Vec<(String, f32)>) -> Vec<(String, f32)>{
-    let mut alive : Vec<(String, f32)> = Vec::new();
-    let mut dead : Vec<(String, f32)> = Vec::new();
-    for item in v.iter() {
-      if item.1 > 0.0 {
-        alive.push(item.clone());
-      }
-      else {
-        dead.push(item.clone());
-      }
+pub fn draw_text(
+    wincan: &mut sdl2::render::WindowCanvas,
+    font: &sdl2::ttf::Font,
+    text: &str,
+    color: Color,
+    r: Rect,
+) -> Result<(), String> {
+    let texture_creator = wincan.texture_creator();
+    let surface = font
+        .render(text)
+        .blended(color)
+        .map_err(|e| e.to_string())?;
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+    let TextureQuery { width, height, .. } = texture.query();
+    let text_rect = center(fit(r, width, height), r.width(), r.height());
+    wincan.copy(&texture, None, text_rect)?;
+    Ok(())
+}
+
+pub fn draw_bag_menu(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &Battle,
+    battle_state: &monster::BattleState,
+    inventory: &inventory::Inventory,
+    choice: usize,
+    target: Option<usize>,
+    message: Option<String>,
+) -> Result<(), String> {
+    let player_team = &battle_state.player_team;
+    let outline_size = 5;
+
+    // Create menu background
+    wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
+    wincan.fill_rect(Rect::new(100, 80, 540, 560))?;
+    wincan.fill_rect(Rect::new(660, 80, 520, 560))?;
+
+    draw_text(wincan, battle_init.font, "Bag", Color::RGB(0xbd, 0xcd, 0xde), Rect::new(120, 90, 500, 40))?;
+
+    // Draw each item slot, outlining the one currently selected
+    if inventory.items.is_empty() {
+        draw_text(wincan, battle_init.font, "Your bag is empty", Color::RGB(0xbd, 0xcd, 0xde), Rect::new(120, 140, 500, 38))?;
+    }
+    // Scroll the list once the selection goes past the last visible slot
+    let first = if choice >= BAG_ROWS { choice + 1 - BAG_ROWS } else { 0 };
+    for (index, (name, count)) in inventory.items.iter().enumerate().skip(first).take(BAG_ROWS) {
+        let r = Rect::new(120, 140 + ((index - first) as i32) * 44, 500, 38);
+        if index == choice {
+            wincan.set_draw_color(Color::RGB(0xf6, 0x52, 0x41));
+            wincan.fill_rect(Rect::new(
+                r.x() - outline_size,
+                r.y() - outline_size,
+                r.width() + (2 * outline_size) as u32,
+                r.height() + (2 * outline_size) as u32,
+            ))?;
+        }
+        wincan.set_draw_color(Color::RGB(0x39, 0x7B, 0xB4));
+        wincan.fill_rect(r)?;
+
+        let f = format!("{} x{}", name, count);
+        draw_text(wincan, battle_init.font, &f, Color::BLACK, r)?;
+    }
+
+    // Describe the selected item, or show the result of the last use
+    let f = match message {
+        Some(text) => text,
+        None => match inventory.items.get(choice) {
+            Some((name, _)) => battle_init.items[name].description.clone(),
+            None => String::from(""),
+        },
+    };
+    if !f.is_empty() {
+        draw_text(wincan, battle_init.font, &f, Color::RGB(0xbd, 0xcd, 0xde), Rect::new(120, 590, 500, 35))?;
+    }
+
+    // Draw the team so a target can be chosen
+    for (index, mon) in player_team.iter().enumerate() {
+        let r = Rect::new(680, 100 + (index as i32) * 90, 480, 80);
+        match target {
+            Some(t) if t == index => {
+                wincan.set_draw_color(Color::YELLOW);
+                wincan.fill_rect(Rect::new(
+                    r.x() - outline_size,
+                    r.y() - outline_size,
+                    r.width() + (2 * outline_size) as u32,
+                    r.height() + (2 * outline_size) as u32,
+                ))?;
+            }
+            _ => {}
+        }
+        wincan.set_draw_color(Color::RGB(0x39, 0x7B, 0xB4));
+        wincan.fill_rect(r)?;
+
+        draw_text(wincan, battle_init.font, &mon.name, Color::BLACK, Rect::new(r.x() + 10, r.y() + 5, 460, 35))?;
+
+        wincan.set_draw_color(Color::BLACK);
+        wincan.fill_rect(Rect::new(r.x() + 10, r.y() + 45, 460, 25))?;
+        if mon.hp > 0.0 {
+            menu_health_bars(
+                wincan,
+                mon.hp,
+                r.x() + 10,
+                r.y() + 45,
+                (mon.hp * 460.0 / 100.0).ceil() as u32,
+                25,
+            )?;
+        } else {
+            draw_text(wincan, battle_init.font, "FAINTED", Color::WHITE, Rect::new(r.x() + 10, r.y() + 45, 460, 25))?;
+        }
+    }
+
+    wincan.present();
+    Ok(())
+}
+
+pub fn draw_transcript(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &Battle,
+    scroll: usize,
+    message: Option<String>,
+) -> Result<(), String> {
+    let lines = battle_init.transcript.lines();
+
+    wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
+    wincan.fill_rect(Rect::new(100, 80, 1080, 560))?;
+    draw_text(wincan, battle_init.font, "Battle Log", Color::RGB(0xbd, 0xcd, 0xde), Rect::new(120, 90, 1040, 40))?;
+
+    for (index, line) in lines.iter().skip(scroll).take(LOG_ROWS).enumerate() {
+        let r = Rect::new(120, 140 + (index as i32) * 30, 1040, 28);
+        wincan.set_draw_color(Color::RGB(0x39, 0x7B, 0xB4));
+        wincan.fill_rect(r)?;
+        draw_left_text(wincan, battle_init.font, line, Color::BLACK, r)?;
     }
-    alive.append(&mut dead);
-    return alive;
+
+    let f = match message {
+        Some(text) => text,
+        None => String::from("W/S to scroll, E to export, Backspace to close"),
+    };
+    draw_text(wincan, battle_init.font, &f, Color::RGB(0xbd, 0xcd, 0xde), Rect::new(120, 595, 1040, 35))?;
+
+    wincan.present();
+    Ok(())
+}
+
+// Like draw_text, but lined up against the left edge of the rect
+fn draw_left_text(
+    wincan: &mut sdl2::render::WindowCanvas,
+    font: &sdl2::ttf::Font,
+    text: &str,
+    color: Color,
+    r: Rect,
+) -> Result<(), String> {
+    let texture_creator = wincan.texture_creator();
+    let surface = font
+        .render(text)
+        .blended(color)
+        .map_err(|e| e.to_string())?;
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+    let TextureQuery { width, height, .. } = texture.query();
+    let text_rect = fit(Rect::new(r.x() + 5, r.y(), r.width() - 10, r.height()), width, height);
+    wincan.copy(&texture, None, text_rect)?;
+    Ok(())
 }
 
 pub fn turn_calc<'a>(monsters: &HashMap<String, monster::Monster>, battle_state: &monster::BattleState) -> bool {
-    return monsters[&battle_state.player_team[0].name].attack_stat >= monsters[&battle_state.enemy_team[0].0].attack_stat;
+    monster::active_stats(monsters, battle_state, true, 0).0 >= monster::active_stats(monsters, battle_state, false, 0).0
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+// Plays AI policies against each other and prints how each pairing did, without opening a window
+//
+// Arguments are the policies, mixed with `--battles <n>`, `--seed <n>`, `--trainer-teams` to
+// battle with the trainers' teams and `--csv <file>` to also save the results
+
+use sdl_rust::{ability, ai, monster, tournament};
+
+// Seeded battles each pair of policies plays, each twice
+const TOURNAMENT_BATTLES: u32 = 500;
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut policies = Vec::new();
+    let mut battles = TOURNAMENT_BATTLES;
+    let mut seed = 0;
+    let mut use_trainers = false;
+    let mut csv = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--battles" => battles = args.next().and_then(|d| d.parse::<u32>().ok()).ok_or("--battles needs a number")?,
+            "--seed" => seed = args.next().and_then(|d| d.parse::<u64>().ok()).ok_or("--seed needs a number")?,
+            "--trainer-teams" => use_trainers = true,
+            "--csv" => csv = Some(args.next().ok_or("--csv needs a file")?),
+            _ => policies.push(tournament::parse_policy(arg)?),
+        }
+    }
+    if policies.len() < 2 {
+        return Err(String::from("Need at least two policies"));
+    }
+
+    let moves_map = monster::load_moves();
+    let abilities_map = ability::load_abilities();
+    let monsters_map = monster::load_mons(&moves_map, &abilities_map);
+    let ai_weights = ai::load_weights();
+    let teams = if use_trainers { tournament::trainer_teams() } else { Vec::new() };
+
+    let results = tournament::run_tournament(&monsters_map, &policies, &teams, battles, seed, &ai_weights["default"]);
+    tournament::print_table(&results);
+    if let Some(path) = csv {
+        tournament::write_csv(&results, path)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(e) = run(&args[1..]) {
+        println!("Failed to run tournament: {}", e);
+        std::process::exit(1);
+    }
+}
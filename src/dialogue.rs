@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::TextureQuery;
+
+use crate::flags::{self, Condition, Progress};
+
+// The text box along the bottom of the screen
+const BOX: (i32, i32, u32, u32) = (100, 540, 1080, 150);
+const PADDING: i32 = 20;
+const SPEAKER_HEIGHT: u32 = 30;
+const LINE_HEIGHT: u32 = 36;
+const LINES_PER_PAGE: usize = 2;
+// Room on the right of the box for the yes/no choice
+const CHOICE_WIDTH: u32 = 120;
+
+// Every conversation starts from this node
+const START_NODE: &str = "start";
+// How close the player has to stand to talk to someone
+const REACH: i32 = 4;
+
+// Townsfolk who don't battle but have something to say
+pub struct Npc {
+    pub name: String,
+    pub rect: Rect,
+    pub conversation: String,
+}
+
+impl Npc {
+    pub fn reach(&self) -> Rect {
+        Rect::new(
+            self.rect.x() - REACH,
+            self.rect.y() - REACH,
+            self.rect.width() + 2 * REACH as u32,
+            self.rect.height() + 2 * REACH as u32,
+        )
+    }
+}
+
+pub fn load_npcs() -> Vec<Npc> {
+    let reader = BufReader::new(File::open("./data/npcs.txt").expect("Cannot open npcs.txt"));
+    let mut npcs = Vec::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        npcs.push(Npc {
+            name: String::from(v[0]),
+            rect: Rect::new(
+                v[1].parse::<i32>().expect("NPC x should be a number"),
+                v[2].parse::<i32>().expect("NPC y should be a number"),
+                32,
+                32,
+            ),
+            conversation: String::from(v[3]),
+        });
+    }
+    npcs
+}
+
+pub struct Node {
+    pub name: String,
+    pub condition: Condition,
+    // Where the conversation goes afterwards, if it isn't over
+    pub next: Option<String>,
+    // Where a yes or no answer leads, if the node asks a question
+    pub choice: Option<(String, String)>,
+    pub text: String,
+}
+
+fn optional(v: &str) -> Option<String> {
+    if v.is_empty() {
+        None
+    } else {
+        Some(String::from(v))
+    }
+}
+
+// Maps each conversation onto its nodes; a node may be listed more than once with different conditions
+pub fn load_dialogue() -> HashMap<String, Vec<Node>> {
+    let reader = BufReader::new(File::open("./data/dialogue.txt").expect("Cannot open dialogue.txt"));
+    let mut conversations: HashMap<String, Vec<Node>> = HashMap::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        let choice = match (optional(v[4]), optional(v[5])) {
+            (Some(yes), Some(no)) => Some((yes, no)),
+            _ => None,
+        };
+        conversations.entry(String::from(v[0])).or_default().push(Node {
+            name: String::from(v[1]),
+            condition: flags::parse_condition(v[2]),
+            next: optional(v[3]),
+            choice,
+            // The text is the last column so it can have commas in it
+            text: v[6..].join(","),
+        });
+    }
+    conversations
+}
+
+// The first version of the node whose condition holds
+fn find_node<'a>(nodes: &'a [Node], name: &str, progress: &Progress) -> Option<&'a Node> {
+    nodes.iter().find(|d| d.name == name && d.condition.holds(progress))
+}
+
+// Splits text into lines that fit the box, breaking between words
+//
+// `measure` gives the width some text is drawn at.
+fn wrap(text: &str, width: u32, measure: impl Fn(&str) -> Result<f32, String>) -> Result<Vec<String>, String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            String::from(word)
+        } else {
+            format!("{} {}", line, word)
+        };
+        if measure(&candidate)? > width as f32 && !line.is_empty() {
+            lines.push(line);
+            line = String::from(word);
+        } else {
+            line = candidate;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+fn paginate(font: &sdl2::ttf::Font, text: &str, width: u32) -> Result<Vec<Vec<String>>, String> {
+    // Text is drawn at the line height, whatever size the font was loaded at
+    let scale = LINE_HEIGHT as f32 / font.height() as f32;
+    let lines = wrap(text, width, |d| {
+        let (w, _) = font.size_of(d).map_err(|e| e.to_string())?;
+        Ok(w as f32 * scale)
+    })?;
+    Ok(pages(lines))
+}
+
+// Groups wrapped lines into the pages of the box, with one empty page for no text
+fn pages(lines: Vec<String>) -> Vec<Vec<String>> {
+    if lines.is_empty() {
+        return vec![Vec::new()];
+    }
+    lines.chunks(LINES_PER_PAGE).map(|d| d.to_vec()).collect()
+}
+
+// An open text box, either a single line or a walk through a conversation
+pub struct Dialogue {
+    pub speaker: String,
+    conversation: Option<String>,
+    pages: Vec<Vec<String>>,
+    page: usize,
+    next: Option<String>,
+    choice: Option<(String, String)>,
+    // Whether "Yes" is highlighted
+    pub yes: bool,
+}
+
+impl Dialogue {
+    // A box that says one thing and closes
+    pub fn new(font: &sdl2::ttf::Font, speaker: &str, text: &str) -> Result<Dialogue, String> {
+        Ok(Dialogue {
+            speaker: String::from(speaker),
+            conversation: None,
+            pages: paginate(font, text, BOX.2 - 2 * PADDING as u32)?,
+            page: 0,
+            next: None,
+            choice: None,
+            yes: true,
+        })
+    }
+
+    /// Opens a conversation from the dialogue data
+    ///
+    /// Returns None if the conversation doesn't exist or none of its opening lines apply.
+    ///
+    /// * `conversations` - Every conversation, from `load_dialogue`
+    /// * `conversation` - Which conversation to start
+    /// * `speaker` - The name shown above the text
+    /// * `progress` - Decides which version of each node is used
+    pub fn start(
+        font: &sdl2::ttf::Font,
+        conversations: &HashMap<String, Vec<Node>>,
+        conversation: &str,
+        speaker: &str,
+        progress: &Progress,
+    ) -> Result<Option<Dialogue>, String> {
+        let node = match conversations.get(conversation) {
+            Some(nodes) => find_node(nodes, START_NODE, progress),
+            None => None,
+        };
+        match node {
+            Some(node) => {
+                let mut dialogue = Dialogue::new(font, speaker, "")?;
+                dialogue.conversation = Some(String::from(conversation));
+                dialogue.enter(font, node)?;
+                Ok(Some(dialogue))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn enter(&mut self, font: &sdl2::ttf::Font, node: &Node) -> Result<(), String> {
+        let width = if node.choice.is_some() {
+            BOX.2 - 2 * PADDING as u32 - CHOICE_WIDTH
+        } else {
+            BOX.2 - 2 * PADDING as u32
+        };
+        self.pages = paginate(font, &node.text, width)?;
+        self.page = 0;
+        self.next = node.next.clone();
+        self.choice = node.choice.clone();
+        self.yes = true;
+        Ok(())
+    }
+
+    // The yes/no choice only shows once the question has been read in full
+    pub fn asking(&self) -> bool {
+        self.choice.is_some() && self.page + 1 == self.pages.len()
+    }
+
+    pub fn toggle(&mut self) {
+        if self.asking() {
+            self.yes = !self.yes;
+        }
+    }
+
+    /// Moves on to the next page, or the next node once this one has been read
+    ///
+    /// Returns false once the conversation is over.
+    pub fn advance(
+        &mut self,
+        font: &sdl2::ttf::Font,
+        conversations: &HashMap<String, Vec<Node>>,
+        progress: &Progress,
+    ) -> Result<bool, String> {
+        if self.page + 1 < self.pages.len() {
+            self.page += 1;
+            return Ok(true);
+        }
+        let next = match &self.choice {
+            Some((yes, no)) => Some(if self.yes { yes.clone() } else { no.clone() }),
+            None => self.next.clone(),
+        };
+        let node = match (&self.conversation, next) {
+            (Some(conversation), Some(next)) => match conversations.get(conversation) {
+                Some(nodes) => find_node(nodes, &next, progress),
+                None => None,
+            },
+            _ => None,
+        };
+        match node {
+            Some(node) => {
+                self.enter(font, node)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn draw(&self, wincan: &mut sdl2::render::WindowCanvas, font: &sdl2::ttf::Font) -> Result<(), String> {
+        let (x, y, w, h) = BOX;
+        wincan.set_draw_color(Color::RGB(0x40, 0x40, 0x80));
+        wincan.fill_rect(Rect::new(x - 4, y - 4, w + 8, h + 8))?;
+        wincan.set_draw_color(Color::RGB(0xf0, 0xf0, 0xf0));
+        wincan.fill_rect(Rect::new(x, y, w, h))?;
+
+        let mut line_y = y + PADDING / 2;
+        draw_line(wincan, font, &self.speaker, Color::RGB(0x40, 0x40, 0x80), x + PADDING, line_y, SPEAKER_HEIGHT)?;
+        line_y += SPEAKER_HEIGHT as i32 + 8;
+        for line in self.pages[self.page].iter() {
+            draw_line(wincan, font, line, Color::RGB(0, 0, 0), x + PADDING, line_y, LINE_HEIGHT)?;
+            line_y += LINE_HEIGHT as i32;
+        }
+
+        let right = x + w as i32 - PADDING;
+        if self.asking() {
+            let choice_x = right - CHOICE_WIDTH as i32 + 20;
+            let (yes, no) = if self.yes { ("> Yes", "  No") } else { ("  Yes", "> No") };
+            draw_line(wincan, font, yes, Color::RGB(0, 0, 0), choice_x, y + 40, LINE_HEIGHT)?;
+            draw_line(wincan, font, no, Color::RGB(0, 0, 0), choice_x, y + 40 + LINE_HEIGHT as i32, LINE_HEIGHT)?;
+        } else if self.page + 1 < self.pages.len() {
+            // More to read on the next page
+            draw_line(wincan, font, "...", Color::RGB(0x40, 0x40, 0x80), right - 40, y + h as i32 - 40, 30)?;
+        }
+        Ok(())
+    }
+}
+
+// Draws left-aligned text at the given height, keeping the font's proportions
+fn draw_line(
+    wincan: &mut sdl2::render::WindowCanvas,
+    font: &sdl2::ttf::Font,
+    text: &str,
+    color: Color,
+    x: i32,
+    y: i32,
+    height: u32,
+) -> Result<(), String> {
+    if text.is_empty() {
+        return Ok(());
+    }
+    let texture_creator = wincan.texture_creator();
+    let surface = font.render(text).blended(color).map_err(|e| e.to_string())?;
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+    let TextureQuery { width, height: text_height, .. } = texture.query();
+    let scaled_width = (width as f32 * height as f32 / text_height as f32) as u32;
+    wincan.copy(&texture, None, Rect::new(x, y, scaled_width, height))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every character is one unit wide
+    fn by_chars(text: &str) -> Result<f32, String> {
+        Ok(text.chars().count() as f32)
+    }
+
+    fn strings(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|d| String::from(*d)).collect()
+    }
+
+    #[test]
+    fn text_wraps_between_words() {
+        let lines = wrap("the quick brown fox jumps over", 10, by_chars).unwrap();
+        assert_eq!(lines, strings(&["the quick", "brown fox", "jumps over"]));
+        // A word too long for the box still gets a line to itself
+        let lines = wrap("a extraordinarily long word", 10, by_chars).unwrap();
+        assert_eq!(lines, strings(&["a", "extraordinarily", "long word"]));
+        // Runs of spaces don't make empty lines
+        assert_eq!(wrap("  hi   there ", 20, by_chars).unwrap(), strings(&["hi there"]));
+        assert!(wrap("", 10, by_chars).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lines_fill_pages_in_order() {
+        let lines = strings(&["one", "two", "three", "four", "five"]);
+        let paged = pages(lines);
+        assert_eq!(paged.len(), 3);
+        assert_eq!(paged[0], strings(&["one", "two"]));
+        assert_eq!(paged[2], strings(&["five"]));
+        assert_eq!(pages(Vec::new()), vec![Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn nodes_take_the_first_version_that_holds() {
+        let node = |condition: &str, text: &str| Node {
+            name: String::from(START_NODE),
+            condition: flags::parse_condition(condition),
+            next: None,
+            choice: None,
+            text: String::from(text),
+        };
+        let nodes = vec![node("talked:Old Man", "Back again?"), node("always", "Hello!")];
+        let team = Vec::new();
+        let mut game_flags = flags::Flags::new();
+
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert_eq!(find_node(&nodes, START_NODE, &progress).unwrap().text, "Hello!");
+        assert!(find_node(&nodes, "missing", &progress).is_none());
+
+        game_flags.set(&flags::talked("Old Man"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert_eq!(find_node(&nodes, START_NODE, &progress).unwrap().text, "Back again?");
+    }
+}
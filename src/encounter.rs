@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+use crate::flags;
+
+// Pixels the player walks to take one step
+const STEP_LENGTH: u32 = 16;
+// Steps after a battle before another wild monster can appear
+const GRACE_STEPS: u32 = 10;
+// Percent chance per step in zones without a rate of their own
+pub const DEFAULT_RATE: u32 = 10;
+
+// One row of a zone's encounter table
+pub struct Encounter {
+    pub monster: String,
+    // Chance of this monster relative to the rest of the zone's table
+    pub weight: u32,
+    pub min_level: u32,
+    pub max_level: u32,
+    // The monster only shows up once this holds
+    pub condition: flags::Condition,
+}
+
+// Maps each zone from `overworld::mark_rectangles` onto its encounter table
+pub fn load_encounters() -> HashMap<String, Vec<Encounter>> {
+    let reader = BufReader::new(File::open("./data/encounters.txt").expect("Cannot open encounters.txt"));
+    let mut encounters: HashMap<String, Vec<Encounter>> = HashMap::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        let encounter = Encounter {
+            monster: String::from(v[1]),
+            weight: v[2].parse::<u32>().unwrap(),
+            min_level: v[3].parse::<u32>().unwrap(),
+            max_level: v[4].parse::<u32>().unwrap(),
+            condition: flags::parse_condition(v.get(5).unwrap_or(&"always")),
+        };
+        encounters.entry(String::from(v[0])).or_default().push(encounter);
+    }
+    encounters
+}
+
+/// Picks the species and level of a wild monster from a zone's table
+///
+/// Returns None if the table has nothing to pick from.
+///
+/// * `table` - The zone's encounter table
+/// * `progress` - Leaves out monsters whose condition doesn't hold yet
+/// * `rng` - Rolls the species and level
+pub fn roll_encounter(table: &[Encounter], progress: &flags::Progress, rng: &mut impl Rng) -> Option<(String, u32)> {
+    let available: Vec<&Encounter> = table.iter().filter(|d| d.condition.holds(progress)).collect();
+    let weights = WeightedIndex::new(available.iter().map(|d| d.weight)).ok()?;
+    let encounter = available[weights.sample(rng)];
+    let level = rng.gen_range(encounter.min_level..=encounter.max_level);
+    Some((encounter.monster.clone(), level))
+}
+
+// Maps each zone onto the percent chance of an encounter per step taken in it
+pub fn load_rates() -> HashMap<String, u32> {
+    let reader = BufReader::new(File::open("./data/zones.txt").expect("Cannot open zones.txt"));
+    let mut rates = HashMap::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+        rates.insert(String::from(v[0]), v[1].parse::<u32>().unwrap());
+    }
+    rates
+}
+
+// Tracks the steps the player takes through the spawn zones
+pub struct Steps {
+    // Pixels walked since the last full step
+    distance: u32,
+    grace: u32,
+    repel: u32,
+}
+
+impl Steps {
+    pub fn new() -> Steps {
+        Steps {
+            distance: 0,
+            grace: 0,
+            repel: 0,
+        }
+    }
+
+    /// Counts distance walked inside a zone, returning true when a wild monster appears
+    ///
+    /// * `distance` - Pixels walked this frame
+    /// * `rate` - The zone's percent chance of an encounter per step
+    /// * `rng` - Rolls for an encounter on each step
+    pub fn walk(&mut self, distance: u32, rate: u32, rng: &mut impl Rng) -> bool {
+        self.distance += distance;
+        while self.distance >= STEP_LENGTH {
+            self.distance -= STEP_LENGTH;
+            if self.grace > 0 {
+                self.grace -= 1;
+            } else if self.repel > 0 {
+                self.repel -= 1;
+            } else if rng.gen_range(0..100) < rate {
+                self.distance = 0;
+                return true;
+            }
+        }
+        false
+    }
+
+    // Gives the player some room to walk away once a battle is over
+    pub fn start_grace(&mut self) {
+        self.grace = GRACE_STEPS;
+        self.distance = 0;
+    }
+
+    pub fn repel(&mut self, steps: u32) {
+        self.repel = steps;
+    }
+}
+
+impl Default for Steps {
+    fn default() -> Steps {
+        Steps::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn encounter(monster: &str, weight: u32, levels: (u32, u32), condition: &str) -> Encounter {
+        Encounter {
+            monster: String::from(monster),
+            weight,
+            min_level: levels.0,
+            max_level: levels.1,
+            condition: flags::parse_condition(condition),
+        }
+    }
+
+    #[test]
+    fn encounters_follow_their_weights_and_levels() {
+        let table = vec![
+            encounter("melon-mon", 75, (3, 5), "always"),
+            encounter("Reusoon", 25, (8, 8), "always"),
+        ];
+        let team = Vec::new();
+        let game_flags = flags::Flags::new();
+        let progress = flags::Progress { badges: 0, team: &team, flags: &game_flags };
+        let mut rng = StdRng::seed_from_u64(38);
+
+        let mut melons = 0;
+        for _ in 0..1000 {
+            let (monster, level) = roll_encounter(&table, &progress, &mut rng).unwrap();
+            if monster == "melon-mon" {
+                assert!((3..=5).contains(&level));
+                melons += 1;
+            } else {
+                assert_eq!(level, 8);
+            }
+        }
+        assert!((700..800).contains(&melons), "{} of 1000 were melon-mon", melons);
+    }
+
+    #[test]
+    fn encounters_wait_for_their_conditions() {
+        let table = vec![
+            encounter("melon-mon", 1, (3, 3), "!badges>=1"),
+            encounter("orcaaa", 1, (5, 5), "badges>=1"),
+        ];
+        let team = Vec::new();
+        let game_flags = flags::Flags::new();
+        let mut rng = StdRng::seed_from_u64(38);
+
+        let early = flags::Progress { badges: 0, team: &team, flags: &game_flags };
+        let late = flags::Progress { badges: 1, team: &team, flags: &game_flags };
+        for _ in 0..20 {
+            assert_eq!(roll_encounter(&table, &early, &mut rng).unwrap().0, "melon-mon");
+            assert_eq!(roll_encounter(&table, &late, &mut rng).unwrap().0, "orcaaa");
+        }
+
+        let locked = vec![encounter("orcaaa", 1, (5, 5), "beaten:Ada")];
+        assert!(roll_encounter(&locked, &early, &mut rng).is_none());
+        assert!(roll_encounter(&[], &early, &mut rng).is_none());
+    }
+
+    #[test]
+    fn steps_wait_for_a_full_step() {
+        let mut steps = Steps::new();
+        let mut rng = StdRng::seed_from_u64(39);
+        for _ in 0..STEP_LENGTH - 1 {
+            assert!(!steps.walk(1, 100, &mut rng));
+        }
+        assert!(steps.walk(1, 100, &mut rng));
+        // The step that found a monster doesn't carry over
+        assert!(!steps.walk(STEP_LENGTH - 1, 100, &mut rng));
+        assert!(!steps.walk(STEP_LENGTH * 50, 0, &mut rng));
+    }
+
+    #[test]
+    fn grace_and_repels_skip_steps() {
+        let mut steps = Steps::new();
+        let mut rng = StdRng::seed_from_u64(39);
+        steps.start_grace();
+        steps.repel(5);
+        for _ in 0..GRACE_STEPS + 5 {
+            assert!(!steps.walk(STEP_LENGTH, 100, &mut rng));
+        }
+        assert!(steps.walk(STEP_LENGTH, 100, &mut rng));
+
+        // Grace also forgets a half-taken step
+        steps.walk(STEP_LENGTH / 2, 100, &mut rng);
+        steps.start_grace();
+        assert!(!steps.walk(STEP_LENGTH * GRACE_STEPS + STEP_LENGTH / 2, 100, &mut rng));
+        assert!(steps.walk(STEP_LENGTH / 2, 100, &mut rng));
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::battle;
+use crate::ActiveMons;
+
+pub enum Trigger {
+    // Evolves once the monster reaches this level
+    Level(u32),
+    // Evolves when this item is used on the monster
+    Item(String),
+}
+
+pub struct Evolution {
+    pub from: String,
+    pub into: String,
+    pub trigger: Trigger,
+}
+
+// An evolution waiting for the player to watch or stop
+pub struct Pending {
+    // Index of the evolving monster in the player's team
+    pub index: usize,
+    pub into: String,
+    // The item that set it off, used up only if the evolution goes through
+    pub item: Option<String>,
+}
+
+pub fn load_evolutions() -> Vec<Evolution> {
+    let reader = BufReader::new(File::open("./data/evolutions.txt").expect("Cannot open evolutions.txt"));
+    let mut evolutions = Vec::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        let trigger = match v[2] {
+            "Level" => Trigger::Level(v[3].parse::<u32>().expect("Evolution level should be a number")),
+            "Item" => Trigger::Item(String::from(v[3])),
+            _ => panic!("Unknown evolution trigger {}", v[2]),
+        };
+        evolutions.push(Evolution {
+            from: String::from(v[0]),
+            into: String::from(v[1]),
+            trigger,
+        });
+    }
+    evolutions
+}
+
+/// Returns the evolutions the monsters that just levelled up are ready for
+///
+/// * `evolutions` - Every evolution rule
+/// * `team` - The player's team
+/// * `level_ups` - Indices into the team of the monsters that gained a level
+pub fn by_level(evolutions: &[Evolution], team: &[ActiveMons], level_ups: &[usize]) -> Vec<Pending> {
+    let mut pending = Vec::new();
+    for &index in level_ups.iter() {
+        let mon = &team[index];
+        let found = evolutions.iter().find(|d| match d.trigger {
+            Trigger::Level(level) => d.from == mon.name && mon.level >= level,
+            _ => false,
+        });
+        if let Some(evolution) = found {
+            pending.push(Pending {
+                index,
+                into: evolution.into.clone(),
+                item: None,
+            });
+        }
+    }
+    pending
+}
+
+// The species a monster evolves into when the item is used on it, if any
+pub fn by_item(evolutions: &[Evolution], mon: &ActiveMons, item: &str) -> Option<String> {
+    evolutions
+        .iter()
+        .find(|d| match &d.trigger {
+            Trigger::Item(name) => d.from == mon.name && name == item,
+            _ => false,
+        })
+        .map(|d| d.into.clone())
+}
+
+// Health, level and held item carry over; stats, moves and type all come from the new species
+pub fn evolve(mon: &mut ActiveMons, into: &str) {
+    mon.name = String::from(into);
+}
+
+/// Draws the evolution scene over the whole screen
+///
+/// * `battle_init` - Holds the font and monster sprites
+/// * `from` - The monster as it is now
+/// * `into` - What it is evolving into, or None once the evolution is over
+/// * `message` - Shown in the box at the bottom
+pub fn draw_evolution(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &battle::Battle,
+    from: &str,
+    into: Option<&str>,
+    message: &str,
+) -> Result<(), String> {
+    wincan.set_draw_color(Color::RGB(0x10, 0x18, 0x30));
+    wincan.fill_rect(Rect::new(0, 0, 1280, 720))?;
+
+    match into {
+        Some(into) => {
+            wincan.copy(&battle_init.monster_text_map[from], None, Rect::new(240, 160, 300, 300))?;
+            battle::draw_text(wincan, battle_init.font, ">>", Color::RGB(0xbd, 0xcd, 0xde), Rect::new(580, 270, 120, 80))?;
+            wincan.copy(&battle_init.monster_text_map[into], None, Rect::new(740, 160, 300, 300))?;
+            battle::draw_text(
+                wincan,
+                battle_init.font,
+                "Return: continue   Backspace: stop",
+                Color::RGB(0xbd, 0xcd, 0xde),
+                Rect::new(340, 480, 600, 40),
+            )?;
+        }
+        None => {
+            wincan.copy(&battle_init.monster_text_map[from], None, Rect::new(490, 160, 300, 300))?;
+        }
+    }
+
+    wincan.set_draw_color(Color::RGB(0xf0, 0xf0, 0xf0));
+    wincan.fill_rect(Rect::new(100, 560, 1080, 120))?;
+    battle::draw_text(wincan, battle_init.font, message, Color::RGB(0, 0, 0), Rect::new(120, 580, 1040, 80))?;
+    wincan.present();
+    Ok(())
+}
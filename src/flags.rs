@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+
+use crate::ActiveMons;
+
+// Story progress, kept as named flags like "defeated:Finn" or "talked:Old Man"
+pub struct Flags {
+    set: HashSet<String>,
+}
+
+impl Flags {
+    pub fn new() -> Flags {
+        Flags { set: HashSet::new() }
+    }
+
+    // Returns true if the flag wasn't already set
+    pub fn set(&mut self, flag: &str) -> bool {
+        self.set.insert(String::from(flag))
+    }
+
+    pub fn has(&self, flag: &str) -> bool {
+        self.set.contains(flag)
+    }
+
+    // Every flag in a stable order, for saving
+    pub fn sorted(&self) -> Vec<String> {
+        let mut flags: Vec<String> = self.set.iter().cloned().collect();
+        flags.sort();
+        flags
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Flags {
+        Flags::new()
+    }
+}
+
+pub fn defeated(trainer: &str) -> String {
+    format!("defeated:{}", trainer)
+}
+
+pub fn caught(species: &str) -> String {
+    format!("caught:{}", species)
+}
+
+pub fn caught_type(monster_type: &str) -> String {
+    format!("caught_type:{}", monster_type)
+}
+
+pub fn talked(npc: &str) -> String {
+    format!("talked:{}", npc)
+}
+
+pub fn quest_done(quest: &str) -> String {
+    format!("quest:{}", quest)
+}
+
+// What conditions can check about the player's progress
+pub struct Progress<'a> {
+    pub badges: usize,
+    pub team: &'a Vec<ActiveMons>,
+    pub flags: &'a Flags,
+}
+
+// A check on the player's progress, shared by dialogue, doors, encounters and quests
+pub enum Condition {
+    Always,
+    // At least this many badges
+    Badges(usize),
+    // A monster of this species was caught, or is on the team
+    Caught(String),
+    // A monster of this type was caught
+    CaughtType(String),
+    // This trainer has been beaten
+    Beaten(String),
+    // The player talked to this NPC
+    Talked(String),
+    // Any other flag is set
+    Flag(String),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn holds(&self, progress: &Progress) -> bool {
+        match self {
+            Condition::Always => true,
+            Condition::Badges(count) => progress.badges >= *count,
+            Condition::Caught(name) => {
+                progress.flags.has(&caught(name)) || progress.team.iter().any(|d| &d.name == name)
+            }
+            Condition::CaughtType(monster_type) => progress.flags.has(&caught_type(monster_type)),
+            Condition::Beaten(name) => progress.flags.has(&defeated(name)),
+            Condition::Talked(name) => progress.flags.has(&talked(name)),
+            Condition::Flag(flag) => progress.flags.has(flag),
+            Condition::Not(condition) => !condition.holds(progress),
+        }
+    }
+}
+
+// Conditions look like "always", "badges>=2", "caught:melon-mon", "caught_type:Fire", "beaten:Ada",
+// "talked:Old Man" or "flag:anything", and "!" in front negates one
+pub fn parse_condition(condition: &str) -> Condition {
+    if let Some(rest) = condition.strip_prefix("!") {
+        return Condition::Not(Box::new(parse_condition(rest)));
+    }
+    if condition == "always" || condition.is_empty() {
+        Condition::Always
+    } else if let Some(count) = condition.strip_prefix("badges>=") {
+        Condition::Badges(count.parse::<usize>().expect("Badge count should be a number"))
+    } else if let Some(name) = condition.strip_prefix("caught:") {
+        Condition::Caught(String::from(name))
+    } else if let Some(monster_type) = condition.strip_prefix("caught_type:") {
+        Condition::CaughtType(String::from(monster_type))
+    } else if let Some(name) = condition.strip_prefix("beaten:") {
+        Condition::Beaten(String::from(name))
+    } else if let Some(name) = condition.strip_prefix("talked:") {
+        Condition::Talked(String::from(name))
+    } else if let Some(flag) = condition.strip_prefix("flag:") {
+        Condition::Flag(String::from(flag))
+    } else {
+        panic!("Unknown condition {}", condition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::monster;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn flags_are_set_once() {
+        let mut flags = Flags::new();
+        assert!(flags.set(&defeated("Ada")));
+        assert!(!flags.set(&defeated("Ada")));
+        flags.set(&talked("Old Man"));
+        assert!(flags.has("defeated:Ada"));
+        assert!(!flags.has(&defeated("Finn")));
+        assert_eq!(flags.sorted(), vec![String::from("defeated:Ada"), String::from("talked:Old Man")]);
+    }
+
+    #[test]
+    fn conditions_check_progress() {
+        let team = vec![monster::roll_instance("Reusoon", monster::STARTING_LEVEL, &mut StdRng::seed_from_u64(43))];
+        let mut flags = Flags::new();
+        flags.set(&defeated("Ada"));
+        flags.set(&caught("orcaaa"));
+        flags.set(&caught_type("Water"));
+        flags.set(&talked("Old Man"));
+        flags.set("bridge_fixed");
+        let progress = Progress { badges: 2, team: &team, flags: &flags };
+
+        for condition in [
+            "always",
+            "",
+            "badges>=2",
+            "caught:orcaaa",
+            // Monsters on the team count as caught even without the flag
+            "caught:Reusoon",
+            "caught_type:Water",
+            "beaten:Ada",
+            "talked:Old Man",
+            "flag:bridge_fixed",
+            "!badges>=3",
+            "!!beaten:Ada",
+        ]
+        .iter()
+        {
+            assert!(parse_condition(condition).holds(&progress), "{} should hold", condition);
+        }
+        for condition in [
+            "badges>=3",
+            "caught:melon-mon",
+            "caught_type:Fire",
+            "beaten:Finn",
+            "talked:Shopkeeper",
+            "flag:bridge_broken",
+            "!always",
+            "!beaten:Ada",
+        ]
+        .iter()
+        {
+            assert!(!parse_condition(condition).holds(&progress), "{} should not hold", condition);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown condition")]
+    fn unknown_conditions_panic() {
+        parse_condition("weather:Rain");
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+
+use crate::ability;
+use crate::inventory::{Item, ItemKind};
+use crate::monster;
+
+/// Points in the battle pipeline where passive effects are checked
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    // The monster was just sent into battle
+    Entry,
+    // Damage of the monster's attack is being calculated
+    Attacking,
+    // Damage against the monster is being calculated
+    Defending,
+    // The monster just lost health
+    HealthDropped,
+    // Both sides have acted this turn
+    EndOfTurn,
+}
+
+pub enum HookEffect {
+    // Multiplies the damage being calculated
+    ScaleDamage(f32),
+    // Restores health to the holder, using up the item if set
+    Heal(f32, bool),
+}
+
+/// Returns the effect a held item has at the given trigger, if any
+///
+/// * `item` - The held item
+/// * `trigger` - The point of the battle being checked
+/// * `attack` - The move being used, for damage triggers
+/// * `health` - The holder's current health (0-100)
+pub fn held_item_effect(
+    item: &Item,
+    trigger: Trigger,
+    attack: Option<&monster::Move>,
+    health: f32,
+) -> Option<HookEffect> {
+    match (item.kind, trigger) {
+        (ItemKind::Charm, Trigger::Attacking) => match attack {
+            Some(a) if a.damage > 0 && a.attack_type == item.param => {
+                Some(HookEffect::ScaleDamage(1.0 + item.amount as f32 / 100.0))
+            }
+            _ => None,
+        },
+        (ItemKind::Leftovers, Trigger::EndOfTurn) => {
+            if health > 0.0 && health < 100.0 {
+                Some(HookEffect::Heal(item.amount as f32, false))
+            } else {
+                None
+            }
+        }
+        (ItemKind::Berry, Trigger::HealthDropped) => {
+            let threshold = item.param.parse::<f32>().unwrap_or(50.0);
+            if health > 0.0 && health < threshold {
+                Some(HookEffect::Heal(item.amount as f32, true))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn player_held_item<'a>(
+    items: &'a HashMap<String, Item>,
+    battle_state: &monster::BattleState,
+    slot: usize,
+) -> Option<&'a Item> {
+    match &battle_state.player_team[slot].held_item {
+        Some(name) => items.get(name),
+        None => None,
+    }
+}
+
+// Name and health of an active monster on one side
+fn active(battle_state: &monster::BattleState, player_side: bool, slot: usize) -> (String, f32) {
+    if player_side {
+        (
+            battle_state.player_team[slot].name.clone(),
+            battle_state.player_team[slot].hp,
+        )
+    } else {
+        battle_state.enemy_team[slot].clone()
+    }
+}
+
+/// Applies the damage hooks of the active monsters to a calculated damage
+///
+/// Returns the new damage and any messages explaining the change.
+///
+/// * `items` - Maps item names onto their definitions
+/// * `monsters` - Maps strings onto their Monster objects; needed for abilities
+/// * `battle_state` - The current state of the battle
+/// * `attack` - The move being used
+/// * `player_turn` - Whether the player is the one attacking
+/// * `slots` - Team indices of the attacker and the defender, (0, 0) outside double battles
+/// * `damage` - The damage from `monster::calculate_damage`
+pub fn modify_damage(
+    items: &HashMap<String, Item>,
+    monsters: &HashMap<String, monster::Monster>,
+    battle_state: &monster::BattleState,
+    attack: &monster::Move,
+    player_turn: bool,
+    slots: (usize, usize),
+    damage: f32,
+) -> (f32, Vec<String>) {
+    let mut damage = damage;
+    let mut messages = Vec::new();
+
+    // Only the player's monsters hold items
+    let (trigger, player_slot) = if player_turn {
+        (Trigger::Attacking, slots.0)
+    } else {
+        (Trigger::Defending, slots.1)
+    };
+    if let Some(item) = player_held_item(items, battle_state, player_slot) {
+        if let Some(HookEffect::ScaleDamage(mult)) = held_item_effect(item, trigger, Some(attack), battle_state.player_team[player_slot].hp) {
+            damage *= mult;
+        }
+    }
+
+    let (damage, ability_messages) = ability_damage(monsters, battle_state, attack, player_turn, slots, damage);
+    messages.extend(ability_messages);
+    (damage, messages)
+}
+
+/// Applies the damage rules of the attacker's and defender's abilities to a calculated damage
+///
+/// Returns the new damage and any messages explaining the change. Unlike held items,
+/// abilities are part of a species, so the AI's search applies these as well.
+///
+/// * `monsters` - Maps strings onto their Monster objects; needed for abilities
+/// * `battle_state` - The current state of the battle
+/// * `attack` - The move being used
+/// * `player_turn` - Whether the player is the one attacking
+/// * `slots` - Team indices of the attacker and the defender, (0, 0) outside double battles
+/// * `damage` - The damage so far
+pub fn ability_damage(
+    monsters: &HashMap<String, monster::Monster>,
+    battle_state: &monster::BattleState,
+    attack: &monster::Move,
+    player_turn: bool,
+    slots: (usize, usize),
+    damage: f32,
+) -> (f32, Vec<String>) {
+    let mut damage = damage;
+    let mut messages = Vec::new();
+
+    let (attacker, attacker_health) = active(battle_state, player_turn, slots.0);
+    let (defender, defender_health) = active(battle_state, !player_turn, slots.1);
+
+    if let Some(a) = monsters[&attacker].ability {
+        for effect in ability::triggered_effects(a, Trigger::Attacking, attacker_health, Some(attack)) {
+            if let ability::Effect::Damage(mult) = effect {
+                damage *= mult;
+                messages.push(format!("{}'s {} powered up the attack!", attacker, a.name));
+            }
+        }
+    }
+    if let Some(a) = monsters[&defender].ability {
+        for effect in ability::triggered_effects(a, Trigger::Defending, defender_health, Some(attack)) {
+            if let ability::Effect::Damage(mult) = effect {
+                damage *= mult;
+                if *mult == 0.0 {
+                    messages.push(format!("{}'s {} made it immune!", defender, a.name));
+                } else {
+                    messages.push(format!("{}'s {} softened the blow!", defender, a.name));
+                }
+            }
+        }
+    }
+
+    (damage, messages)
+}
+
+fn stage_message(mon: &str, ability: &str, target: &str, stat: &str, stages: i32) -> String {
+    let change = if stages > 0 { "raised" } else { "lowered" };
+    format!("{}'s {} {} {}'s {}!", mon, ability, change, target, stat)
+}
+
+/// Runs a trigger that isn't part of damage calculation for one side's active monster
+///
+/// Returns the messages for any effects that happened.
+///
+/// * `items` - Maps item names onto their definitions
+/// * `monsters` - Maps strings onto their Monster objects; needed for abilities
+/// * `battle_state` - The current state of the battle
+/// * `trigger` - The point of the battle being checked
+/// * `player_side` - Whether to run the hooks for the player's or the enemy's monster
+/// * `slot` - Team index of the monster, 0 outside double battles
+pub fn run_hooks(
+    items: &HashMap<String, Item>,
+    monsters: &HashMap<String, monster::Monster>,
+    battle_state: &mut monster::BattleState,
+    trigger: Trigger,
+    player_side: bool,
+    slot: usize,
+) -> Vec<String> {
+    let mut messages = Vec::new();
+
+    // Held items first, only the player's monsters hold them
+    if player_side {
+        if let Some(item) = player_held_item(items, battle_state, slot) {
+            let mon = &mut battle_state.player_team[slot];
+            if let Some(HookEffect::Heal(amount, consumed)) = held_item_effect(item, trigger, None, mon.hp) {
+                mon.hp = (mon.hp + amount).clamp(0.0, 100.0);
+                if consumed {
+                    mon.held_item = None;
+                    messages.push(format!("{} ate its {}!", mon.name, item.name));
+                } else {
+                    messages.push(format!("{} restored health with its {}!", mon.name, item.name));
+                }
+            }
+        }
+    }
+
+    let (name, health) = active(battle_state, player_side, slot);
+    let (opp_name, _) = active(battle_state, !player_side, 0);
+    if health <= 0.0 {
+        return messages;
+    }
+    let a = match monsters[&name].ability {
+        Some(a) => a,
+        None => return messages,
+    };
+
+    for effect in ability::triggered_effects(a, trigger, health, None) {
+        // Stages are stored from the player's point of view
+        let (own_attack, own_defense, opp_attack, opp_defense) = if player_side {
+            (
+                &mut battle_state.self_attack_stages,
+                &mut battle_state.self_defense_stages,
+                &mut battle_state.opp_attack_stages,
+                &mut battle_state.opp_defense_stages,
+            )
+        } else {
+            (
+                &mut battle_state.opp_attack_stages,
+                &mut battle_state.opp_defense_stages,
+                &mut battle_state.self_attack_stages,
+                &mut battle_state.self_defense_stages,
+            )
+        };
+        let (stages, n, target, stat) = match effect {
+            ability::Effect::SelfAttack(n) => (own_attack, *n, &name, "attack"),
+            ability::Effect::SelfDefense(n) => (own_defense, *n, &name, "defense"),
+            ability::Effect::OppAttack(n) => (opp_attack, *n, &opp_name, "attack"),
+            ability::Effect::OppDefense(n) => (opp_defense, *n, &opp_name, "defense"),
+            ability::Effect::Heal(amount) => {
+                if health < 100.0 {
+                    if player_side {
+                        battle_state.player_team[slot].hp = (health + amount).clamp(0.0, 100.0);
+                    } else {
+                        battle_state.enemy_team[slot].1 = (health + amount).clamp(0.0, 100.0);
+                    }
+                    messages.push(format!("{} restored health with {}!", name, a.name));
+                }
+                continue;
+            }
+            ability::Effect::Damage(_) => continue,
+        };
+        *stages = (*stages + n).clamp(-monster::STAGE_LIMIT, monster::STAGE_LIMIT);
+        messages.push(stage_message(&name, &a.name, target, stat, n));
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ActiveMons;
+
+    fn test_move(attack_type: &str) -> monster::Move {
+        monster::Move {
+            name: String::from("Test"),
+            damage: 40,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+            attack_type: String::from(attack_type),
+            effect: String::new(),
+            move_effect: monster::MoveEffect::None,
+            spread: false,
+        }
+    }
+
+    fn test_monster<'a>(ability: Option<&'a ability::Ability>) -> monster::Monster<'a> {
+        monster::Monster {
+            attack_stat: 100,
+            defense_stat: 100,
+            moves: Vec::new(),
+            monster_type: String::from("Normal"),
+            ability,
+        }
+    }
+
+    // Hero, on the player's side, against Foe
+    fn test_state() -> monster::BattleState {
+        monster::BattleState {
+            player_turn: true,
+            player_team: vec![ActiveMons {
+                name: String::from("Hero"),
+                hp: 100.0,
+                held_item: None,
+                level: monster::STARTING_LEVEL,
+                exp: 0,
+                attack_iv: 0,
+                defense_iv: 0,
+                nature: monster::Nature::Hardy,
+            }],
+            enemy_team: vec![(String::from("Foe"), 100.0)],
+            enemy_level: monster::STARTING_LEVEL,
+            wild: false,
+            weather: None,
+            double: false,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        }
+    }
+
+    fn test_item(kind: ItemKind, amount: u32, param: &str) -> Item {
+        Item {
+            name: String::from("Trinket"),
+            kind,
+            amount,
+            param: String::from(param),
+            price: 0,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn charms_boost_their_type() {
+        let charm = test_item(ItemKind::Charm, 25, "Fire");
+        let fire = test_move("Fire");
+        let water = test_move("Water");
+        let mut fire_status = test_move("Fire");
+        fire_status.damage = 0;
+
+        match held_item_effect(&charm, Trigger::Attacking, Some(&fire), 100.0) {
+            Some(HookEffect::ScaleDamage(mult)) => assert_eq!(mult, 1.25),
+            _ => panic!("Expected the charm to scale damage"),
+        }
+        assert!(held_item_effect(&charm, Trigger::Attacking, Some(&water), 100.0).is_none());
+        assert!(held_item_effect(&charm, Trigger::Attacking, Some(&fire_status), 100.0).is_none());
+        assert!(held_item_effect(&charm, Trigger::Defending, Some(&fire), 100.0).is_none());
+    }
+
+    #[test]
+    fn leftovers_heal_while_hurt() {
+        let leftovers = test_item(ItemKind::Leftovers, 6, "0");
+        match held_item_effect(&leftovers, Trigger::EndOfTurn, None, 50.0) {
+            Some(HookEffect::Heal(amount, consumed)) => {
+                assert_eq!(amount, 6.0);
+                assert!(!consumed);
+            }
+            _ => panic!("Expected leftovers to heal"),
+        }
+        assert!(held_item_effect(&leftovers, Trigger::EndOfTurn, None, 100.0).is_none());
+        assert!(held_item_effect(&leftovers, Trigger::EndOfTurn, None, 0.0).is_none());
+    }
+
+    #[test]
+    fn berries_are_eaten_below_their_threshold() {
+        let mut items = HashMap::new();
+        items.insert(String::from("Trinket"), test_item(ItemKind::Berry, 30, "50"));
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(None));
+        monsters.insert(String::from("Foe"), test_monster(None));
+        let mut state = test_state();
+        state.player_team[0].held_item = Some(String::from("Trinket"));
+
+        state.player_team[0].hp = 60.0;
+        assert!(run_hooks(&items, &monsters, &mut state, Trigger::HealthDropped, true, 0).is_empty());
+        assert_eq!(state.player_team[0].hp, 60.0);
+
+        state.player_team[0].hp = 40.0;
+        assert_eq!(run_hooks(&items, &monsters, &mut state, Trigger::HealthDropped, true, 0).len(), 1);
+        assert_eq!(state.player_team[0].hp, 70.0);
+        assert!(state.player_team[0].held_item.is_none());
+    }
+
+    #[test]
+    fn held_items_scale_the_holders_attacks() {
+        let mut items = HashMap::new();
+        items.insert(String::from("Trinket"), test_item(ItemKind::Charm, 25, "Fire"));
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(None));
+        monsters.insert(String::from("Foe"), test_monster(None));
+        let mut state = test_state();
+        state.player_team[0].held_item = Some(String::from("Trinket"));
+        let fire = test_move("Fire");
+
+        assert_eq!(modify_damage(&items, &monsters, &state, &fire, true, (0, 0), 40.0).0, 50.0);
+        // Only the player's monsters hold items, and a charm doesn't help defend
+        assert_eq!(modify_damage(&items, &monsters, &state, &fire, false, (0, 0), 40.0).0, 40.0);
+    }
+
+    #[test]
+    fn abilities_change_damage_both_ways() {
+        let abilities = ability::load_abilities();
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(None));
+        monsters.insert(String::from("Foe"), test_monster(Some(&abilities["Levitate"])));
+        let state = test_state();
+        let ground = test_move("Ground");
+        let fire = test_move("Fire");
+
+        let (damage, messages) = ability_damage(&monsters, &state, &ground, true, (0, 0), 30.0);
+        assert_eq!(damage, 0.0);
+        assert_eq!(messages.len(), 1);
+        let (damage, messages) = ability_damage(&monsters, &state, &fire, true, (0, 0), 30.0);
+        assert_eq!(damage, 30.0);
+        assert!(messages.is_empty());
+        // Levitate only guards its holder
+        let (damage, _) = ability_damage(&monsters, &state, &ground, false, (0, 0), 30.0);
+        assert_eq!(damage, 30.0);
+    }
+
+    #[test]
+    fn entry_abilities_change_stages() {
+        let abilities = ability::load_abilities();
+        let mut monsters = HashMap::new();
+        monsters.insert(String::from("Hero"), test_monster(None));
+        monsters.insert(String::from("Foe"), test_monster(Some(&abilities["Intimidate"])));
+        let mut state = test_state();
+
+        let messages = run_hooks(&HashMap::new(), &monsters, &mut state, Trigger::Entry, false, 0);
+        assert_eq!(messages.len(), 1);
+        // Stages are stored from the player's side, so the enemy lowered the player's attack
+        assert_eq!(state.self_attack_stages, -1);
+        assert_eq!(state.opp_attack_stages, 0);
+
+        // Fainted monsters don't trigger anything
+        state.enemy_team[0].1 = 0.0;
+        assert!(run_hooks(&HashMap::new(), &monsters, &mut state, Trigger::Entry, false, 0).is_empty());
+        assert_eq!(state.self_attack_stages, -1);
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use rand::Rng;
+
+use crate::monster;
+use crate::ActiveMons;
+
+pub const MAX_TEAM_SIZE: usize = 6;
+// Species the player starts with, lead first; fewer than MAX_TEAM_SIZE so there is room to catch more
+pub const STARTING_TEAM: [&str; 3] = ["Chromacat", "deer pokemon", "tokoro"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ItemKind {
+    Heal,
+    Cure,
+    Revive,
+    AttackBoost,
+    DefenseBoost,
+    Ball,
+    Charm,
+    Leftovers,
+    Berry,
+    Stone,
+    Repel,
+}
+
+pub struct Item {
+    pub name: String,
+    pub kind: ItemKind,
+    pub amount: u32,
+    pub param: String,
+    // What the shop charges; items are sold back for half
+    pub price: u32,
+    pub description: String,
+}
+
+impl Item {
+    // Healing and held items are used on a chosen team member, everything else acts on the battle
+    pub fn needs_target(&self) -> bool {
+        self.kind == ItemKind::Heal
+            || self.kind == ItemKind::Revive
+            || self.kind == ItemKind::Stone
+            || self.is_held()
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.kind == ItemKind::Charm || self.kind == ItemKind::Leftovers || self.kind == ItemKind::Berry
+    }
+}
+
+pub enum ItemUse {
+    Used(String),
+    Caught(String),
+    Failed(String),
+    // The item is now held, and any item it replaced goes back into the bag
+    Equipped(String, Option<String>),
+    // The team member at this index may evolve; the item is only used up if it does
+    Evolve(usize),
+    // Wild monsters stay away for this many steps
+    Repel(u32),
+}
+
+pub struct Inventory {
+    pub items: Vec<(String, u32)>,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory { items: Vec::new() }
+    }
+
+    pub fn starter() -> Inventory {
+        let mut inventory = Inventory::new();
+        inventory.add("Potion", 5);
+        inventory.add("Revive", 1);
+        inventory.add("Monster Ball", 5);
+        inventory.add("Oran Berry", 1);
+        inventory.add("Repel", 1);
+        inventory
+    }
+
+    pub fn add(&mut self, name: &str, count: u32) {
+        match self.items.iter_mut().find(|d| d.0 == name) {
+            Some(entry) => entry.1 += count,
+            None => self.items.push((String::from(name), count)),
+        }
+    }
+
+    pub fn count(&self, name: &str) -> u32 {
+        match self.items.iter().find(|d| d.0 == name) {
+            Some(entry) => entry.1,
+            None => 0,
+        }
+    }
+
+    // Removes a single item, dropping the entry once none are left
+    pub fn consume(&mut self, name: &str) -> bool {
+        match self.items.iter().position(|d| d.0 == name) {
+            Some(index) => {
+                self.items[index].1 -= 1;
+                if self.items[index].1 == 0 {
+                    self.items.remove(index);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Inventory {
+    fn default() -> Inventory {
+        Inventory::new()
+    }
+}
+
+fn parse_kind(kind: &str) -> Result<ItemKind, String> {
+    match kind {
+        "Heal" => Ok(ItemKind::Heal),
+        "Cure" => Ok(ItemKind::Cure),
+        "Revive" => Ok(ItemKind::Revive),
+        "AttackBoost" => Ok(ItemKind::AttackBoost),
+        "DefenseBoost" => Ok(ItemKind::DefenseBoost),
+        "Ball" => Ok(ItemKind::Ball),
+        "Charm" => Ok(ItemKind::Charm),
+        "Leftovers" => Ok(ItemKind::Leftovers),
+        "Berry" => Ok(ItemKind::Berry),
+        "Stone" => Ok(ItemKind::Stone),
+        "Repel" => Ok(ItemKind::Repel),
+        _ => Err(format!("Unknown item kind {}", kind)),
+    }
+}
+
+fn parse_item(line: &str) -> Result<Item, String> {
+    let v = line.split(",").collect::<Vec<&str>>();
+    if v.len() < 6 {
+        return Err(format!("Expected 6 fields but found {}", v.len()));
+    }
+    Ok(Item {
+        name: String::from(v[0]),
+        kind: parse_kind(v[1])?,
+        amount: v[2].parse::<u32>().map_err(|_| format!("Amount {} should be a number", v[2]))?,
+        param: String::from(v[3]),
+        price: v[4].parse::<u32>().map_err(|_| format!("Price {} should be a number", v[4]))?,
+        description: String::from(v[5]),
+    })
+}
+
+pub fn load_items() -> HashMap<String, Item> {
+    let reader = BufReader::new(File::open("./data/items.txt").expect("Cannot open items.txt"));
+    let mut items = HashMap::new();
+    for (n, line) in reader.lines().skip(1).enumerate() {
+        let line = line.unwrap();
+        // Line numbers count the header
+        let item = parse_item(&line).unwrap_or_else(|e| panic!("items.txt line {}: {} ({})", n + 2, e, line));
+        items.insert(item.name.clone(), item);
+    }
+    items
+}
+
+fn catch_chance(item: &Item, enemy_health: f32) -> f64 {
+    // A full health monster is caught a third of the time with a standard ball
+    let health_factor = 1.0 - (enemy_health as f64 / 150.0);
+    (item.amount as f64 / 100.0 * health_factor).clamp(0.0, 1.0)
+}
+
+/// Applies an item for the player and returns what happened
+///
+/// Returns an error message (and the item should not be consumed) if the item can't be used.
+///
+/// * `item` - The item being used
+/// * `battle_state` - Holds the player's team, and the current battle if there is one
+/// * `target` - Index into the player's team for items that need a target
+/// * `in_battle` - Whether the item is used during a battle
+/// * `rng` - Decides whether a ball catches; the battle's own RNG so replays match
+pub fn use_item(
+    item: &Item,
+    battle_state: &mut monster::BattleState,
+    target: usize,
+    in_battle: bool,
+    rng: &mut impl Rng,
+) -> Result<ItemUse, String> {
+    let no_effect = String::from("It won't have any effect.");
+    match item.kind {
+        ItemKind::Heal => {
+            let mon: &mut ActiveMons = &mut battle_state.player_team[target];
+            if mon.hp <= 0.0 || mon.hp >= 100.0 {
+                return Err(no_effect);
+            }
+            mon.hp = (mon.hp + item.amount as f32).clamp(0.0, 100.0);
+            Ok(ItemUse::Used(format!("{} was healed!", mon.name)))
+        }
+        ItemKind::Revive => {
+            let mon: &mut ActiveMons = &mut battle_state.player_team[target];
+            if mon.hp > 0.0 {
+                return Err(no_effect);
+            }
+            mon.hp = (item.amount as f32).clamp(0.0, 100.0);
+            Ok(ItemUse::Used(format!("{} was revived!", mon.name)))
+        }
+        ItemKind::Cure => {
+            if !in_battle
+                || (battle_state.self_attack_stages >= 0 && battle_state.self_defense_stages >= 0)
+            {
+                return Err(no_effect);
+            }
+            battle_state.self_attack_stages = battle_state.self_attack_stages.max(0);
+            battle_state.self_defense_stages = battle_state.self_defense_stages.max(0);
+            Ok(ItemUse::Used(format!(
+                "{}'s stats were restored!",
+                battle_state.player_team[0].name
+            )))
+        }
+        ItemKind::AttackBoost => {
+            if !in_battle || battle_state.self_attack_stages >= monster::STAGE_LIMIT {
+                return Err(no_effect);
+            }
+            battle_state.self_attack_stages = (battle_state.self_attack_stages
+                + item.amount as i32)
+                .clamp(-monster::STAGE_LIMIT, monster::STAGE_LIMIT);
+            Ok(ItemUse::Used(format!(
+                "{}'s attack rose!",
+                battle_state.player_team[0].name
+            )))
+        }
+        ItemKind::DefenseBoost => {
+            if !in_battle || battle_state.self_defense_stages >= monster::STAGE_LIMIT {
+                return Err(no_effect);
+            }
+            battle_state.self_defense_stages = (battle_state.self_defense_stages
+                + item.amount as i32)
+                .clamp(-monster::STAGE_LIMIT, monster::STAGE_LIMIT);
+            Ok(ItemUse::Used(format!(
+                "{}'s defense rose!",
+                battle_state.player_team[0].name
+            )))
+        }
+        ItemKind::Ball => {
+            if !in_battle || !battle_state.wild {
+                return Err(String::from("You can only catch wild monsters."));
+            }
+            if battle_state.player_team.len() >= MAX_TEAM_SIZE {
+                return Err(String::from("Your team is full!"));
+            }
+            let enemy = battle_state.enemy_team[0].clone();
+            if rng.gen_bool(catch_chance(item, enemy.1)) {
+                let mut caught = monster::roll_instance(&enemy.0, battle_state.enemy_level, rng);
+                caught.hp = enemy.1;
+                battle_state.player_team.push(caught);
+                Ok(ItemUse::Caught(format!("You caught {}!", enemy.0)))
+            } else {
+                Ok(ItemUse::Failed(format!("{} broke free!", enemy.0)))
+            }
+        }
+        ItemKind::Repel => {
+            if in_battle {
+                return Err(String::from("You can't do that in battle."));
+            }
+            Ok(ItemUse::Repel(item.amount))
+        }
+        ItemKind::Stone => {
+            if in_battle {
+                return Err(String::from("You can't do that in battle."));
+            }
+            Ok(ItemUse::Evolve(target))
+        }
+        ItemKind::Charm | ItemKind::Leftovers | ItemKind::Berry => {
+            if in_battle {
+                return Err(String::from("You can't do that in battle."));
+            }
+            let mon: &mut ActiveMons = &mut battle_state.player_team[target];
+            let previous = mon.held_item.replace(item.name.clone());
+            Ok(ItemUse::Equipped(
+                format!("{} is now holding {}.", mon.name, item.name),
+                previous,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_item(kind: ItemKind, amount: u32) -> Item {
+        Item {
+            name: String::from("Trinket"),
+            kind,
+            amount,
+            param: String::new(),
+            price: 100,
+            description: String::new(),
+        }
+    }
+
+    // The player's Hero, at the given health, against a wild Foe
+    fn test_state(hp: f32) -> monster::BattleState {
+        let mut rng = StdRng::seed_from_u64(26);
+        let mut hero = monster::roll_instance("Hero", monster::STARTING_LEVEL, &mut rng);
+        hero.hp = hp;
+        monster::BattleState {
+            player_turn: true,
+            player_team: vec![hero],
+            enemy_team: vec![(String::from("Foe"), 100.0)],
+            enemy_level: monster::STARTING_LEVEL,
+            wild: true,
+            weather: None,
+            double: false,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        }
+    }
+
+    #[test]
+    fn inventory_counts_items() {
+        let mut inventory = Inventory::new();
+        inventory.add("Potion", 2);
+        inventory.add("Potion", 1);
+        assert_eq!(inventory.count("Potion"), 3);
+        assert!(inventory.consume("Potion"));
+        assert_eq!(inventory.count("Potion"), 2);
+        assert!(inventory.consume("Potion") && inventory.consume("Potion"));
+        // Used up items leave the bag
+        assert!(inventory.items.is_empty());
+        assert!(!inventory.consume("Potion"));
+    }
+
+    #[test]
+    fn healing_needs_a_hurt_monster() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let potion = test_item(ItemKind::Heal, 30);
+        let revive = test_item(ItemKind::Revive, 50);
+
+        let mut state = test_state(80.0);
+        assert!(use_item(&potion, &mut state, 0, true, &mut rng).is_ok());
+        assert_eq!(state.player_team[0].hp, 100.0);
+        assert!(use_item(&potion, &mut state, 0, true, &mut rng).is_err());
+        assert!(use_item(&revive, &mut state, 0, true, &mut rng).is_err());
+
+        let mut state = test_state(0.0);
+        assert!(use_item(&potion, &mut state, 0, false, &mut rng).is_err());
+        assert!(use_item(&revive, &mut state, 0, false, &mut rng).is_ok());
+        assert_eq!(state.player_team[0].hp, 50.0);
+    }
+
+    #[test]
+    fn battle_items_change_stages() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let tonic = test_item(ItemKind::AttackBoost, 1);
+        let antidote = test_item(ItemKind::Cure, 0);
+
+        let mut state = test_state(100.0);
+        assert!(use_item(&tonic, &mut state, 0, false, &mut rng).is_err());
+        assert!(use_item(&antidote, &mut state, 0, true, &mut rng).is_err());
+
+        state.self_attack_stages = monster::STAGE_LIMIT - 1;
+        assert!(use_item(&tonic, &mut state, 0, true, &mut rng).is_ok());
+        assert_eq!(state.self_attack_stages, monster::STAGE_LIMIT);
+        assert!(use_item(&tonic, &mut state, 0, true, &mut rng).is_err());
+
+        state.self_defense_stages = -2;
+        assert!(use_item(&antidote, &mut state, 0, true, &mut rng).is_ok());
+        assert_eq!(state.self_defense_stages, 0);
+        assert_eq!(state.self_attack_stages, monster::STAGE_LIMIT);
+    }
+
+    #[test]
+    fn balls_only_catch_wild_monsters() {
+        let mut rng = StdRng::seed_from_u64(26);
+        // Strong enough to always catch
+        let ball = test_item(ItemKind::Ball, 1000);
+
+        let mut state = test_state(100.0);
+        assert!(use_item(&ball, &mut state, 0, false, &mut rng).is_err());
+        state.wild = false;
+        assert!(use_item(&ball, &mut state, 0, true, &mut rng).is_err());
+
+        state.wild = true;
+        match use_item(&ball, &mut state, 0, true, &mut rng) {
+            Ok(ItemUse::Caught(_)) => {}
+            _ => panic!("Expected the ball to catch"),
+        }
+        assert_eq!(state.player_team[1].name, "Foe");
+
+        while state.player_team.len() < MAX_TEAM_SIZE {
+            state.player_team.push(state.player_team[0].clone());
+        }
+        assert!(use_item(&ball, &mut state, 0, true, &mut rng).is_err());
+    }
+
+    #[test]
+    fn balls_catch_with_the_starting_team() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let ball = test_item(ItemKind::Ball, 1000);
+
+        let mut state = test_state(100.0);
+        state.player_team = STARTING_TEAM
+            .iter()
+            .map(|d| monster::roll_instance(d, monster::STARTING_LEVEL, &mut rng))
+            .collect();
+        match use_item(&ball, &mut state, 0, true, &mut rng) {
+            Ok(ItemUse::Caught(_)) => {}
+            _ => panic!("Expected the ball to catch"),
+        }
+        assert_eq!(state.player_team.len(), STARTING_TEAM.len() + 1);
+    }
+
+    #[test]
+    fn held_items_swap_outside_battle() {
+        let mut rng = StdRng::seed_from_u64(26);
+        let charm = test_item(ItemKind::Charm, 25);
+
+        let mut state = test_state(100.0);
+        assert!(use_item(&charm, &mut state, 0, true, &mut rng).is_err());
+        state.player_team[0].held_item = Some(String::from("Leftovers"));
+        match use_item(&charm, &mut state, 0, false, &mut rng) {
+            Ok(ItemUse::Equipped(_, previous)) => assert_eq!(previous, Some(String::from("Leftovers"))),
+            _ => panic!("Expected the charm to be held"),
+        }
+        assert_eq!(state.player_team[0].held_item, Some(String::from("Trinket")));
+    }
+
+    #[test]
+    fn items_parse_from_lines() {
+        let item = parse_item("Oran Berry,Berry,30,50,150,Holder heals 30 when below half health").unwrap();
+        assert_eq!(item.name, "Oran Berry");
+        assert!(item.kind == ItemKind::Berry);
+        assert_eq!(item.amount, 30);
+        assert_eq!(item.param, "50");
+        assert_eq!(item.price, 150);
+        assert!(item.is_held());
+        assert!(item.needs_target());
+    }
+
+    #[test]
+    fn bad_items_are_errors() {
+        assert!(parse_item("Potion,Heal,30,0,200").is_err());
+        assert!(parse_item("Potion,Elixir,30,0,200,x").is_err());
+        assert!(parse_item("Potion,Heal,lots,0,200,x").is_err());
+        assert!(parse_item("Potion,Heal,30,0,-1,x").is_err());
+    }
+
+    #[test]
+    fn items_load_from_data() {
+        let items = load_items();
+        assert!(items.contains_key("Potion"));
+        assert!(Inventory::starter().items.iter().all(|d| items.contains_key(&d.0)));
+    }
+}
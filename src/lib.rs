@@ -0,0 +1,55 @@
+// The battle logic and AI, which don't need SDL, so headless tools can share them with the game
+
+pub mod monster;
+pub mod ai;
+pub mod inventory;
+pub mod hooks;
+pub mod ability;
+pub mod weather;
+pub mod tournament;
+
+// A monster on the player's team; its species' data comes from the Monster of the same name
+pub struct ActiveMons {
+  pub name: String,
+  pub hp: f32,
+  pub held_item: Option<String>,
+  pub level: u32,
+  // Experience gained towards the next level
+  pub exp: u32,
+  // Individual values rolled when the monster is first met
+  pub attack_iv: u32,
+  pub defense_iv: u32,
+  pub nature: monster::Nature,
+}
+
+impl Clone for ActiveMons{
+
+  fn clone(&self) -> Self 
+  { 
+    ActiveMons{
+      name: self.name.clone(),
+      hp: self.hp,
+      held_item: self.held_item.clone(),
+      level: self.level,
+      exp: self.exp,
+      attack_iv: self.attack_iv,
+      defense_iv: self.defense_iv,
+      nature: self.nature,
+    }
+  }
+
+}
+
+impl monster::TeamMember for ActiveMons {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn health(&self) -> f32 {
+    self.hp
+  }
+
+  fn set_health(&mut self, health: f32) {
+    self.hp = health;
+  }
+}
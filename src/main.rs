@@ -2,13 +2,25 @@ extern crate sdl2;
 
 // Modules
 mod battle;
-pub mod monster;
 pub mod overworld;
 pub mod player;
 pub mod gym;
 pub mod maze;
-pub mod ai;
 pub mod intro;
+pub mod save;
+pub mod transcript;
+pub mod replay;
+pub mod evolution;
+pub mod encounter;
+pub mod trainer;
+pub mod dialogue;
+pub mod shop;
+pub mod flags;
+pub mod quest;
+
+// Shared with the headless tools
+use sdl_rust::{ability, ai, hooks, inventory, monster, weather};
+use sdl_rust::ActiveMons;
 
 use battle::Map;
 
@@ -34,8 +46,9 @@ use std::time::Duration;
 use std::thread;
 
 use rand::thread_rng;
-use rand::{self, Rng};
 use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 const TITLE: &str = "Monster Town";
 const TILE_SIZE: u32 = 16;
@@ -56,24 +69,9 @@ const DELTA_TIME: f64 = 1.0/60.0;
 // supposed keypress duration
 const KEYPRESS_DURATION: f64 = 1.0; 
 
-pub struct ActiveMons {
-  name: String,
-  hp: f32,
-}
-
-impl Clone for ActiveMons{
-
-  fn clone(&self) -> Self 
-  { 
-    let e =  ActiveMons{
-      name: self.name,
-      hp: self.hp
-    };
-
-    return e;
-  }
-
-}
+// Random battles played, and how far ahead each side searches, when comparing AI weights
+const TUNING_BATTLES: u32 = 200;
+const TUNING_DEPTH: i32 = 2;
 
 fn resist(vel: i32, deltav: i32) -> i32 {
   if deltav == 0 {
@@ -119,21 +117,11 @@ fn check_within(small: &Rect, large: &Rect) -> bool {
   }
 }
 
-fn random_spawn() -> bool {
-  let mut rng = thread_rng();
-  let ran = rng.gen_range(0..100);
-  if ran == 2 {
-    true
-  } else {
-    false
-  }
-}
-
-fn next_available_mon(v: &Vec<(String, f32)>) -> String {
+fn next_available_mon<T: monster::TeamMember>(v: &Vec<T>) -> String {
   let a = String::new();
   for i in v {
-    if i.1 > 0.0 {
-      return i.0.clone();
+    if i.health() > 0.0 {
+      return String::from(i.name());
     }
   }
   return a;
@@ -171,6 +159,110 @@ pub fn init(
   Ok((wincan, event_pump))
 }
 
+// A hidden, software rendered window for checking replays without a display
+pub fn init_headless(width: u32, height: u32) -> Result<sdl2::render::WindowCanvas, String> {
+  std::env::set_var("SDL_VIDEODRIVER", "dummy");
+  let sdl_cxt = sdl2::init()?;
+  let video_subsys = sdl_cxt.video()?;
+
+  let window = video_subsys
+    .window(TITLE, width, height)
+    .hidden()
+    .build()
+    .map_err(|e| e.to_string())?;
+
+  window.into_canvas().software().build().map_err(|e| e.to_string())
+}
+
+// Plays back a saved battle, returning whether it ended the way it was recorded
+fn run_replay(
+  wincan: &mut sdl2::render::WindowCanvas,
+  path: &str,
+  headless: bool,
+) -> Result<bool, String> {
+  let replay = replay::load_replay(path)?;
+
+  let texture_creator = wincan.texture_creator();
+  let battle_bg = texture_creator.load_texture("images/battle_bg.png")?;
+  wincan.set_blend_mode(BlendMode::Blend);
+
+  let moves_map = load_moves();
+  let abilities_map = ability::load_abilities();
+  let monsters_map = load_mons(&moves_map, &abilities_map);
+  let items_map = inventory::load_items();
+  let ai_weights = ai::load_weights();
+
+  let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+  let font_path = Path::new(r"./fonts/framd.ttf");
+  let font = ttf_context.load_font(font_path, 256)?;
+
+  let all_moves = moves_map
+    .keys()
+    .map(String::from)
+    .collect::<Vec<String>>();
+  let all_effects = moves_map
+    .values()
+    .map(|d| d.effect.clone())
+    .collect::<Vec<String>>();
+  let all_monsters = monsters_map
+    .keys()
+    .map(String::from)
+    .collect::<Vec<String>>();
+
+  let move_textures = battle::create_all_attack_textures(&texture_creator, &font, &all_moves)?;
+  let effect_textures = battle::create_all_effect_textures(&texture_creator, &font, &all_effects)?;
+  let names_tup = battle::create_all_name_tuples(&texture_creator, &font, &all_monsters)?;
+  let monster_textures = battle::create_all_monster_textures(&texture_creator, &all_monsters)?;
+
+  let mut battle_state = replay.initial_state();
+  let mut battle_draw = battle::Battle {
+    background_texture: &battle_bg,
+    player_name: String::new(),
+    enemy_name: String::new(),
+    font: &font,
+    player_health: 100.0,
+    enemy_health: 100.0,
+    name_text_map: &names_tup,
+    attack_map: &move_textures,
+    effect_map: &effect_textures,
+    monster_text_map: &monster_textures,
+    monsters: &monsters_map,
+    moves: &moves_map,
+    items: &items_map,
+    weather: None,
+    double: if replay.double {
+      Some(battle::DoubleDraw {
+        player_name: String::new(),
+        player_health: 0.0,
+        enemy_name: String::new(),
+        enemy_health: 0.0,
+        acting: 0,
+        target: None,
+      })
+    } else {
+      None
+    },
+    transcript: transcript::Transcript::new(),
+    rng: StdRng::seed_from_u64(replay.seed),
+    searches: ai::SearchLog::new(),
+    replay: replay::Replay::new(),
+    level_ups: Vec::new(),
+    replaying: true,
+    headless,
+    money: 0,
+    prize: 0,
+    difficulty: replay.difficulty,
+    profile: replay.profile,
+    weights: ai_weights[ai::profile_weights(replay.profile)],
+    lead: 0,
+  };
+
+  let result = replay::play(wincan, &mut battle_state, &mut battle_draw, &monsters_map, &items_map, &replay)?;
+  println!("Recorded result: {}", replay.result.as_deref().unwrap_or("unfinished"));
+  println!("Replayed result: {}", result.as_deref().unwrap_or("unfinished"));
+  Ok(result == replay.result)
+}
+
 fn run(
   wincan: &mut sdl2::render::WindowCanvas,
   event_pump: &mut sdl2::EventPump,
@@ -184,6 +276,9 @@ fn run(
   let gym_4 = texture_creator.load_texture("images/GymV2.png")?;
   let hospital = texture_creator.load_texture("images/center.png")?;
   let home = texture_creator.load_texture("images/home.png")?;
+  // The shop is a house painted blue
+  let mut shop_texture = texture_creator.load_texture("images/home.png")?;
+  shop_texture.set_color_mod(0x90, 0xb0, 0xff);
   let battle_bg = texture_creator.load_texture("images/battle_bg.png")?;
   let npc_static = texture_creator.load_texture("images/NPC_1.png")?;
   let diff_texture = texture_creator.load_texture("images/difficulty_select.png")?;
@@ -194,7 +289,13 @@ fn run(
   let mut loaded_map = Map::Intro;
 
   let moves_map = load_moves();
-  let monsters_map = load_mons(&moves_map);
+  let abilities_map = ability::load_abilities();
+  let monsters_map = load_mons(&moves_map, &abilities_map);
+  let items_map = inventory::load_items();
+  let ai_weights = ai::load_weights();
+  let evolutions = evolution::load_evolutions();
+  let encounters = encounter::load_encounters();
+  let encounter_rates = encounter::load_rates();
 
   let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
   let font_path = Path::new(r"./fonts/framd.ttf");
@@ -218,13 +319,23 @@ fn run(
   let names_tup = battle::create_all_name_tuples(&texture_creator, &font, &all_monsters)?;
   let monster_textures = battle::create_all_monster_textures(&texture_creator, &all_monsters)?;
 
-  let mut player_team: Vec<ActiveMons> = Vec::new();
-  player_team.push(ActiveMons{ name: String::from("Chromacat"), hp: 100.0});
-  player_team.push(ActiveMons{ name: String::from("deer pokemon"), hp: 100.0});
-  player_team.push(ActiveMons{ name: String::from("tokoro"), hp: 100.0});
-  player_team.push(ActiveMons{ name: String::from("Shockshroom"), hp: 100.0});
-  player_team.push(ActiveMons{ name: String::from("Gurmail"), hp: 100.0});
-  player_team.push(ActiveMons{ name: String::from("Burhan2"), hp: 100.0}) ;
+  let mut player_team: Vec<ActiveMons> = inventory::STARTING_TEAM
+    .iter()
+    .map(|d| monster::roll_instance(d, monster::STARTING_LEVEL, &mut thread_rng()))
+    .collect();
+
+  // Pick up where the player left off if there is a save
+  let mut bag = inventory::Inventory::starter();
+  let mut game_flags = flags::Flags::new();
+  let mut money = shop::STARTING_MONEY;
+  if let Some(data) = save::load_game() {
+    if !data.team.is_empty() {
+      player_team = data.team;
+    }
+    bag = data.inventory;
+    game_flags = data.flags;
+    money = data.money.unwrap_or(shop::STARTING_MONEY);
+  }
 
 /*  let mut player_team: HashMap<String, f32> = HashMap::new(); 
   player_team.insert(String::from("Chromacat"), 100.0);
@@ -267,6 +378,22 @@ fn run(
     monster_text_map: &monster_textures,
     monsters: &monsters_map,
     moves: &moves_map,
+    items: &items_map,
+    weather: None,
+    double: None,
+    transcript: transcript::Transcript::new(),
+    rng: StdRng::seed_from_u64(0),
+    searches: ai::SearchLog::new(),
+    replay: replay::Replay::new(),
+    level_ups: Vec::new(),
+    replaying: false,
+    headless: false,
+    money,
+    prize: 0,
+    difficulty: ai::Difficulty::Normal,
+    profile: ai::Profile::Standard,
+    weights: ai_weights["default"],
+    lead: 0,
   };
 
   let player_monster = next_available_mon(&player_team);
@@ -277,6 +404,10 @@ fn run(
       >= monsters_map[&enemy_monster].attack_stat,
     player_team: player_team.clone(),
     enemy_team: enemy_team.clone(),
+    enemy_level: monster::STARTING_LEVEL,
+    wild: false,
+    weather: None,
+    double: false,
     self_attack_stages: 0,
     self_defense_stages: 0,
     opp_attack_stages: 0,
@@ -287,6 +418,19 @@ fn run(
   let mut menu_active = false;
   let mut menu_choice: usize = 0;
   let mut menu_selected_choice: Option<usize> = None;
+  let mut bag_active = false;
+  let mut bag_choice: usize = 0;
+  let mut bag_target: Option<usize> = None;
+  let mut bag_message: Option<String> = None;
+  // Slot, move and target picked so far for each of the player's monsters in a double battle
+  let mut double_picks: Vec<(usize, usize, usize)> = Vec::new();
+  let mut log_active = false;
+  let mut log_scroll: usize = 0;
+  let mut log_message: Option<String> = None;
+  let mut steps = encounter::Steps::new();
+  let mut evolution_queue: Vec<evolution::Pending> = Vec::new();
+  // Shown once an evolution finished or was stopped, until the player moves on
+  let mut evolution_message: Option<String> = None;
   
   let mut intro_played = false;
   let mut difficulty_choice = 1;
@@ -294,13 +438,22 @@ fn run(
   let mut x_vel = 0;
   let mut y_vel = 0;
 
-  let mut delta_x_npc1 = 0;
-  let mut delta_x_npc2 = 0;
-  let mut delta_x_npc3 = 0;
-
-  let mut flip_1 = false;
-  let mut flip_2 = false;
-  let mut flip_3 = false;
+  let mut trainers = trainer::load_trainers();
+  // The trainer walking up to challenge the player
+  let mut challenger: Option<usize> = None;
+  // The trainer the player is currently battling
+  let mut trainer_battle: Option<usize> = None;
+  // Whether the challenger has said their piece yet
+  let mut challenge_heard = false;
+  let npcs = dialogue::load_npcs();
+  let conversations = dialogue::load_dialogue();
+  // The text box currently on screen
+  let mut talk: Option<dialogue::Dialogue> = None;
+  let mut shop_menu = shop::Shop::new();
+  let doors = overworld::load_doors();
+  let quests = quest::load_quests();
+  let mut journal_active = false;
+  let mut journal_scroll: usize = 0;
 
   // Tracking time
   let time_count = Instant::now();
@@ -315,21 +468,8 @@ fn run(
 
   let mut player_box = Rect::new(player.x(), player.y(), player.height(), player.width());
 
-  // Create roaming npc players
-  let npc_player1 = Player::create(
-    Rect::new(480,612,TILE_SIZE * 2 as u32,TILE_SIZE * 2 as u32),
-    texture_creator.load_texture("images/single_npc.png")?,
-  );
-
-  let npc_player2 = Player::create(
-    Rect::new(510,430,TILE_SIZE * 2 as u32,TILE_SIZE * 2 as u32),
-    texture_creator.load_texture("images/single_npc.png")?,
-  );
-
-  let npc_player3 = Player::create(
-    Rect::new(992,240,TILE_SIZE * 2 as u32,TILE_SIZE * 2 as u32),
-    texture_creator.load_texture("images/single_npc.png")?,
-  );
+  // Sprite for the trainers who patrol
+  let npc_walking = texture_creator.load_texture("images/single_npc.png")?;
 
   let mut gym_one_maze = maze::Maze::create_random_maze(16, 9);
   let mut gym_two_maze = maze::Maze::create_random_maze(9, 6);
@@ -356,6 +496,11 @@ fn run(
             Keycode::Right => keypress_timer = 0.0,
             Keycode::Return => keypress_timer = 0.0,
             Keycode::M => keypress_timer = 0.0,
+            Keycode::B => keypress_timer = 0.0,
+            Keycode::E => keypress_timer = 0.0,
+            Keycode::Backspace => keypress_timer = 0.0,
+            Keycode::F => keypress_timer = 0.0,
+            Keycode::J => keypress_timer = 0.0,
             _ => {},
           }
         }
@@ -425,6 +570,7 @@ fn run(
         }
         if keystate.contains(&Keycode::Return) {
           if keypress_timer == 0.0 {
+            battle_draw.difficulty = ai::difficulty_from_choice(difficulty_choice);
             wincan.set_draw_color(Color::RGBA(0, 0, 0, 20));
             for _i in 0..100 {
               wincan.fill_rect(screen)?;
@@ -438,6 +584,57 @@ fn run(
       }
 
       Map::Overworld => {
+        // Beaten trainers stay beaten; anyone who won goes back to where they started
+        if let Some(i) = trainer_battle.take() {
+          if battle_draw.transcript.result.as_deref() == Some(transcript::PLAYER_WON) {
+            game_flags.set(&flags::defeated(&trainers[i].name));
+          } else {
+            trainers[i].return_home();
+          }
+        }
+
+        // Monsters that levelled up in the last battle get the chance to evolve
+        if !battle_draw.level_ups.is_empty() {
+          evolution_queue = evolution::by_level(&evolutions, &battle_state.player_team, &battle_draw.level_ups);
+          battle_draw.level_ups.clear();
+        }
+        if !evolution_queue.is_empty() {
+          let index = evolution_queue[0].index;
+          let name = battle_state.player_team[index].name.clone();
+          match &evolution_message {
+            Some(f) => evolution::draw_evolution(wincan, &battle_draw, &name, None, f)?,
+            None => {
+              let f = format!("What? {} is evolving!", name);
+              evolution::draw_evolution(wincan, &battle_draw, &name, Some(&evolution_queue[0].into), &f)?;
+            }
+          }
+          if keystate.contains(&Keycode::Return) {
+            if keypress_timer == 0.0 {
+              if evolution_message.is_some() {
+                evolution_queue.remove(0);
+                evolution_message = None;
+              } else {
+                let pending = &evolution_queue[0];
+                evolution::evolve(&mut battle_state.player_team[index], &pending.into);
+                if let Some(item) = &pending.item {
+                  bag.consume(item);
+                }
+                evolution_message = Some(format!("Congratulations! {} evolved into {}!", name, pending.into));
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Backspace) && evolution_message.is_none() {
+            evolution_message = Some(format!("Huh? {} stopped evolving!", name));
+          }
+          continue;
+        }
+
         wincan.set_draw_color(Color::RGBA(0, 128, 128, 255));
         overworld::draw_overworld(wincan)?;
         let spawnable_areas = overworld::mark_rectangles();
@@ -466,6 +663,10 @@ fn run(
         let home_box = Rect::new(610, 250, 150, 140);
         wincan.copy(&home, None, home_box)?;
 
+        // Create Shop
+        let shop_box = Rect::new(1120, 76, 130, 120);
+        wincan.copy(&shop_texture, None, shop_box)?;
+
         // Create front of gym box for each gym
         // LETS GET THESE TO BE TIGHTER
         let front_of_gym_1_box = Rect::new(400,250,20,5);
@@ -476,20 +677,331 @@ fn run(
         //Create front of building box for buildings
         let front_of_hospital_box = Rect::new(110, 600, 20, 5);
         let front_of_home_box = Rect::new(680,400,20,5);
+        let front_of_shop_box = Rect::new(1175, 196, 20, 5);
+
+        // Patrolling trainers keep walking unless one of them is busy challenging the player
+        if challenger.is_none() && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0) {
+          for t in trainers.iter_mut() {
+            t.patrol_step();
+          }
+        }
+        trainer::draw_trainers(wincan, &trainers, &npc_static, &npc_walking)?;
+        for npc in npcs.iter() {
+          wincan.copy(&npc_static, None, npc.rect)?;
+        }
+
+        // An open text box takes every key press until it closes
+        let mut talk_finished = false;
+        if let Some(d) = &mut talk {
+          x_vel = 0;
+          y_vel = 0;
+          wincan.copy(player.texture(), None, player_box)?;
+          d.draw(wincan, battle_draw.font)?;
+          wincan.present();
+          if keystate.contains(&Keycode::W)
+            || keystate.contains(&Keycode::Up)
+            || keystate.contains(&Keycode::S)
+            || keystate.contains(&Keycode::Down)
+          {
+            if keypress_timer == 0.0 {
+              d.toggle();
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Return) {
+            if keypress_timer == 0.0 {
+              let progress = flags::Progress {
+                badges: trainer::badges(&trainers, &game_flags),
+                team: &battle_state.player_team,
+                flags: &game_flags,
+              };
+              talk_finished = !d.advance(battle_draw.font, &conversations, &progress)?;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          // A challenger's battle starts as soon as they finish talking
+          if !talk_finished || challenger.is_none() {
+            if talk_finished {
+              talk = None;
+            }
+            continue;
+          }
+        }
+        if talk_finished {
+          talk = None;
+        }
+
+        // Finished quests pay out as soon as nothing else is going on
+        if challenger.is_none() {
+          let progress = flags::Progress {
+            badges: trainer::badges(&trainers, &game_flags),
+            team: &battle_state.player_team,
+            flags: &game_flags,
+          };
+          if let Some(q) = quest::newly_completed(&quests, &progress) {
+            game_flags.set(&flags::quest_done(&quests[q].id));
+            battle_draw.money += quests[q].reward_money;
+            if let Some(item) = &quests[q].reward_item {
+              bag.add(item, 1);
+            }
+            talk = Some(dialogue::Dialogue::new(battle_draw.font, "Journal", &quests[q].reward_message())?);
+            continue;
+          }
+        }
+
+        // A trainer who spotted the player walks up, says their piece, then starts the battle
+        if let Some(i) = challenger {
+          x_vel = 0;
+          y_vel = 0;
+          wincan.copy(player.texture(), None, player_box)?;
+          if trainers[i].step_toward(&player_box) {
+            if !challenge_heard {
+              talk = Some(dialogue::Dialogue::new(battle_draw.font, &trainers[i].title(), &trainers[i].before)?);
+              challenge_heard = true;
+            } else {
+              // Double battle trainers only fight two on two if the player has two monsters to send out
+              let double = trainers[i].double
+                && battle_state.player_team.iter().filter(|d| d.hp > 0.0).count() >= battle::DOUBLE_ACTIVE;
+              let enemy_team = trainers[i].enemy_team();
+              if double {
+                // Put the monsters that can still fight in the two active slots
+                battle_state.player_team.sort_by_key(|d| d.hp <= 0.0);
+              }
+
+              let enemy_monster = enemy_team[0].0.clone();
+              battle_draw.enemy_name = enemy_monster.clone();
+              let player_monster = next_available_mon(&battle_state.player_team);
+              battle_draw.player_name = player_monster.clone();
+
+              battle_state = monster::BattleState {
+                player_turn: battle::turn_calc(&monsters_map, &battle_state),
+                player_team: battle_state.player_team.clone(),
+                enemy_team: enemy_team.clone(),
+                enemy_level: trainers[i].level,
+                wild: false,
+                weather: None,
+                double,
+                self_attack_stages: 0,
+                self_defense_stages: 0,
+                opp_attack_stages: 0,
+                opp_defense_stages: 0,
+              };
+
+              loaded_map = Map::Battle;
+              battle_draw.enemy_health = 100.0;
+              battle_draw.weather = None;
+              battle_draw.double = if double {
+                Some(battle::DoubleDraw {
+                  player_name: String::new(),
+                  player_health: 0.0,
+                  enemy_name: String::new(),
+                  enemy_health: 0.0,
+                  acting: 0,
+                  target: None,
+                })
+              } else {
+                None
+              };
+              double_picks.clear();
+              battle::sync_draw(&battle_state, &mut battle_draw);
+              challenger = None;
+              challenge_heard = false;
+              trainer_battle = Some(i);
+              battle_draw.prize = trainers[i].prize();
+              battle_draw.profile = trainers[i].profile;
+              battle_draw.weights = ai_weights[ai::profile_weights(trainers[i].profile)];
+
+              wincan.present();
+              wincan.clear();
+              battle::draw_battle(wincan, &battle_draw, Some(current_choice as usize), None)?;
+              battle::start_battle(wincan, &mut battle_state, &mut battle_draw, rand::random())?;
+              steps.start_grace();
+              continue;
+            }
+          }
+          wincan.present();
+          continue;
+        }
+
+        if bag_active {
+          battle::draw_bag_menu(
+            wincan,
+            &battle_draw,
+            &battle_state,
+            &bag,
+            bag_choice,
+            bag_target,
+            bag_message.clone(),
+          )?;
+          if keystate.contains(&Keycode::W) || keystate.contains(&Keycode::Up) {
+            if keypress_timer == 0.0 {
+              match bag_target {
+                Some(t) => {
+                  bag_target = Some(if t == 0 { battle_state.player_team.len() - 1 } else { t - 1 });
+                }
+                None => {
+                  bag_choice = if bag_choice == 0 { bag.items.len().max(1) - 1 } else { bag_choice - 1 };
+                }
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::S) || keystate.contains(&Keycode::Down) {
+            if keypress_timer == 0.0 {
+              match bag_target {
+                Some(t) => {
+                  bag_target = Some((t + 1) % battle_state.player_team.len());
+                }
+                None => {
+                  bag_choice = (bag_choice + 1) % bag.items.len().max(1);
+                }
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Backspace) {
+            if keypress_timer == 0.0 {
+              // Back out of choosing a target first, then out of the bag
+              if bag_target.is_some() {
+                bag_target = None;
+              } else {
+                bag_active = false;
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Return) {
+            if keypress_timer == 0.0 {
+              if bag_choice < bag.items.len() {
+                let item = &items_map[&bag.items[bag_choice].0];
+                if item.needs_target() && bag_target.is_none() {
+                  bag_target = Some(0);
+                } else {
+                  match inventory::use_item(item, &mut battle_state, bag_target.unwrap_or(0), false, &mut thread_rng()) {
+                    Ok(inventory::ItemUse::Used(f))
+                    | Ok(inventory::ItemUse::Failed(f))
+                    | Ok(inventory::ItemUse::Caught(f)) => {
+                      bag.consume(&item.name);
+                      bag_message = Some(f);
+                    }
+                    Ok(inventory::ItemUse::Equipped(f, previous)) => {
+                      bag.consume(&item.name);
+                      if let Some(p) = previous {
+                        bag.add(&p, 1);
+                      }
+                      bag_message = Some(f);
+                    }
+                    Ok(inventory::ItemUse::Repel(count)) => {
+                      bag.consume(&item.name);
+                      steps.repel(count);
+                      bag_message = Some(format!("Wild monsters will stay away for {} steps.", count));
+                    }
+                    Ok(inventory::ItemUse::Evolve(target)) => {
+                      match evolution::by_item(&evolutions, &battle_state.player_team[target], &item.name) {
+                        Some(into) => {
+                          evolution_queue.push(evolution::Pending {
+                            index: target,
+                            into,
+                            item: Some(item.name.clone()),
+                          });
+                          bag_active = false;
+                          menu_active = false;
+                        }
+                        None => bag_message = Some(String::from("It won't have any effect.")),
+                      }
+                    }
+                    Err(f) => bag_message = Some(f),
+                  }
+                  bag_target = None;
+                  bag_choice = bag_choice.min(bag.items.len().max(1) - 1);
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          continue;
+        }
 
-        // Create several static npcs
-        let npc_static_box1 = Rect::new(490,230,32,32);
-        wincan.copy(&npc_static, None, npc_static_box1)?;
-        let npc_static_box2 = Rect::new(890,430,32,32);
-        wincan.copy(&npc_static, None, npc_static_box2)?;
-        let npc_static_box3 = Rect::new(560,65,32,32);
-        wincan.copy(&npc_static, None, npc_static_box3)?;
-        let npc_static_box4 = Rect::new(322, 330,32,32);
-        wincan.copy(&npc_static, None, npc_static_box4)?;
-        let npc_static_box5 = Rect::new(240,480,32,32);
-        wincan.copy(&npc_static, None, npc_static_box5)?;
-        let npc_static_box6 = Rect::new(880,180,32,32);
-        wincan.copy(&npc_static, None, npc_static_box6)?;
+        if keystate.contains(&Keycode::J) {
+          if keypress_timer == 0.0 {
+            journal_active = !journal_active;
+            journal_scroll = 0;
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+
+        if journal_active {
+          let progress = flags::Progress {
+            badges: trainer::badges(&trainers, &game_flags),
+            team: &battle_state.player_team,
+            flags: &game_flags,
+          };
+          quest::draw_journal(wincan, &battle_draw, &quests, &progress, journal_scroll)?;
+          let started = quests.iter().filter(|d| d.started(&progress)).count();
+          if keystate.contains(&Keycode::W) || keystate.contains(&Keycode::Up) {
+            if keypress_timer == 0.0 {
+              journal_scroll = journal_scroll.saturating_sub(1);
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::S) || keystate.contains(&Keycode::Down) {
+            if keypress_timer == 0.0 {
+              if journal_scroll + 1 < started {
+                journal_scroll += 1;
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          continue;
+        }
 
         if keystate.contains(&Keycode::M) {
           menu_active = true;
@@ -497,6 +1009,13 @@ fn run(
         }
 
         if menu_active {
+          if keystate.contains(&Keycode::B) {
+            bag_active = true;
+            bag_choice = 0;
+            bag_target = None;
+            bag_message = None;
+            continue;
+          }
           battle::draw_monster_menu(
             wincan,
             &battle_draw,
@@ -659,13 +1178,13 @@ fn run(
                 menu_active = false;
                 menu_selected_choice = None;
                 //selection_buffer = BUFFER_FRAMES;
-                battle_state.player_team = battle::verify_team(&battle_state.player_team);
+                battle_state.player_team = monster::verify_team(&battle_state.player_team);
                 continue;
               }
               match menu_selected_choice {
                 Some(choice) => {
                   if choice != menu_choice {
-                    battle_state.player_team.swap(choice, menu_choice);
+                    battle::reorder_team(&mut battle_state, &mut battle_draw, choice, menu_choice);
                     menu_selected_choice = None;
                   }
                 }
@@ -720,49 +1239,6 @@ fn run(
         // Try to move vertically
         player_box.set_y(player_box.y() + y_vel);
 
-        // Three NPCs are moving horizontally
-        let mut npc1_box = Rect::new(
-          npc_player1.x(),
-          npc_player1.y(),
-          npc_player1.height(),
-          npc_player1.width(),
-        );
-        let mut npc2_box = Rect::new(
-          npc_player2.x(),
-          npc_player2.y(),
-          npc_player2.height(),
-          npc_player2.width(),
-        );
-        let mut npc3_box = Rect::new(
-          npc_player3.x(),
-          npc_player3.y(),
-          npc_player3.height(),
-          npc_player3.width(),
-        );
-        npc1_box.set_x((npc1_box.x() + delta_x_npc1).clamp(480, 600));
-        npc2_box.set_x((npc2_box.x() + delta_x_npc2).clamp(510, 640));
-        npc3_box.set_x((npc3_box.x() + delta_x_npc3).clamp(992, 1117));
-
-        if npc1_box.x() == 600  { flip_1 = true; }
-        if npc1_box.x() == 480 { flip_1 = false; }
-        if flip_1 == false && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc1 += 1; }
-        if flip_1 == true && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc1 -= 1;}
-
-        if npc2_box.x() == 640  { flip_2 = true; }
-        if npc2_box.x() == 510 { flip_2 = false; }
-        if flip_2 == false && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc2 += 1; }
-        if flip_2 == true && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc2 -= 1;}
-        
-        if npc3_box.x() == 1117  { flip_3 = true; }
-        if npc3_box.x() == 992 { flip_3 = false; }
-        if flip_3 == false && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc3 += 1; }
-        if flip_3 == true && ((elapsed * 100.0).round() % (DELTA_TIME * 100.0).round() == 0.0)
-          { delta_x_npc3 -= 1;}
         // Check for collision between player and gyms as well as cam bounds(need to consider trees)
         // Use the "go-back" approach to collision resolution
         if check_collision(&player_box, &gym_1_box)
@@ -771,6 +1247,9 @@ fn run(
           || check_collision(&player_box, &gym_4_box)
           || check_collision(&player_box, &hospital_box)
           || check_collision(&player_box, &home_box)
+          || check_collision(&player_box, &shop_box)
+          || trainers.iter().any(|t| check_collision(&player_box, &t.rect))
+          || npcs.iter().any(|d| check_collision(&player_box, &d.rect))
           || player_box.left() < 0
           || player_box.right() > CAM_W as i32
           || player_box.top() < 64
@@ -780,10 +1259,25 @@ fn run(
           player_box.set_y(player_box.y() - y_vel);
         }
 
+        // Doors stay shut until the player has made enough progress, and say why when tried
+        let progress = flags::Progress {
+          badges: trainer::badges(&trainers, &game_flags),
+          team: &battle_state.player_team,
+          flags: &game_flags,
+        };
+        let mut locked_door: Option<String> = None;
+        let mut door_open = |door: &str| match overworld::door_locked(&doors, door, &progress) {
+          Some(f) => {
+            locked_door = Some(f);
+            false
+          }
+          None => true,
+        };
+
         if check_collision(&player_box, &front_of_gym_1_box)
           {
             gym::display_gym_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("gym_one")
             {
               loaded_map = Map::GymOne;
               player_box.set_x(1200);
@@ -793,7 +1287,7 @@ fn run(
           if check_collision(&player_box, &front_of_gym_2_box)
           {
             gym::display_gym_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("gym_two")
             {
               loaded_map = Map::GymTwo;
               player_box.set_x(1200);
@@ -804,7 +1298,7 @@ fn run(
           if check_collision(&player_box, &front_of_gym_3_box)
           {
             gym::display_gym_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("gym_three")
             {
               loaded_map = Map::GymThree;
               player_box.set_x(1200);
@@ -815,7 +1309,7 @@ fn run(
           if check_collision(&player_box, &front_of_gym_4_box)
           {
             gym::display_gym_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("gym_four")
             {
               loaded_map = Map::GymFour;
               player_box.set_x(1200);
@@ -827,7 +1321,7 @@ fn run(
           if check_collision(&player_box, &front_of_hospital_box)
           {
             overworld::display_building_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("hospital")
             {
               loaded_map = Map::Hospital;
               player_box.set_x(1200);
@@ -838,7 +1332,7 @@ fn run(
           if check_collision(&player_box, &front_of_home_box)
           {
             overworld::display_building_menu(wincan)?;
-            if keystate.contains(&Keycode::Y)
+            if keystate.contains(&Keycode::Y) && door_open("home")
             {
               loaded_map = Map::Home;
               player_box.set_x(1200);
@@ -846,11 +1340,29 @@ fn run(
             }
           }
 
-        for i in &spawnable_areas {
+          if check_collision(&player_box, &front_of_shop_box)
+          {
+            overworld::display_building_menu(wincan)?;
+            if keystate.contains(&Keycode::Y) && door_open("shop")
+            {
+              loaded_map = Map::Shop;
+              shop_menu = shop::Shop::new();
+              x_vel = 0;
+              y_vel = 0;
+            }
+          }
+
+        if let Some(f) = locked_door {
+          talk = Some(dialogue::Dialogue::new(battle_draw.font, "", &f)?);
+        }
+
+        // Encounters come from steps taken inside a zone, so standing still never starts a battle
+        let walked = (x_vel.abs() + y_vel.abs()) as u32;
+        for (zone, i) in &spawnable_areas {
           let test_result = check_within(&player_box, i);
-          if test_result == true && random_spawn() 
-            && ((elapsed  * 100.0).round()) % ((DELTA_TIME* 100.0).round()) == 0.0 {
-            println!("check time step: {:.2}", elapsed);
+          let rate = encounter_rates.get(zone).copied().unwrap_or(encounter::DEFAULT_RATE);
+          if test_result && steps.walk(walked, rate, &mut thread_rng()) {
+            steps.start_grace();
             let screen = Rect::new(0, 0, CAM_W, CAM_H);
             wincan.copy(player.texture(), None, player_box)?;
             wincan.set_draw_color(Color::RGBA(0, 0, 0, 15));
@@ -860,10 +1372,24 @@ fn run(
             }
             loaded_map = Map::Battle;
             battle_draw.enemy_health = 100.0;
+            battle_draw.weather = None;
+            battle_draw.double = None;
+
+            // Zones without an encounter table fall back to any monster at the starting level
+            let progress = flags::Progress {
+              badges: trainer::badges(&trainers, &game_flags),
+              team: &battle_state.player_team,
+              flags: &game_flags,
+            };
+            let (enemy_monster, enemy_level) = match encounters
+              .get(zone)
+              .and_then(|table| encounter::roll_encounter(table, &progress, &mut thread_rng()))
+            {
+              Some(found) => found,
+              None => (select_random_team(&all_monsters, 1)[0].0.clone(), monster::STARTING_LEVEL),
+            };
+            let enemy_team = vec![(enemy_monster.clone(), 100.0)];
 
-            let enemy_team = select_random_team(&all_monsters, 1);
-
-            let enemy_monster = enemy_team[0].0.clone();
             battle_draw.enemy_name = enemy_monster.clone();
             let player_monster = next_available_mon(&player_team);
             battle_draw.player_name = player_monster.clone();
@@ -873,11 +1399,18 @@ fn run(
                 >= monsters_map[&enemy_monster].attack_stat,
               player_team: battle_state.player_team.clone(),
               enemy_team: enemy_team.clone(),
+              enemy_level,
+              wild: true,
+              weather: None,
+              double: false,
               self_attack_stages: 0,
               self_defense_stages: 0,
               opp_attack_stages: 0,
               opp_defense_stages: 0,
             };
+            battle_draw.profile = ai::Profile::Standard;
+            battle_draw.weights = ai_weights["default"];
+            battle::start_battle(wincan, &mut battle_state, &mut battle_draw, rand::random())?;
 
             player_box.set_x(player_box.x() - x_vel);
             player_box.set_y(player_box.y() - y_vel);
@@ -885,118 +1418,231 @@ fn run(
           }
         }
 
-        // Check for collision between player and gyms as well as cam bounds
-        // Use the "go-back" approach to collision resolution
-        if check_collision(&player_box, &npc_static_box1)
-          || check_collision(&player_box, &npc_static_box2)
-          || check_collision(&player_box, &npc_static_box3)
-          || check_collision(&player_box, &npc_static_box4)
-          || check_collision(&player_box, &npc_static_box5)
-          || check_collision(&player_box, &npc_static_box6)
-          || check_collision(&player_box, &npc1_box)
-          || check_collision(&player_box, &npc2_box)
-          || check_collision(&player_box, &npc3_box)
+        // Trainers who haven't been beaten challenge the player as soon as they see them
+        if let Some(i) = trainers
+          .iter()
+          .position(|t| !game_flags.has(&flags::defeated(&t.name)) && t.spots(&player_box))
         {
-          wincan.copy(player.texture(), None, player_box)?;
-          wincan.copy(npc_player1.texture(), None, npc1_box)?;
-          wincan.copy(npc_player2.texture(), None, npc2_box)?;
-          wincan.copy(npc_player3.texture(), None, npc3_box)?;
+          challenger = Some(i);
+          x_vel = 0;
+          y_vel = 0;
+        }
 
-          overworld::display_menu(wincan, player_box.x(), player_box.y())?;
+        wincan.copy(player.texture(), None, player_box)?;
 
+        // Beaten trainers and townsfolk talk when the player presses F next to them
+        let nearby_trainer = trainers
+          .iter()
+          .find(|t| game_flags.has(&flags::defeated(&t.name)) && t.reach().has_intersection(player_box));
+        let nearby_npc = npcs.iter().find(|d| d.reach().has_intersection(player_box));
+        if nearby_trainer.is_some() || nearby_npc.is_some() {
+          overworld::display_menu(wincan, player_box.x(), player_box.y())?;
           if keystate.contains(&Keycode::F) {
-            let enemy_team = select_random_team(&all_monsters, 2);
-            
-            let enemy_monster = enemy_team[0].0.clone();
-            battle_draw.enemy_name = enemy_monster.clone();
-            let player_monster = next_available_mon(&battle_state.player_team);
-            battle_draw.player_name = player_monster.clone();
-
-            battle_state = monster::BattleState {
-              player_turn: battle::turn_calc(&monsters_map, &battle_state),
-              player_team: battle_state.player_team.clone(),
-              enemy_team: enemy_team.clone(),
-              self_attack_stages: 0,
-              self_defense_stages: 0,
-              opp_attack_stages: 0,
-              opp_defense_stages: 0,
+            if keypress_timer == 0.0 {
+              let progress = flags::Progress {
+                badges: trainer::badges(&trainers, &game_flags),
+                team: &battle_state.player_team,
+                flags: &game_flags,
+              };
+              talk = match (nearby_trainer, nearby_npc) {
+                // Trainers without a conversation of their own repeat their post-battle line
+                (Some(t), _) => match dialogue::Dialogue::start(battle_draw.font, &conversations, &t.name, &t.title(), &progress)? {
+                  Some(d) => Some(d),
+                  None => Some(dialogue::Dialogue::new(battle_draw.font, &t.title(), &t.after)?),
+                },
+                (None, Some(npc)) => {
+                  dialogue::Dialogue::start(battle_draw.font, &conversations, &npc.conversation, &npc.name, &progress)?
+                }
+                (None, None) => None,
+              };
+              // Talking to someone counts as soon as the conversation opens
+              match nearby_npc {
+                Some(npc) if nearby_trainer.is_none() && talk.is_some() => {
+                  game_flags.set(&flags::talked(&npc.name));
+                }
+                _ => {}
+              }
+            } else {
+              continue;
             };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+        }
 
-            loaded_map = Map::Battle;
-            battle_draw.enemy_health = 100.0;
-
-            wincan.present();
-            wincan.clear();
-            battle::draw_battle(wincan, &battle_draw, Some(current_choice as usize), None)?;
-
-            x_vel = 0;
-            y_vel = 0;
+        wincan.present();
+      },
 
-            continue;
+      Map::Battle => {
+        if log_active {
+          battle::draw_transcript(wincan, &battle_draw, log_scroll, log_message.clone())?;
+          if keystate.contains(&Keycode::W) || keystate.contains(&Keycode::Up) {
+            if keypress_timer == 0.0 {
+              log_scroll = log_scroll.saturating_sub(1);
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::S) || keystate.contains(&Keycode::Down) {
+            if keypress_timer == 0.0 {
+              let lines = battle_draw.transcript.lines().len();
+              log_scroll = (log_scroll + 1).min(lines.saturating_sub(battle::LOG_ROWS));
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::E) {
+            if keypress_timer == 0.0 {
+              log_message = Some(match battle_draw.transcript.export() {
+                Ok(path) => format!("Saved to {}.txt and .json", path),
+                Err(e) => e,
+              });
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Backspace) {
+            log_active = false;
+          }
+          continue;
+        }
+        if bag_active {
+          battle::draw_bag_menu(
+            wincan,
+            &battle_draw,
+            &battle_state,
+            &bag,
+            bag_choice,
+            bag_target,
+            bag_message.clone(),
+          )?;
+          if keystate.contains(&Keycode::W) || keystate.contains(&Keycode::Up) {
+            if keypress_timer == 0.0 {
+              match bag_target {
+                Some(t) => {
+                  bag_target = Some(if t == 0 { battle_state.player_team.len() - 1 } else { t - 1 });
+                }
+                None => {
+                  bag_choice = if bag_choice == 0 { bag.items.len().max(1) - 1 } else { bag_choice - 1 };
+                }
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::S) || keystate.contains(&Keycode::Down) {
+            if keypress_timer == 0.0 {
+              match bag_target {
+                Some(t) => {
+                  bag_target = Some((t + 1) % battle_state.player_team.len());
+                }
+                None => {
+                  bag_choice = (bag_choice + 1) % bag.items.len().max(1);
+                }
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
           }
-
-          //flashing not active when moving away
-
-          if keystate.contains(&Keycode::W)
-            || keystate.contains(&Keycode::Up)
-            || keystate.contains(&Keycode::A)
-            || keystate.contains(&Keycode::Left)
-            || keystate.contains(&Keycode::S)
-            || keystate.contains(&Keycode::Down)
-            || keystate.contains(&Keycode::D)
-            || keystate.contains(&Keycode::Right)
-          {
-            wincan.present();
-
-            x_vel = 0;
-            y_vel = 0;
-
-            continue;
+          if keystate.contains(&Keycode::Backspace) {
+            if keypress_timer == 0.0 {
+              // Back out of choosing a target first, then out of the bag
+              if bag_target.is_some() {
+                bag_target = None;
+              } else {
+                bag_active = false;
+              }
+              bag_message = None;
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Return) {
+            if keypress_timer == 0.0 {
+              if bag_choice < bag.items.len() {
+                let item = &items_map[&bag.items[bag_choice].0];
+                if item.needs_target() && bag_target.is_none() {
+                  bag_target = Some(0);
+                } else {
+                  let target = bag_target.unwrap_or(0);
+                  let used = inventory::use_item(item, &mut battle_state, target, true, &mut battle_draw.rng);
+                  bag_target = None;
+                  match used {
+                    Err(f) => {
+                      bag_message = Some(f);
+                    }
+                    Ok(outcome) => {
+                      // Catching a monster is something quests and conversations can check for
+                      if let inventory::ItemUse::Caught(_) = &outcome {
+                        let caught = battle_state.player_team[battle_state.player_team.len() - 1].name.clone();
+                        game_flags.set(&flags::caught(&caught));
+                        game_flags.set(&flags::caught_type(&monsters_map[&caught].monster_type));
+                      }
+                      // Using an item takes up the player's turn
+                      bag.consume(&item.name);
+                      bag_active = false;
+                      bag_choice = 0;
+
+                      if let Map::Overworld = battle::item_turn(
+                        wincan,
+                        &mut battle_state,
+                        &mut battle_draw,
+                        &monsters_map,
+                        item,
+                        target,
+                        outcome,
+                      )? {
+                        loaded_map = Map::Overworld;
+                        if battle_draw.transcript.result.as_deref() == Some(transcript::BLACKED_OUT) {
+                          // Have the player spawn at the hospital with full health
+                          player_box.set_x(112);
+                          player_box.set_y(604);
+                          battle_draw.player_health = 100.0;
+                        }
+                        continue;
+                      }
+                    }
+                  }
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
           }
-
-          //causes the flashing effect. Every time near npc, screen flashes
-          wincan.present();
-          wincan.present();
-
-          x_vel = 0;
-          y_vel = 0;
-
           continue;
         }
-
-        wincan.copy(player.texture(), None, player_box)?;
-        wincan.copy_ex(
-          npc_player1.texture(),
-          Rect::new(0, 0, 32, 32),
-          Rect::new(npc1_box.x(), npc1_box.y(), 32, 32),
-          0.0,
-          None,
-          flip_1,
-          false,
-        )?;
-        wincan.copy_ex(
-          npc_player2.texture(),
-          Rect::new(0, 0, 32, 32),
-          Rect::new(npc2_box.x(), npc2_box.y(), 32, 32),
-          0.0,
-          None,
-          flip_2,
-          false,
-        )?;
-        wincan.copy_ex(
-          npc_player3.texture(),
-          Rect::new(0, 0, 32, 32),
-          Rect::new(npc3_box.x(), npc3_box.y(), 32, 32),
-          0.0,
-          None,
-          flip_3,
-          false,
-        )?;
-
-        wincan.present();
-      },
-
-      Map::Battle => {
         if menu_active {
           battle::draw_monster_menu(
             wincan,
@@ -1167,52 +1813,22 @@ fn run(
                 menu_active = false;
                 menu_selected_choice = None;
                 //selection_buffer = BUFFER_FRAMES;
-                battle_state.player_team = battle::verify_team(&battle_state.player_team);
-
-                let mut switched_front : &(String, f32) = &(String::from(""), 0.0);
-                for i in 0..battle_state.player_team.len() {
-                  if battle_state.player_team[i].1 > 0.0 {
-                    switched_front = &battle_state.player_team[i];
-                    break;
-                  }
-                }
-
-                if battle_draw.player_name != switched_front.0 {
-                  let new_mon = String::from(switched_front.0.clone());
-                  let f = format!("You switched in {}!", new_mon);
-                  battle_draw.player_name = new_mon.clone();
-                  battle_draw.player_health = switched_front.1;
-                  battle::draw_battle(wincan, &battle_draw, None, Some(f))?;
-
-                  match battle::enemy_battle_turn(
-                    wincan,
-                    &mut battle_state,
-                    &mut battle_draw,
-                    &monsters_map,
-                  )? {
-                    Map::Overworld => {
-                      loaded_map = Map::Overworld;
-    
-                      // Have the player spawn at the hospital with full health
-                      player_box.set_x(112);
-                      player_box.set_y(604);
+                if let Map::Overworld = battle::confirm_team_turn(wincan, &mut battle_state, &mut battle_draw, &monsters_map)? {
+                  loaded_map = Map::Overworld;
 
-                      for i in 0..battle_state.player_team.len() {
-                        battle_state.player_team[i].1 = 100.0;
-                      }
-                      battle_draw.player_health = 100.0;
-                      continue;
-                    }
-                    _ => {}
-                  }
+                  // Have the player spawn at the hospital with full health
+                  player_box.set_x(112);
+                  player_box.set_y(604);
+                  battle_draw.player_health = 100.0;
+                  continue;
                 }
 
                 continue;
               }
               match menu_selected_choice {
                 Some(choice) => {
-                  if choice != menu_choice {
-                    battle_state.player_team.swap(choice, menu_choice);
+                  if choice != menu_choice && battle::can_reorder(&battle_state, &battle_draw, choice, menu_choice) {
+                    battle::reorder_team(&mut battle_state, &mut battle_draw, choice, menu_choice);
                     menu_selected_choice = None;
                   }
                 }
@@ -1238,6 +1854,135 @@ fn run(
           //}
           continue;
         }
+        // Fainted monsters have to be replaced before the battle goes on
+        if battle::needs_replacement(&battle_state, &battle_draw) {
+          menu_active = true;
+          menu_choice = 0;
+          menu_selected_choice = None;
+          continue;
+        }
+        if keystate.contains(&Keycode::L) {
+          log_active = true;
+          log_scroll = 0;
+          log_message = None;
+          continue;
+        }
+        // Double battles pick a move and target for each of the player's monsters before the turn plays out
+        if battle_state.double {
+          battle::draw_battle(wincan, &battle_draw, Some(current_choice as usize), None)?;
+          let (acting, target) = match &battle_draw.double {
+            Some(double) => (double.acting, double.target),
+            None => (0, None),
+          };
+          let foes = battle::alive_slots(&battle_state, false);
+          if keystate.contains(&Keycode::A) || keystate.contains(&Keycode::Left) {
+            if keypress_timer == 0.0 {
+              match (target, &mut battle_draw.double) {
+                (Some(t), Some(double)) => {
+                  double.target = Some(*foes.iter().find(|&&d| d != t).unwrap_or(&t));
+                }
+                _ => {
+                  current_choice = (current_choice + 3) % 4;
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::D) || keystate.contains(&Keycode::Right) {
+            if keypress_timer == 0.0 {
+              match (target, &mut battle_draw.double) {
+                (Some(t), Some(double)) => {
+                  double.target = Some(*foes.iter().find(|&&d| d != t).unwrap_or(&t));
+                }
+                _ => {
+                  current_choice = (current_choice + 1) % 4;
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Backspace) {
+            if keypress_timer == 0.0 {
+              // Back out of choosing a target first, then out of the last monster's pick
+              if let Some(double) = &mut battle_draw.double {
+                if target.is_some() {
+                  double.target = None;
+                } else if let Some((slot, m, _)) = double_picks.pop() {
+                  double.acting = slot;
+                  current_choice = m as i32;
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          if keystate.contains(&Keycode::Return) {
+            if keypress_timer == 0.0 {
+              let attack = monsters_map[&battle_state.player_team[acting].name].moves[current_choice as usize];
+              if target.is_none() && !attack.spread && foes.len() > 1 {
+                if let Some(double) = &mut battle_draw.double {
+                  double.target = Some(foes[0]);
+                }
+              } else {
+                double_picks.push((acting, current_choice as usize, target.unwrap_or(foes[0])));
+                let next = battle::alive_slots(&battle_state, true).into_iter().find(|&i| i > acting);
+                current_choice = 0;
+                if let Some(double) = &mut battle_draw.double {
+                  double.target = None;
+                  double.acting = next.unwrap_or(acting);
+                }
+
+                if next.is_none() {
+                  let result = battle::double_turn(
+                    wincan,
+                    &mut battle_state,
+                    &mut battle_draw,
+                    &monsters_map,
+                    &double_picks,
+                  )?;
+                  double_picks.clear();
+                  if let Map::Overworld = result {
+                    loaded_map = Map::Overworld;
+                    battle_draw.double = None;
+                    if !battle::alive_slots(&battle_state, false).is_empty() {
+                      // Have the player spawn at the hospital with full health
+                      player_box.set_x(112);
+                      player_box.set_y(604);
+                    }
+                    continue;
+                  }
+
+                  // The next turn starts with the first monster that can still fight
+                  let first = battle::alive_slots(&battle_state, true);
+                  if let Some(double) = &mut battle_draw.double {
+                    double.acting = *first.first().unwrap_or(&0);
+                  }
+                }
+              }
+            } else {
+              continue;
+            };
+            keypress_timer += single_elapsed;
+            if keypress_timer >= KEYPRESS_DURATION {
+              keypress_timer = 0.0;
+            }
+          }
+          continue;
+        }
         battle::draw_battle(wincan, &battle_draw, Some(current_choice as usize), None)?;
         if keystate.contains(&Keycode::A) || keystate.contains(&Keycode::Left) {
           if keypress_timer == 0.0 {
@@ -1296,77 +2041,31 @@ fn run(
           menu_active = true;
           continue;
         }
+        if keystate.contains(&Keycode::B) {
+          bag_active = true;
+          bag_choice = 0;
+          bag_target = None;
+          bag_message = None;
+          continue;
+        }
         if keystate.contains(&Keycode::Return) {
           if keypress_timer == 0.0 {
-            battle_state.player_turn = battle::turn_calc(&monsters_map, &battle_state);
             // Battle Logic
-            if battle_state.player_turn {
-              match battle::player_battle_turn(
-                wincan,
-                &mut battle_state,
-                &mut battle_draw,
-                &monsters_map,
-                current_choice as usize,
-              )? {
-                Map::Overworld => {
-                  loaded_map = Map::Overworld;
-                  continue;
-                }
-                _ => {}
-              }
-              
-              if !battle_state.player_turn {
-                match battle::enemy_battle_turn(
-                  wincan,
-                  &mut battle_state,
-                  &mut battle_draw,
-                  &monsters_map,
-                )? {
-                  Map::Overworld => {
-                    loaded_map = Map::Overworld;
-
-                    // Have the player spawn at the hospital with full health
-                    player_box.set_x(112);
-                    player_box.set_y(604);
-                    battle_draw.player_health = 100.0;
-                    continue;
-                  }
-                  _ => {}
-                }
-              }
-            } else {
-              match battle::enemy_battle_turn(
-                wincan,
-                &mut battle_state,
-                &mut battle_draw,
-                &monsters_map,
-              )? {
-                Map::Overworld => {
-                  loaded_map = Map::Overworld;
-                  // Have the player spawn at the hospital with full health
-                  player_box.set_x(112);
-                  player_box.set_y(604);
-                  battle_draw.player_health = 100.0;
-                  continue;
-                }
-                _ => {}
-              }
-
-              if battle_state.player_turn {
-                match battle::player_battle_turn(
-                  wincan,
-                  &mut battle_state,
-                  &mut battle_draw,
-                  &monsters_map,
-                  current_choice as usize,
-                )? {
-                  Map::Overworld => {
-                    loaded_map = Map::Overworld;
-                    continue;
-                  }
-                  _ => {}
-                }
+            if let Map::Overworld = battle::move_turn(
+              wincan,
+              &mut battle_state,
+              &mut battle_draw,
+              &monsters_map,
+              current_choice as usize,
+            )? {
+              loaded_map = Map::Overworld;
+              if battle_draw.transcript.result.as_deref() == Some(transcript::BLACKED_OUT) {
+                // Have the player spawn at the hospital with full health
+                player_box.set_x(112);
+                player_box.set_y(604);
+                battle_draw.player_health = 100.0;
               }
+              continue;
             }
           } else {
             continue;
@@ -1756,6 +2455,112 @@ fn run(
           wincan.present();        
       },
 
+      Map::Shop => {
+        shop::draw_shop(wincan, &battle_draw, &shop_menu, &bag, &items_map, battle_draw.money)?;
+        let listing = shop_menu.listing(&bag, &items_map);
+
+        if keystate.contains(&Keycode::W) || keystate.contains(&Keycode::Up) {
+          if keypress_timer == 0.0 {
+            match shop_menu.quantity {
+              Some(q) => {
+                let item = &items_map[&listing[shop_menu.choice]];
+                let max = shop_menu.max_quantity(&bag, item, battle_draw.money);
+                shop_menu.quantity = Some(if q >= max { 1 } else { q + 1 });
+              }
+              None => {
+                shop_menu.choice = if shop_menu.choice == 0 { listing.len().max(1) - 1 } else { shop_menu.choice - 1 };
+              }
+            }
+            shop_menu.message = None;
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+        if keystate.contains(&Keycode::S) || keystate.contains(&Keycode::Down) {
+          if keypress_timer == 0.0 {
+            match shop_menu.quantity {
+              Some(q) => {
+                let item = &items_map[&listing[shop_menu.choice]];
+                let max = shop_menu.max_quantity(&bag, item, battle_draw.money);
+                shop_menu.quantity = Some(if q <= 1 { max } else { q - 1 });
+              }
+              None => {
+                shop_menu.choice = if shop_menu.choice + 1 >= listing.len() { 0 } else { shop_menu.choice + 1 };
+              }
+            }
+            shop_menu.message = None;
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+        if keystate.contains(&Keycode::A)
+          || keystate.contains(&Keycode::Left)
+          || keystate.contains(&Keycode::D)
+          || keystate.contains(&Keycode::Right)
+        {
+          if keypress_timer == 0.0 {
+            if shop_menu.quantity.is_none() {
+              shop_menu.toggle_mode();
+            }
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+        if keystate.contains(&Keycode::Return) {
+          if keypress_timer == 0.0 {
+            match shop_menu.quantity {
+              Some(_) => shop_menu.confirm(&mut bag, &items_map, &mut battle_draw.money),
+              None => {
+                if let Some(name) = listing.get(shop_menu.choice) {
+                  if shop_menu.max_quantity(&bag, &items_map[name], battle_draw.money) == 0 {
+                    shop_menu.message = Some(String::from("You can't afford that."));
+                  } else {
+                    shop_menu.quantity = Some(1);
+                    shop_menu.message = None;
+                  }
+                }
+              }
+            }
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+        if keystate.contains(&Keycode::Backspace) {
+          if keypress_timer == 0.0 {
+            if shop_menu.quantity.is_some() {
+              shop_menu.quantity = None;
+            } else {
+              // Step back out in front of the shop door
+              loaded_map = Map::Overworld;
+              player_box.set_x(1170);
+              player_box.set_y(205);
+            }
+          } else {
+            continue;
+          };
+          keypress_timer += single_elapsed;
+          if keypress_timer >= KEYPRESS_DURATION {
+            keypress_timer = 0.0;
+          }
+        }
+      },
+
       Map::Home => {
           
         let keystate: HashSet<Keycode> = event_pump
@@ -1817,10 +2622,61 @@ fn run(
     }
   }
 
+  save::save_game(&battle_state.player_team, &bag, &game_flags, battle_draw.money)?;
+
+  Ok(())
+}
+
+// Plays two sets of AI weights from data/ai_weights.txt against each other and prints how each did
+fn run_tuning(a: &str, b: &str, battles: u32) -> Result<(), String> {
+  let moves_map = load_moves();
+  let abilities_map = ability::load_abilities();
+  let monsters_map = load_mons(&moves_map, &abilities_map);
+  let ai_weights = ai::load_weights();
+  let weights_a = ai_weights.get(a).ok_or(format!("No weight set named {}", a))?;
+  let weights_b = ai_weights.get(b).ok_or(format!("No weight set named {}", b))?;
+
+  let (wins_a, wins_b) = ai::compare_weights(&monsters_map, weights_a, weights_b, TUNING_DEPTH, battles, 0);
+  let games = 2 * battles;
+  println!("{} won {} of {} games", a, wins_a, games);
+  println!("{} won {} of {} games", b, wins_b, games);
+  println!("{} games were draws", games - wins_a - wins_b);
   Ok(())
 }
 
 fn main() {
+  // `--replay <file>` plays a saved battle back, and `--verify <file>` checks its result without drawing it
+  let args: Vec<String> = std::env::args().collect();
+  // `--tune <set> <set> [battles]` compares two sets of AI weights
+  if args.len() > 3 && args[1] == "--tune" {
+    let battles = args.get(4).and_then(|d| d.parse::<u32>().ok()).unwrap_or(TUNING_BATTLES);
+    if let Err(e) = run_tuning(&args[2], &args[3], battles) {
+      println!("Failed to tune: {}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+  if args.len() > 2 && (args[1] == "--replay" || args[1] == "--verify") {
+    let headless = args[1] == "--verify";
+    let wincan = if headless {
+      init_headless(CAM_W, CAM_H)
+    } else {
+      init(TITLE, VSYNC, CAM_W, CAM_H).map(|d| d.0)
+    };
+    match wincan.and_then(|mut wincan| run_replay(&mut wincan, &args[2], headless)) {
+      Ok(true) => println!("Replay matches its recorded result"),
+      Ok(false) => {
+        println!("Replay does not match its recorded result");
+        std::process::exit(1);
+      }
+      Err(e) => {
+        println!("Failed to play replay: {}", e);
+        std::process::exit(1);
+      }
+    }
+    return;
+  }
+
   println!("\nRunning {}:", TITLE);
   print!("\tInitting...");
   match init(TITLE, VSYNC, CAM_W, CAM_H) {
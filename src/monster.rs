@@ -1,17 +1,45 @@
+use crate::ability::Ability;
+use crate::weather::{self, Weather};
 use crate::ActiveMons;
 use std::collections::HashMap;
 
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
+use rand::Rng;
+
 const STAGE_MULT: f32 = 0.125;
-const STAGE_LIMIT: i32 = 6;
+pub const STAGE_LIMIT: i32 = 6;
+// Spread moves are weaker since they hit both foes
+const SPREAD_MULT: f32 = 0.75;
+// Monsters start out, and are caught, at this level
+pub const STARTING_LEVEL: u32 = 5;
+// Experience each surviving monster gets for every foe beaten
+pub const EXP_PER_FOE: u32 = 40;
+// Individual values are rolled between 0 and this, adding up to a tenth to a stat
+pub const IV_MAX: u32 = 31;
+const IV_BONUS: f32 = 0.1;
+// Stats grow by this fraction of the base stat every level past the starting level
+const LEVEL_GROWTH: f32 = 0.02;
+const NATURE_MULT: f32 = 0.1;
+
+// Raises one stat and lowers the other, or leaves both alone
+#[derive(Clone, Copy, PartialEq)]
+pub enum Nature {
+    Hardy,
+    Lonely,
+    Bold,
+    Docile,
+}
+
+const NATURES: [Nature; 4] = [Nature::Hardy, Nature::Lonely, Nature::Bold, Nature::Docile];
 
 pub struct Monster<'a> {
     pub attack_stat: u32,
     pub defense_stat: u32,
     pub moves: Vec<&'a Move>,
     pub monster_type: String,
+    pub ability: Option<&'a Ability>,
 }
 
 pub struct Move {
@@ -23,27 +51,132 @@ pub struct Move {
     pub opp_defense_stages: i32,
     pub attack_type: String,
     pub effect: String,
+    pub move_effect: MoveEffect,
+    // Hits both foes in a double battle
+    pub spread: bool,
+}
+
+// Typed effects a move has on top of its damage and stage changes
+#[derive(Clone, Copy, PartialEq)]
+pub enum MoveEffect {
+    None,
+    // Heals the user by a percentage of its max health
+    Heal(f32),
+    // Heals the user by a percentage of the damage dealt
+    Drain(f32),
+    // Hurts the user by a percentage of the damage dealt
+    Recoil(f32),
+    // Hits a random number of times between the bounds (inclusive)
+    MultiHit(u32, u32),
+    // Always deals the same damage, ignoring stats and types
+    FixedDamage(f32),
+    // Sets the battle's weather for a number of turns
+    SetWeather(Weather, u32),
+}
+
+// What a move did once its effect was resolved
+pub struct MoveResult {
+    pub damage: f32,
+    pub hits: u32,
+    pub user_health_change: f32,
 }
 
+// A monster in a team, either one of the player's or one of the enemy's (name, health) pairs
+pub trait TeamMember: Clone {
+    fn name(&self) -> &str;
+    // Health as a percentage (0-100)
+    fn health(&self) -> f32;
+    fn set_health(&mut self, health: f32);
+}
+
+impl TeamMember for (String, f32) {
+    fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn health(&self) -> f32 {
+        self.1
+    }
+
+    fn set_health(&mut self, health: f32) {
+        self.1 = health;
+    }
+}
+
+// Moves fainted monsters behind the ones still standing, keeping each group's order
+pub fn verify_team<T: TeamMember>(v: &[T]) -> Vec<T>{
+    let mut alive : Vec<T> = Vec::new();
+    let mut dead : Vec<T> = Vec::new();
+    for item in v.iter() {
+      if item.health() > 0.0 {
+        alive.push(item.clone());
+      }
+      else {
+        dead.push(item.clone());
+      }
+    }
+    alive.append(&mut dead);
+    alive
+}
+
+#[derive(Clone)]
 pub struct BattleState {
     pub player_turn: bool,
     pub player_team:  Vec<ActiveMons>,
     pub enemy_team: Vec<(String, f32)>,
+    // Every monster on the enemy's team fights at this level
+    pub enemy_level: u32,
+    pub wild: bool,
+    // The current weather and how many turns it has left
+    pub weather: Option<(Weather, u32)>,
+    // Two monsters are active per side, the first two of each team
+    pub double: bool,
     pub self_attack_stages: i32,
     pub self_defense_stages: i32,
     pub opp_attack_stages: i32,
     pub opp_defense_stages: i32,
 }
 
+fn parse_value<T: std::str::FromStr>(value: Option<&&str>, effect: &str) -> Result<T, String> {
+    match value {
+        Some(d) => d.parse::<T>().map_err(|_| format!("{} should be a number in {}", d, effect)),
+        None => Err(format!("Missing a value in {}", effect)),
+    }
+}
+
+// Effects are written as a name and its values, e.g. "Drain 50" or "MultiHit 2-5"
+fn parse_move_effect(effect: &str) -> Result<MoveEffect, String> {
+    let v = effect.split(" ").collect::<Vec<&str>>();
+    match v[0] {
+        "None" => Ok(MoveEffect::None),
+        "Heal" => parse_value(v.get(1), effect).map(MoveEffect::Heal),
+        "Drain" => parse_value(v.get(1), effect).map(MoveEffect::Drain),
+        "Recoil" => parse_value(v.get(1), effect).map(MoveEffect::Recoil),
+        "MultiHit" => {
+            let hits = v.get(1).map(|d| d.split("-").collect::<Vec<&str>>()).unwrap_or_default();
+            Ok(MoveEffect::MultiHit(parse_value(hits.first(), effect)?, parse_value(hits.get(1), effect)?))
+        }
+        "Fixed" => parse_value(v.get(1), effect).map(MoveEffect::FixedDamage),
+        "Weather" => {
+            let weather = weather::parse_weather(v.get(1).unwrap_or(&""))?;
+            Ok(MoveEffect::SetWeather(weather, parse_value(v.get(2), effect)?))
+        }
+        _ => Err(format!("Unknown move effect {}", effect)),
+    }
+}
+
 pub fn load_moves() -> HashMap<String, Move> {
     let reader = BufReader::new(File::open("./data/moves.txt").expect("Cannot open moves.txt"));
     let mut moves = HashMap::new();
-    for line in reader.lines().skip(1) {
+    for (n, line) in reader.lines().skip(1).enumerate() {
         let line = line;
         let v = line.unwrap();
         let v = v.split(",").collect::<Vec<&str>>();
         let v: Vec<String> = v.into_iter().map(|d| String::from(d)).collect();
-        
+
+        // Line numbers count the header
+        let move_effect = parse_move_effect(v.get(8).map(|d| d.as_str()).unwrap_or("None"))
+            .unwrap_or_else(|e| panic!("moves.txt line {}: {}", n + 2, e));
         let mov = Move {
             name: v[0].clone(),
             damage: v[1].parse::<u32>().unwrap(),
@@ -53,12 +186,17 @@ pub fn load_moves() -> HashMap<String, Move> {
             opp_defense_stages: v[5].parse::<i32>().unwrap(),
             attack_type: v[6].clone(),
             effect: v[7].clone(),
+            move_effect,
+            spread: v.get(9).map(|d| d == "AllFoes").unwrap_or(false),
         };
         moves.insert(v[0].clone(), mov);
     }
     moves
 }
-pub fn load_mons<'a>(moves_map: &'a HashMap<String, Move>) -> HashMap<String, Monster<'a>> {
+pub fn load_mons<'a>(
+    moves_map: &'a HashMap<String, Move>,
+    abilities_map: &'a HashMap<String, Ability>,
+) -> HashMap<String, Monster<'a>> {
     let reader =
         BufReader::new(File::open("./data/monsters.txt").expect("Cannot open monsters.txt"));
     let mut mons = HashMap::new();
@@ -67,14 +205,16 @@ pub fn load_mons<'a>(moves_map: &'a HashMap<String, Move>) -> HashMap<String, Mo
         let v = line.unwrap();
         let v = v.split(",").collect::<Vec<&str>>();
         let v: Vec<String> = v.into_iter().map(|d| String::from(d)).collect();
-        let a = &v[4..];
+        let a = &v[4..8];
         let moves = a.into_iter().map(|d| &moves_map[d]).collect();
+        let ability = v.get(8).map(|d| &abilities_map[d]);
 
         let mon = Monster {
             attack_stat: v[1].parse::<u32>().unwrap(),
             defense_stat: v[2].parse::<u32>().unwrap(),
             moves: moves,
             monster_type: v[3].clone(),
+            ability,
         };
         mons.insert(v[0].clone(), mon);
     }
@@ -85,6 +225,9 @@ pub fn str_effectiveness(attack: &Move, defense_type: &String) -> Option<String>
     if attack.damage == 0 {
         return None;
     }
+    if let MoveEffect::FixedDamage(_) = attack.move_effect {
+        return None;
+    }
     
     let attack_type = &attack.attack_type;
     let a = type_effectiveness(&attack_type, defense_type);
@@ -99,6 +242,15 @@ pub fn str_effectiveness(attack: &Move, defense_type: &String) -> Option<String>
     };
 }
 
+// Type multiplier of a move against a monster, 1 for moves that ignore types
+pub fn effectiveness(attack: &Move, defense_type: &String) -> f32 {
+    match attack.move_effect {
+        MoveEffect::FixedDamage(_) => 1.0,
+        _ if attack.damage == 0 => 1.0,
+        _ => type_effectiveness(&attack.attack_type, defense_type),
+    }
+}
+
 fn type_effectiveness(attack_type: &String, defense_type: &String) -> f32 {
     match attack_type.as_str() {
         "Grass" => match defense_type.as_str() {
@@ -157,7 +309,90 @@ fn damage_calc(damage: f32, a: f32, d: f32, stab: f32, typb: f32) -> f32 {
     return (30.0 * damage * (a / d) / 100.0) * stab * typb;
 }
 
+fn weather_bonus(battle_state: &BattleState, attack: &Move) -> f32 {
+    match battle_state.weather {
+        Some((w, _)) => weather::type_multiplier(w, &attack.attack_type),
+        None => 1.0,
+    }
+}
+
+fn set_weather(battle_state: &mut BattleState, attack: &Move) {
+    if let MoveEffect::SetWeather(w, turns) = attack.move_effect {
+        battle_state.weather = Some((w, turns));
+    }
+}
+
+/// Resolves a move's effect on top of the damage of a single hit
+///
+/// * `attack` - The move being used
+/// * `damage` - Damage of one hit, after any hooks
+/// * `user_health` - Health (0-100) of the monster using the move
+/// * `opponent_health` - Health (0-100) of the monster being hit
+pub fn resolve_move_effect(
+    attack: &Move,
+    damage: f32,
+    user_health: f32,
+    opponent_health: f32,
+    rng: &mut impl Rng,
+) -> MoveResult {
+    let hits = match attack.move_effect {
+        MoveEffect::MultiHit(low, high) => rng.gen_range(low..=high),
+        _ => 1,
+    };
+    resolve_hits(attack, damage, hits, user_health, opponent_health)
+}
+
+// How likely each number of hits is; moves that don't hit several times always hit once
+pub fn hit_chances(attack: &Move) -> Vec<(u32, f64)> {
+    match attack.move_effect {
+        MoveEffect::MultiHit(low, high) => {
+            let chance = 1.0 / (high - low + 1) as f64;
+            (low..=high).map(|hits| (hits, chance)).collect()
+        }
+        _ => vec![(1, 1.0)],
+    }
+}
+
+// Resolves a move's effect once it is known how many times it hit
+pub fn resolve_hits(attack: &Move, damage: f32, hits: u32, user_health: f32, opponent_health: f32) -> MoveResult {
+    let dealt = (damage * hits as f32).min(opponent_health);
+
+    let user_health_change = match attack.move_effect {
+        MoveEffect::Heal(percent) => (100.0 - user_health).min(percent),
+        MoveEffect::Drain(percent) => (100.0 - user_health).min(dealt * percent / 100.0),
+        // Recoil never knocks out the user
+        MoveEffect::Recoil(percent) => -(dealt * percent / 100.0).min(user_health - 1.0).max(0.0),
+        _ => 0.0,
+    };
+
+    MoveResult {
+        damage: damage * hits as f32,
+        hits,
+        user_health_change,
+    }
+}
+
+pub fn str_move_effect(attack: &Move, result: &MoveResult, user: &str, opponent: &str) -> Option<String> {
+    match attack.move_effect {
+        MoveEffect::MultiHit(_, _) => Some(format!("Hit {} times!", result.hits)),
+        MoveEffect::Heal(_) if result.user_health_change > 0.0 => {
+            Some(format!("{} restored its health!", user))
+        }
+        MoveEffect::Drain(_) if result.user_health_change > 0.0 => {
+            Some(format!("{} drained health from {}!", user, opponent))
+        }
+        MoveEffect::Recoil(_) if result.user_health_change < 0.0 => {
+            Some(format!("{} was hurt by recoil!", user))
+        }
+        MoveEffect::Heal(_) => Some(format!("{}'s health is already full!", user)),
+        MoveEffect::SetWeather(w, _) => Some(weather::start_message(w)),
+        _ => None,
+    }
+}
+
 pub fn calculate_damage(monsters: &HashMap<String, Monster>, battle_state: &mut BattleState, move_index: usize, player_turn: bool) -> f32 {
+    let (player_attack, player_defense) = active_stats(monsters, battle_state, true, 0);
+    let (enemy_attack, enemy_defense) = active_stats(monsters, battle_state, false, 0);
     if player_turn {
         let attack = monsters[&battle_state.player_team[0].name].moves[move_index];
         calculate_player_attack(
@@ -165,6 +400,8 @@ pub fn calculate_damage(monsters: &HashMap<String, Monster>, battle_state: &mut
             attack,
             &monsters[&battle_state.player_team[0].name],
             &monsters[&battle_state.enemy_team[0].0],
+            player_attack,
+            enemy_defense,
         )
     } else {
         let attack = monsters[&battle_state.enemy_team[0].0].moves[move_index];
@@ -173,92 +410,337 @@ pub fn calculate_damage(monsters: &HashMap<String, Monster>, battle_state: &mut
             attack,
             &monsters[&battle_state.enemy_team[0].0],
             &monsters[&battle_state.player_team[0].name],
+            enemy_attack,
+            player_defense,
         )
     }
 }
 
+/// Damage of a move between two active monsters of a double battle
+///
+/// Unlike `calculate_damage` this leaves the stages alone, so a spread move can be
+/// calculated against each foe and `apply_move_stages` called once.
+///
+/// * `slots` - Active slot of the monster using the move, then of the monster being hit
+/// * `player_turn` - Whether the attacker is on the player's side
+pub fn damage_between(
+    monsters: &HashMap<String, Monster>,
+    battle_state: &BattleState,
+    attack: &Move,
+    slots: (usize, usize),
+    player_turn: bool,
+) -> f32 {
+    let (attack_stages, defense_stages) = if player_turn {
+        (battle_state.self_attack_stages, battle_state.opp_defense_stages)
+    } else {
+        (battle_state.opp_attack_stages, battle_state.self_defense_stages)
+    };
+    let (attack_stat, _) = active_stats(monsters, battle_state, player_turn, slots.0);
+    let (_, defense_stat) = active_stats(monsters, battle_state, !player_turn, slots.1);
+    let attacker = &monsters[&active_name(battle_state, player_turn, slots.0)];
+    let opponent = &monsters[&active_name(battle_state, !player_turn, slots.1)];
+    let effective_attack = attack_stat as f32 * (1.0 + STAGE_MULT * attack_stages as f32);
+    let effective_defense = defense_stat as f32 * (1.0 + STAGE_MULT * defense_stages as f32);
+    let spread = if attack.spread { SPREAD_MULT } else { 1.0 };
+
+    match attack.move_effect {
+        MoveEffect::FixedDamage(fixed) => fixed,
+        _ => damage_calc(
+            attack.damage as f32,
+            effective_attack,
+            effective_defense,
+            stab_bonus(&attack.attack_type, &attacker.monster_type),
+            type_effectiveness(&attack.attack_type, &opponent.monster_type),
+        ) * weather_bonus(battle_state, attack) * spread,
+    }
+}
+
+pub fn apply_move_stages(battle_state: &mut BattleState, attack: &Move, player_turn: bool) {
+    if player_turn {
+        battle_state.self_attack_stages += attack.self_attack_stages;
+        battle_state.self_defense_stages += attack.self_defense_stages;
+        battle_state.opp_attack_stages += attack.opp_attack_stages;
+        battle_state.opp_defense_stages += attack.opp_defense_stages;
+    } else {
+        battle_state.opp_attack_stages += attack.self_attack_stages;
+        battle_state.opp_defense_stages += attack.self_defense_stages;
+        battle_state.self_attack_stages += attack.opp_attack_stages;
+        battle_state.self_defense_stages += attack.opp_defense_stages;
+    }
+
+    battle_state.opp_attack_stages = battle_state.opp_attack_stages.clamp(-STAGE_LIMIT, STAGE_LIMIT);
+    battle_state.opp_defense_stages = battle_state.opp_defense_stages.clamp(-STAGE_LIMIT, STAGE_LIMIT);
+    battle_state.self_attack_stages = battle_state.self_attack_stages.clamp(-STAGE_LIMIT, STAGE_LIMIT);
+    battle_state.self_defense_stages = battle_state.self_defense_stages.clamp(-STAGE_LIMIT, STAGE_LIMIT);
+
+    set_weather(battle_state, attack);
+}
+
 fn calculate_player_attack(
-    mut battle_state: &mut BattleState,
+    battle_state: &mut BattleState,
     attack: &Move,
     attacker: &Monster,
     opponent: &Monster,
+    // Final stats of the attacker and opponent, before stages
+    attack_stat: u32,
+    defense_stat: u32,
 ) -> f32 {
     let effective_attack =
-        attacker.attack_stat as f32 * (1.0 + STAGE_MULT * battle_state.self_attack_stages as f32);
+        attack_stat as f32 * (1.0 + STAGE_MULT * battle_state.self_attack_stages as f32);
     let effective_defense =
-        opponent.defense_stat as f32 * (1.0 + STAGE_MULT * battle_state.opp_defense_stages as f32);
+        defense_stat as f32 * (1.0 + STAGE_MULT * battle_state.opp_defense_stages as f32);
     let damage = attack.damage as f32;
     let stab_bonus = stab_bonus(&attack.attack_type, &attacker.monster_type);
     let type_bonus = type_effectiveness(&attack.attack_type, &opponent.monster_type);
 
-    let a = damage_calc(
-        damage,
-        effective_attack,
-        effective_defense,
-        stab_bonus,
-        type_bonus,
-    );
-
-    battle_state.self_attack_stages += attack.self_attack_stages;
-    battle_state.self_defense_stages += attack.self_defense_stages;
-    battle_state.opp_attack_stages += attack.opp_attack_stages;
-    battle_state.opp_defense_stages += attack.opp_defense_stages;
-
-    battle_state.opp_attack_stages = battle_state
-        .opp_attack_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.opp_defense_stages = battle_state
-        .opp_defense_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.self_attack_stages = battle_state
-        .self_attack_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.self_defense_stages = battle_state
-        .self_defense_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
+    let a = match attack.move_effect {
+        MoveEffect::FixedDamage(fixed) => fixed,
+        _ => damage_calc(
+            damage,
+            effective_attack,
+            effective_defense,
+            stab_bonus,
+            type_bonus,
+        ) * weather_bonus(battle_state, attack),
+    };
+
+    apply_move_stages(battle_state, attack, true);
 
     a
 }
 
 fn calculate_opp_attack(
-    mut battle_state: &mut BattleState,
+    battle_state: &mut BattleState,
     attack: &Move,
     attacker: &Monster,
     opponent: &Monster,
+    // Final stats of the attacker and opponent, before stages
+    attack_stat: u32,
+    defense_stat: u32,
 ) -> f32 {
     let effective_attack =
-        attacker.attack_stat as f32 * (1.0 + STAGE_MULT * battle_state.opp_attack_stages as f32);
+        attack_stat as f32 * (1.0 + STAGE_MULT * battle_state.opp_attack_stages as f32);
     let effective_defense =
-        opponent.defense_stat as f32 * (1.0 + STAGE_MULT * battle_state.self_defense_stages as f32);
+        defense_stat as f32 * (1.0 + STAGE_MULT * battle_state.self_defense_stages as f32);
     let damage = attack.damage as f32;
     let stab_bonus = stab_bonus(&attack.attack_type, &attacker.monster_type);
     let type_bonus = type_effectiveness(&attack.attack_type, &opponent.monster_type);
 
-    let a = damage_calc(
-        damage,
-        effective_attack,
-        effective_defense,
-        stab_bonus,
-        type_bonus,
-    );
-
-    battle_state.opp_attack_stages += attack.self_attack_stages;
-    battle_state.opp_defense_stages += attack.self_defense_stages;
-    battle_state.self_attack_stages += attack.opp_attack_stages;
-    battle_state.self_defense_stages += attack.opp_defense_stages;
-
-    battle_state.opp_attack_stages = battle_state
-        .opp_attack_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.opp_defense_stages = battle_state
-        .opp_defense_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.self_attack_stages = battle_state
-        .self_attack_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
-    battle_state.self_defense_stages = battle_state
-        .self_defense_stages
-        .clamp(-STAGE_LIMIT, STAGE_LIMIT);
+    let a = match attack.move_effect {
+        MoveEffect::FixedDamage(fixed) => fixed,
+        _ => damage_calc(
+            damage,
+            effective_attack,
+            effective_defense,
+            stab_bonus,
+            type_bonus,
+        ) * weather_bonus(battle_state, attack),
+    };
+
+    apply_move_stages(battle_state, attack, false);
 
     a
 }
+
+// Experience needed to go from the given level to the next
+pub fn exp_to_next(level: u32) -> u32 {
+    level * 20
+}
+
+// Adds experience to a monster, returning how many levels it gained
+pub fn gain_exp(mon: &mut ActiveMons, amount: u32) -> u32 {
+    let mut gained = 0;
+    mon.exp += amount;
+    while mon.exp >= exp_to_next(mon.level) {
+        mon.exp -= exp_to_next(mon.level);
+        mon.level += 1;
+        gained += 1;
+    }
+    gained
+}
+
+pub fn nature_name(nature: Nature) -> &'static str {
+    match nature {
+        Nature::Hardy => "Hardy",
+        Nature::Lonely => "Lonely",
+        Nature::Bold => "Bold",
+        Nature::Docile => "Docile",
+    }
+}
+
+pub fn parse_nature(nature: &str) -> Result<Nature, String> {
+    match nature {
+        "Hardy" => Ok(Nature::Hardy),
+        "Lonely" => Ok(Nature::Lonely),
+        "Bold" => Ok(Nature::Bold),
+        "Docile" => Ok(Nature::Docile),
+        _ => Err(format!("Unknown nature {}", nature)),
+    }
+}
+
+// Multipliers a nature applies to attack and defense
+fn nature_mults(nature: Nature) -> (f32, f32) {
+    match nature {
+        Nature::Lonely => (1.0 + NATURE_MULT, 1.0 - NATURE_MULT),
+        Nature::Bold => (1.0 - NATURE_MULT, 1.0 + NATURE_MULT),
+        Nature::Hardy | Nature::Docile => (1.0, 1.0),
+    }
+}
+
+/// A new monster of the given species with rolled individual values and nature
+///
+/// * `name` - The species
+/// * `level` - The level it starts at
+/// * `rng` - Rolls the individual values and nature
+pub fn roll_instance(name: &str, level: u32, rng: &mut impl Rng) -> ActiveMons {
+    ActiveMons {
+        name: String::from(name),
+        hp: 100.0,
+        held_item: None,
+        level,
+        exp: 0,
+        attack_iv: rng.gen_range(0..=IV_MAX),
+        defense_iv: rng.gen_range(0..=IV_MAX),
+        nature: NATURES[rng.gen_range(0..NATURES.len())],
+    }
+}
+
+fn final_stat(base: u32, level: u32, iv: u32, nature_mult: f32) -> u32 {
+    let level_mult = 1.0 + LEVEL_GROWTH * (level as f32 - STARTING_LEVEL as f32);
+    let iv_mult = 1.0 + IV_BONUS * iv as f32 / IV_MAX as f32;
+    (base as f32 * level_mult * iv_mult * nature_mult).round() as u32
+}
+
+// Attack and defense of a team member, from its species' base stats, level, individual values and nature
+pub fn instance_stats(monster: &Monster, mon: &ActiveMons) -> (u32, u32) {
+    let (attack_mult, defense_mult) = nature_mults(mon.nature);
+    (
+        final_stat(monster.attack_stat, mon.level, mon.attack_iv, attack_mult),
+        final_stat(monster.defense_stat, mon.level, mon.defense_iv, defense_mult),
+    )
+}
+
+// Enemies don't keep individual values, so they fight as an average monster of their level
+pub fn enemy_stats(monster: &Monster, level: u32) -> (u32, u32) {
+    (
+        final_stat(monster.attack_stat, level, IV_MAX / 2, 1.0),
+        final_stat(monster.defense_stat, level, IV_MAX / 2, 1.0),
+    )
+}
+
+fn active_name(battle_state: &BattleState, player_side: bool, slot: usize) -> String {
+    if player_side {
+        battle_state.player_team[slot].name.clone()
+    } else {
+        battle_state.enemy_team[slot].0.clone()
+    }
+}
+
+// Attack and defense of the monster in one side's active slot
+pub fn active_stats(
+    monsters: &HashMap<String, Monster>,
+    battle_state: &BattleState,
+    player_side: bool,
+    slot: usize,
+) -> (u32, u32) {
+    if player_side {
+        let mon = &battle_state.player_team[slot];
+        instance_stats(&monsters[&mon.name], mon)
+    } else {
+        enemy_stats(&monsters[&battle_state.enemy_team[slot].0], battle_state.enemy_level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_move(move_effect: MoveEffect) -> Move {
+        Move {
+            name: String::from("Test"),
+            damage: 40,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+            attack_type: String::from("Normal"),
+            effect: String::new(),
+            move_effect,
+            spread: false,
+        }
+    }
+
+    #[test]
+    fn move_effects_parse() {
+        assert!(parse_move_effect("None").unwrap() == MoveEffect::None);
+        assert!(parse_move_effect("Drain 50").unwrap() == MoveEffect::Drain(50.0));
+        assert!(parse_move_effect("MultiHit 2-5").unwrap() == MoveEffect::MultiHit(2, 5));
+        assert!(parse_move_effect("Weather Rain 5").unwrap() == MoveEffect::SetWeather(Weather::Rain, 5));
+        assert!(parse_move_effect("Drain").is_err());
+        assert!(parse_move_effect("Drain half").is_err());
+        assert!(parse_move_effect("MultiHit 2").is_err());
+        assert!(parse_move_effect("Weather Fog 5").is_err());
+        assert!(parse_move_effect("Teleport").is_err());
+    }
+
+    #[test]
+    fn heals_stop_at_full_health() {
+        let heal = test_move(MoveEffect::Heal(50.0));
+        assert_eq!(resolve_hits(&heal, 0.0, 1, 30.0, 100.0).user_health_change, 50.0);
+        assert_eq!(resolve_hits(&heal, 0.0, 1, 80.0, 100.0).user_health_change, 20.0);
+    }
+
+    #[test]
+    fn drain_follows_the_damage_dealt() {
+        let drain = test_move(MoveEffect::Drain(50.0));
+        assert_eq!(resolve_hits(&drain, 30.0, 1, 50.0, 100.0).user_health_change, 15.0);
+        // Only the health the opponent had left can be drained
+        assert_eq!(resolve_hits(&drain, 30.0, 1, 50.0, 10.0).user_health_change, 5.0);
+        assert_eq!(resolve_hits(&drain, 30.0, 1, 95.0, 100.0).user_health_change, 5.0);
+    }
+
+    #[test]
+    fn recoil_never_knocks_out_the_user() {
+        let recoil = test_move(MoveEffect::Recoil(50.0));
+        assert_eq!(resolve_hits(&recoil, 30.0, 1, 100.0, 100.0).user_health_change, -15.0);
+        assert_eq!(resolve_hits(&recoil, 30.0, 1, 10.0, 100.0).user_health_change, -9.0);
+        assert_eq!(resolve_hits(&recoil, 30.0, 1, 1.0, 100.0).user_health_change, 0.0);
+    }
+
+    #[test]
+    fn multi_hits_add_up() {
+        let flurry = test_move(MoveEffect::MultiHit(2, 5));
+        let result = resolve_hits(&flurry, 10.0, 3, 100.0, 100.0);
+        assert_eq!(result.damage, 30.0);
+        assert_eq!(result.hits, 3);
+        let chances = hit_chances(&flurry);
+        assert_eq!(chances.len(), 4);
+        assert!((chances.iter().map(|d| d.1).sum::<f64>() - 1.0).abs() < 1e-9);
+        assert_eq!(hit_chances(&test_move(MoveEffect::None)), vec![(1, 1.0)]);
+    }
+
+    #[test]
+    fn stages_are_mirrored_for_the_enemy() {
+        let mut growl = test_move(MoveEffect::None);
+        growl.self_attack_stages = 1;
+        growl.opp_defense_stages = -STAGE_LIMIT - 1;
+        let mut state = BattleState {
+            player_turn: true,
+            player_team: Vec::new(),
+            enemy_team: Vec::new(),
+            enemy_level: STARTING_LEVEL,
+            wild: false,
+            weather: None,
+            double: false,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        };
+
+        apply_move_stages(&mut state, &growl, true);
+        assert_eq!((state.self_attack_stages, state.opp_defense_stages), (1, -STAGE_LIMIT));
+        apply_move_stages(&mut state, &growl, false);
+        assert_eq!((state.opp_attack_stages, state.self_defense_stages), (1, -STAGE_LIMIT));
+    }
+}
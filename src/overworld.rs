@@ -5,6 +5,12 @@ use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
 
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::flags;
+
 
 const TILE_SIZE: u32 = 16;
 
@@ -240,32 +246,63 @@ pub fn display_menu(wincan: &mut sdl2::render::WindowCanvas, player_x: i32, play
     Ok(())
   }
 
-pub fn mark_rectangles() -> Vec<Rect>{
+// Each spawn area is named after its zone's encounter table in encounters.txt
+pub fn mark_rectangles() -> Vec<(String, Rect)>{
   let mut spn_rectangles = Vec::new();
   // Top left corner of the grass patches
   let left_corner_grass = Rect::new((6*TILE_SIZE) as i32, 96, 2*TILE_SIZE*7, 2*TILE_SIZE*4);
-  spn_rectangles.push(left_corner_grass);
+  spn_rectangles.push((String::from("left_corner_grass"), left_corner_grass));
   // A pond to the right bottom corner of map
   let right_bottom_pond = Rect::new((48*TILE_SIZE) as i32, 480, 2*TILE_SIZE*10, 2*TILE_SIZE*4);
-  spn_rectangles.push(right_bottom_pond);
+  spn_rectangles.push((String::from("right_bottom_pond"), right_bottom_pond));
   // Rock patches to right upper corner of map
   let right_upper_rock = Rect::new((60*TILE_SIZE) as i32, 66, 2*TILE_SIZE*9, 2*TILE_SIZE*5);
-  spn_rectangles.push(right_upper_rock);
+  spn_rectangles.push((String::from("right_upper_rock"), right_upper_rock));
   // Large grass patches to north of map
   let north_grass = Rect::new((32*TILE_SIZE) as i32, 96, 2*TILE_SIZE*10, 2*TILE_SIZE*4);
-  spn_rectangles.push(north_grass);
+  spn_rectangles.push((String::from("north_grass"), north_grass));
   // Grass patches to the south of map
   let south_grass = Rect::new((32*TILE_SIZE) as i32, 480, 2*TILE_SIZE*7, 2*TILE_SIZE*4);
-  spn_rectangles.push(south_grass);
+  spn_rectangles.push((String::from("south_grass"), south_grass));
   // Grass patches to the center right of map
   let center_right_grass = Rect::new((62*TILE_SIZE) as i32, 300, 2*TILE_SIZE*8, 2*TILE_SIZE*4);
-  spn_rectangles.push(center_right_grass);
+  spn_rectangles.push((String::from("center_right_grass"), center_right_grass));
   // A pond to the middle center left of map
   let center_left_pond = Rect::new((4*TILE_SIZE) as i32, 280, 2*TILE_SIZE*8, 2*TILE_SIZE*4);
-  spn_rectangles.push(center_left_pond);
+  spn_rectangles.push((String::from("center_left_pond"), center_left_pond));
   // Small grass patches in the center of the map
   let center_grass = Rect::new((24*TILE_SIZE) as i32, 280, 2*TILE_SIZE*6, 2*TILE_SIZE*4);
-  spn_rectangles.push(center_grass);
+  spn_rectangles.push((String::from("center_grass"), center_grass));
 
   return spn_rectangles;
 }
+
+// A building door that stays locked until the player has made some progress
+pub struct Door {
+  pub condition: flags::Condition,
+  pub locked_message: String,
+}
+
+// Doors missing from doors.txt are always open
+pub fn load_doors() -> HashMap<String, Door> {
+  let reader = BufReader::new(File::open("./data/doors.txt").expect("Cannot open doors.txt"));
+  let mut doors = HashMap::new();
+  for line in reader.lines().skip(1) {
+    let v = line.unwrap();
+    let v = v.split(",").collect::<Vec<&str>>();
+    doors.insert(String::from(v[0]), Door {
+      condition: flags::parse_condition(v[1]),
+      // The message is the last column so it can have commas in it
+      locked_message: v[2..].join(","),
+    });
+  }
+  doors
+}
+
+// Returns what to tell the player if the door won't open for them yet
+pub fn door_locked(doors: &HashMap<String, Door>, door: &str, progress: &flags::Progress) -> Option<String> {
+  match doors.get(door) {
+    Some(d) if !d.condition.holds(progress) => Some(d.locked_message.clone()),
+    _ => None,
+  }
+}
@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::battle;
+use crate::flags::{self, Condition, Progress};
+
+const JOURNAL_ROWS: usize = 5;
+
+pub struct Quest {
+    pub id: String,
+    pub title: String,
+    // The quest shows up in the journal once this holds
+    pub starts: Condition,
+    // The quest is complete once this holds
+    pub objective: Condition,
+    pub reward_money: u32,
+    pub reward_item: Option<String>,
+    pub description: String,
+}
+
+pub fn load_quests() -> Vec<Quest> {
+    let reader = BufReader::new(File::open("./data/quests.txt").expect("Cannot open quests.txt"));
+    let mut quests = Vec::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        quests.push(Quest {
+            id: String::from(v[0]),
+            title: String::from(v[1]),
+            starts: flags::parse_condition(v[2]),
+            objective: flags::parse_condition(v[3]),
+            reward_money: v[4].parse::<u32>().expect("Quest reward should be a number"),
+            reward_item: if v[5].is_empty() { None } else { Some(String::from(v[5])) },
+            // The description is the last column so it can have commas in it
+            description: v[6..].join(","),
+        });
+    }
+    quests
+}
+
+impl Quest {
+    pub fn done(&self, progress: &Progress) -> bool {
+        progress.flags.has(&flags::quest_done(&self.id))
+    }
+
+    pub fn started(&self, progress: &Progress) -> bool {
+        self.done(progress) || self.starts.holds(progress)
+    }
+
+    pub fn reward_message(&self) -> String {
+        match &self.reward_item {
+            Some(item) => format!(
+                "Quest complete: {}! You got ${} and a {}.",
+                self.title, self.reward_money, item
+            ),
+            None => format!("Quest complete: {}! You got ${}.", self.title, self.reward_money),
+        }
+    }
+}
+
+// The first started quest whose objective has just been met, which the caller then rewards
+pub fn newly_completed(quests: &[Quest], progress: &Progress) -> Option<usize> {
+    quests
+        .iter()
+        .position(|d| !d.done(progress) && d.starts.holds(progress) && d.objective.holds(progress))
+}
+
+/// Draws the journal listing every quest the player has started
+///
+/// * `battle_init` - Holds the font
+/// * `quests` - Every quest
+/// * `progress` - Decides which quests are started and done
+/// * `scroll` - How many quests to skip from the top
+pub fn draw_journal(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &battle::Battle,
+    quests: &[Quest],
+    progress: &Progress,
+    scroll: usize,
+) -> Result<(), String> {
+    let light = Color::RGB(0xbd, 0xcd, 0xde);
+    wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
+    wincan.fill_rect(Rect::new(100, 80, 1080, 560))?;
+    battle::draw_text(wincan, battle_init.font, "Journal", light, Rect::new(120, 90, 1040, 40))?;
+
+    let started: Vec<&Quest> = quests.iter().filter(|d| d.started(progress)).collect();
+    if started.is_empty() {
+        battle::draw_text(wincan, battle_init.font, "No quests yet", light, Rect::new(120, 140, 1040, 38))?;
+    }
+    for (index, quest) in started.iter().skip(scroll).take(JOURNAL_ROWS).enumerate() {
+        let r = Rect::new(120, 140 + (index as i32) * 88, 1040, 80);
+        wincan.set_draw_color(if quest.done(progress) {
+            Color::RGB(0x5a, 0x8a, 0x5a)
+        } else {
+            Color::RGB(0x39, 0x7B, 0xB4)
+        });
+        wincan.fill_rect(r)?;
+
+        let f = if quest.done(progress) {
+            format!("{} (done)", quest.title)
+        } else {
+            quest.title.clone()
+        };
+        battle::draw_text(wincan, battle_init.font, &f, Color::BLACK, Rect::new(r.x() + 10, r.y() + 5, 1020, 35))?;
+        battle::draw_text(
+            wincan,
+            battle_init.font,
+            &quest.description,
+            Color::BLACK,
+            Rect::new(r.x() + 10, r.y() + 45, 1020, 28),
+        )?;
+    }
+
+    battle::draw_text(
+        wincan,
+        battle_init.font,
+        "W/S to scroll, J to close",
+        light,
+        Rect::new(120, 595, 1040, 35),
+    )?;
+    wincan.present();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quest(id: &str, starts: &str, objective: &str, reward_item: Option<&str>) -> Quest {
+        Quest {
+            id: String::from(id),
+            title: String::from(id),
+            starts: flags::parse_condition(starts),
+            objective: flags::parse_condition(objective),
+            reward_money: 100,
+            reward_item: reward_item.map(String::from),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn quests_complete_once_started_and_met() {
+        let quests = vec![
+            quest("Gym", "talked:Old Man", "beaten:Ada", None),
+            quest("Fishing", "always", "caught_type:Water", Some("Potion")),
+        ];
+        let team = Vec::new();
+        let mut game_flags = flags::Flags::new();
+
+        // Meeting the objective of a quest that hasn't started doesn't count
+        game_flags.set(&flags::defeated("Ada"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert!(!quests[0].started(&progress));
+        assert!(quests[1].started(&progress));
+        assert_eq!(newly_completed(&quests, &progress), None);
+
+        game_flags.set(&flags::talked("Old Man"));
+        game_flags.set(&flags::caught_type("Water"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert_eq!(newly_completed(&quests, &progress), Some(0));
+
+        // Rewarded quests stay done and move the next one up
+        game_flags.set(&flags::quest_done("Gym"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert!(quests[0].done(&progress));
+        assert_eq!(newly_completed(&quests, &progress), Some(1));
+        game_flags.set(&flags::quest_done("Fishing"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert_eq!(newly_completed(&quests, &progress), None);
+    }
+
+    #[test]
+    fn finished_quests_stay_in_the_journal() {
+        let quests = [quest("Gym", "badges>=1", "beaten:Ada", None)];
+        let team = Vec::new();
+        let mut game_flags = flags::Flags::new();
+        game_flags.set(&flags::quest_done("Gym"));
+        let progress = Progress { badges: 0, team: &team, flags: &game_flags };
+        assert!(quests[0].started(&progress));
+    }
+
+    #[test]
+    fn rewards_mention_the_item() {
+        assert_eq!(quest("Gym", "always", "always", None).reward_message(), "Quest complete: Gym! You got $100.");
+        assert_eq!(
+            quest("Gym", "always", "always", Some("Potion")).reward_message(),
+            "Quest complete: Gym! You got $100 and a Potion."
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ai::{self, Difficulty, Profile};
+use crate::battle::{self, Map};
+use crate::inventory;
+use crate::monster;
+use crate::ActiveMons;
+
+const REPLAY_DIR: &str = "./replays";
+
+// Everything the player decided during a battle; the enemy's choices come from the seeded RNG
+pub enum Action {
+    // Use one of the lead's four moves
+    Move(usize),
+    // Swap two team members in the monster menu, which doesn't take a turn
+    Reorder(usize, usize),
+    // Confirm the team order, switching or replacing the lead if it changed
+    ConfirmTeam,
+    // Use an item on a team member
+    Item(String, usize),
+    // The slot, move and target for each of the player's monsters in a double battle
+    Double(Vec<(usize, usize, usize)>),
+}
+
+pub struct Replay {
+    pub seed: u64,
+    // The enemy searches differently on each difficulty, so it has to match for the replay to
+    pub difficulty: Difficulty,
+    // Likewise the trainer's profile, which also picks the evaluation weights
+    pub profile: Profile,
+    pub wild: bool,
+    pub double: bool,
+    pub enemy_level: u32,
+    pub player_team: Vec<ActiveMons>,
+    pub enemy_team: Vec<(String, f32)>,
+    pub actions: Vec<Action>,
+    // How many positions each of the trainer's deepening searches visited, so they stop in the same places
+    pub searches: Vec<u32>,
+    pub result: Option<String>,
+}
+
+impl Replay {
+    pub fn new() -> Replay {
+        Replay {
+            seed: 0,
+            // Replays from before difficulty was recorded were all played on normal
+            difficulty: Difficulty::Normal,
+            // Replays from before profiles were recorded were all against standard trainers
+            profile: Profile::Standard,
+            wild: false,
+            double: false,
+            enemy_level: monster::STARTING_LEVEL,
+            player_team: Vec::new(),
+            enemy_team: Vec::new(),
+            actions: Vec::new(),
+            searches: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn start(seed: u64, difficulty: Difficulty, profile: Profile, battle_state: &monster::BattleState) -> Replay {
+        Replay {
+            seed,
+            difficulty,
+            profile,
+            wild: battle_state.wild,
+            double: battle_state.double,
+            enemy_level: battle_state.enemy_level,
+            player_team: battle_state.player_team.clone(),
+            enemy_team: battle_state.enemy_team.clone(),
+            actions: Vec::new(),
+            searches: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn record(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    pub fn initial_state(&self) -> monster::BattleState {
+        monster::BattleState {
+            player_turn: true,
+            player_team: self.player_team.clone(),
+            enemy_team: self.enemy_team.clone(),
+            enemy_level: self.enemy_level,
+            wild: self.wild,
+            weather: None,
+            double: self.double,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        }
+    }
+
+    // Writes the replay to its own file, returning the path
+    pub fn save(&self) -> Result<String, String> {
+        fs::create_dir_all(REPLAY_DIR).map_err(|e| e.to_string())?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_millis();
+        let path = format!("{}/battle_{}.txt", REPLAY_DIR, time);
+
+        let mut file = File::create(&path).map_err(|e| e.to_string())?;
+        for line in self.lines() {
+            writeln!(file, "{}", line).map_err(|e| e.to_string())?;
+        }
+        Ok(path)
+    }
+
+    // The replay as the lines of its file
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(format!("seed,{}", self.seed));
+        lines.push(format!("difficulty,{}", ai::difficulty_name(self.difficulty)));
+        lines.push(format!("profile,{}", ai::profile_name(self.profile)));
+        lines.push(format!("battle,{},{},{}", self.wild, self.double, self.enemy_level));
+        for mon in self.player_team.iter() {
+            let held = match &mon.held_item {
+                Some(item) => item.clone(),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "player,{},{},{},{},{},{},{},{}",
+                mon.name,
+                mon.hp,
+                held,
+                mon.level,
+                mon.exp,
+                mon.attack_iv,
+                mon.defense_iv,
+                monster::nature_name(mon.nature)
+            ));
+        }
+        for mon in self.enemy_team.iter() {
+            lines.push(format!("enemy,{},{}", mon.0, mon.1));
+        }
+        for action in self.actions.iter() {
+            lines.push(match action {
+                Action::Move(choice) => format!("move,{}", choice),
+                Action::Reorder(a, b) => format!("reorder,{},{}", a, b),
+                Action::ConfirmTeam => String::from("confirm"),
+                Action::Item(name, target) => format!("item,{},{}", name, target),
+                Action::Double(picks) => format!(
+                    "double,{}",
+                    picks
+                        .iter()
+                        .map(|d| format!("{}:{}:{}", d.0, d.1, d.2))
+                        .collect::<Vec<String>>()
+                        .join(";")
+                ),
+            });
+        }
+        for visited in self.searches.iter() {
+            lines.push(format!("search,{}", visited));
+        }
+        if let Some(result) = &self.result {
+            lines.push(format!("result,{}", result));
+        }
+        lines
+    }
+}
+
+impl Default for Replay {
+    fn default() -> Replay {
+        Replay::new()
+    }
+}
+
+// A field of a replay line, which is an error if the line is too short
+fn field<'a>(v: &[&'a str], index: usize) -> Result<&'a str, String> {
+    v.get(index)
+        .copied()
+        .ok_or_else(|| format!("Replay line \"{}\" is missing field {}", v.join(","), index))
+}
+
+fn parse_index(v: &str) -> Result<usize, String> {
+    v.parse::<usize>().map_err(|e| e.to_string())
+}
+
+fn parse_number<T: std::str::FromStr>(v: &[&str], index: usize) -> Result<T, String> {
+    let d = field(v, index)?;
+    d.parse::<T>()
+        .map_err(|_| format!("Replay line \"{}\" has {} where a number should be", v.join(","), d))
+}
+
+// A field that older replays don't have, which takes the default when it's missing
+fn parse_optional<T: std::str::FromStr>(v: &[&str], index: usize, default: T) -> Result<T, String> {
+    if index < v.len() {
+        parse_number(v, index)
+    } else {
+        Ok(default)
+    }
+}
+
+// Reads one line of a replay into it
+fn parse_line(replay: &mut Replay, line: &str) -> Result<(), String> {
+    let v = line.split(",").collect::<Vec<&str>>();
+    match v[0] {
+        "seed" => replay.seed = parse_number(&v, 1)?,
+        "difficulty" => replay.difficulty = ai::parse_difficulty(field(&v, 1)?)?,
+        "profile" => replay.profile = ai::parse_profile(field(&v, 1)?)?,
+        "battle" => {
+            replay.wild = field(&v, 1)? == "true";
+            replay.double = field(&v, 2)? == "true";
+            replay.enemy_level = parse_number(&v, 3)?;
+        }
+        "player" => replay.player_team.push(ActiveMons {
+            name: String::from(field(&v, 1)?),
+            hp: parse_number(&v, 2)?,
+            held_item: v.get(3).filter(|d| !d.is_empty()).map(|d| String::from(*d)),
+            // Replays from before levels existed start everyone at the starting level
+            level: parse_optional(&v, 4, monster::STARTING_LEVEL)?,
+            exp: parse_optional(&v, 5, 0)?,
+            // Older replays didn't roll individual values, so those monsters are average
+            attack_iv: parse_optional(&v, 6, monster::IV_MAX / 2)?,
+            defense_iv: parse_optional(&v, 7, monster::IV_MAX / 2)?,
+            nature: match v.get(8) {
+                Some(nature) => monster::parse_nature(nature)?,
+                None => monster::Nature::Hardy,
+            },
+        }),
+        "enemy" => replay
+            .enemy_team
+            .push((String::from(field(&v, 1)?), parse_number(&v, 2)?)),
+        "move" => replay.record(Action::Move(parse_index(field(&v, 1)?)?)),
+        "reorder" => replay.record(Action::Reorder(parse_index(field(&v, 1)?)?, parse_index(field(&v, 2)?)?)),
+        "confirm" => replay.record(Action::ConfirmTeam),
+        "item" => replay.record(Action::Item(String::from(field(&v, 1)?), parse_index(field(&v, 2)?)?)),
+        "double" => {
+            let mut picks = Vec::new();
+            for pick in field(&v, 1)?.split(";") {
+                let p = pick.split(":").collect::<Vec<&str>>();
+                picks.push((parse_index(field(&p, 0)?)?, parse_index(field(&p, 1)?)?, parse_index(field(&p, 2)?)?));
+            }
+            replay.record(Action::Double(picks));
+        }
+        "search" => replay.searches.push(parse_number(&v, 1)?),
+        "result" => replay.result = Some(String::from(field(&v, 1)?)),
+        _ => return Err(format!("Unknown replay record {}", v[0])),
+    }
+    Ok(())
+}
+
+// Each line of a replay is a record type followed by its fields, like the save file
+pub fn load_replay(path: &str) -> Result<Replay, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let reader = BufReader::new(file);
+
+    let mut replay = Replay::new();
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        parse_line(&mut replay, &line)?;
+    }
+    Ok(replay)
+}
+
+/// Re-simulates a recorded battle through the same turn functions the game uses
+///
+/// Returns the result the battle reached, or None if the actions ran out first.
+///
+/// * `battle_state` - Should be the replay's initial state
+/// * `battle_draw` - The battle drawing; set `headless` to skip drawing and pauses
+/// * `monsters_map` - Maps strings onto their Monster objects
+/// * `items` - Maps item names onto their definitions
+/// * `replay` - The recorded battle
+pub fn play(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_state: &mut monster::BattleState,
+    battle_draw: &mut battle::Battle,
+    monsters_map: &HashMap<String, monster::Monster>,
+    items: &HashMap<String, inventory::Item>,
+    replay: &Replay,
+) -> Result<Option<String>, String> {
+    battle::start_battle(wincan, battle_state, battle_draw, replay.seed)?;
+    battle_draw.searches = ai::SearchLog::replay(replay.searches.clone());
+    for action in replay.actions.iter() {
+        let map = match action {
+            Action::Move(choice) => battle::move_turn(wincan, battle_state, battle_draw, monsters_map, *choice)?,
+            Action::Reorder(a, b) => {
+                battle::reorder_team(battle_state, battle_draw, *a, *b);
+                Map::Battle
+            }
+            Action::ConfirmTeam => battle::confirm_team_turn(wincan, battle_state, battle_draw, monsters_map)?,
+            Action::Item(name, target) => {
+                let item = &items[name];
+                // Every recorded item could be used, so failing here means the replay went off track
+                let outcome = inventory::use_item(item, battle_state, *target, true, &mut battle_draw.rng)?;
+                battle::item_turn(wincan, battle_state, battle_draw, monsters_map, item, *target, outcome)?
+            }
+            Action::Double(picks) => battle::double_turn(wincan, battle_state, battle_draw, monsters_map, picks)?,
+        };
+        if let Map::Overworld = map {
+            break;
+        }
+    }
+    Ok(battle_draw.transcript.result.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ability;
+    use crate::transcript;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn parse(lines: &[String]) -> Result<Replay, String> {
+        let mut replay = Replay::new();
+        for line in lines.iter() {
+            parse_line(&mut replay, line)?;
+        }
+        Ok(replay)
+    }
+
+    // A trainer battle against two of taterface, with the given player monster
+    fn test_replay(player: &str) -> Replay {
+        let mut rng = StdRng::seed_from_u64(35);
+        let mut battle_state = monster::BattleState {
+            player_turn: true,
+            player_team: vec![monster::roll_instance(player, monster::STARTING_LEVEL, &mut rng)],
+            enemy_team: vec![(String::from("taterface"), 100.0), (String::from("taterface"), 100.0)],
+            enemy_level: monster::STARTING_LEVEL,
+            wild: false,
+            weather: None,
+            double: false,
+            self_attack_stages: 0,
+            self_defense_stages: 0,
+            opp_attack_stages: 0,
+            opp_defense_stages: 0,
+        };
+        battle_state.player_team[0].held_item = Some(String::from("Leftovers"));
+        Replay::start(35, Difficulty::Easy, Profile::Aggressive, &battle_state)
+    }
+
+    #[test]
+    fn replays_round_trip_through_their_lines() {
+        let mut replay = test_replay("Reusoon");
+        replay.record(Action::Move(2));
+        replay.record(Action::Reorder(0, 1));
+        replay.record(Action::ConfirmTeam);
+        replay.record(Action::Item(String::from("Potion"), 1));
+        replay.record(Action::Double(vec![(0, 1, 1), (1, 3, 0)]));
+        replay.searches = vec![1520, 87];
+        replay.result = Some(String::from(transcript::PLAYER_WON));
+
+        let loaded = parse(&replay.lines()).unwrap();
+        assert_eq!(loaded.lines(), replay.lines());
+        assert_eq!(loaded.seed, 35);
+        assert_eq!(loaded.searches, vec![1520, 87]);
+        assert!(loaded.difficulty == Difficulty::Easy);
+        assert!(loaded.profile == Profile::Aggressive);
+        let mon = &loaded.player_team[0];
+        let original = &replay.player_team[0];
+        assert_eq!(mon.held_item, Some(String::from("Leftovers")));
+        assert_eq!((mon.attack_iv, mon.defense_iv), (original.attack_iv, original.defense_iv));
+        assert!(mon.nature == original.nature);
+        assert_eq!(loaded.enemy_team.len(), 2);
+        assert_eq!(loaded.actions.len(), 5);
+    }
+
+    #[test]
+    fn old_player_lines_take_defaults() {
+        let replay = parse(&[String::from("player,Reusoon,100,")]).unwrap();
+        let mon = &replay.player_team[0];
+        assert_eq!(mon.held_item, None);
+        assert_eq!((mon.level, mon.exp), (monster::STARTING_LEVEL, 0));
+        assert_eq!((mon.attack_iv, mon.defense_iv), (monster::IV_MAX / 2, monster::IV_MAX / 2));
+        assert!(mon.nature == monster::Nature::Hardy);
+
+        let replay = parse(&[String::from("player,Reusoon,100,,7,40")]).unwrap();
+        let mon = &replay.player_team[0];
+        assert_eq!((mon.level, mon.exp), (7, 40));
+        assert_eq!(mon.attack_iv, monster::IV_MAX / 2);
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        let mut replay = Replay::new();
+        for line in [
+            "seed",
+            "seed,soon",
+            "difficulty,Nightmare",
+            "profile,sneaky",
+            "battle,false",
+            "player,Reusoon",
+            "player,Reusoon,100,,5,0,3,3,Grumpy",
+            "enemy,taterface,full",
+            "move",
+            "reorder,0",
+            "double,0:1",
+            "teleport,1",
+        ]
+        .iter()
+        {
+            assert!(parse_line(&mut replay, line).is_err(), "{} should not parse", line);
+        }
+        assert!(replay.player_team.is_empty());
+        assert!(replay.actions.is_empty());
+    }
+
+    // Plays a replay back without drawing it, as `--verify` does
+    fn verify(replay: &Replay) -> Option<String> {
+        let mut wincan = crate::init_headless(64, 64).unwrap();
+        let texture_creator = wincan.texture_creator();
+        let background = texture_creator
+            .create_texture_streaming(sdl2::pixels::PixelFormatEnum::RGBA8888, 1, 1)
+            .unwrap();
+        let ttf_context = sdl2::ttf::init().unwrap();
+        let font = ttf_context.load_font("./fonts/framd.ttf", 16).unwrap();
+        let moves = monster::load_moves();
+        let abilities = ability::load_abilities();
+        let monsters = monster::load_mons(&moves, &abilities);
+        let items = inventory::load_items();
+        // Nothing is drawn, so no text or monster textures are needed
+        let names = HashMap::new();
+        let textures = HashMap::new();
+
+        let mut battle_state = replay.initial_state();
+        let mut battle_draw = battle::Battle {
+            background_texture: &background,
+            player_name: String::new(),
+            enemy_name: String::new(),
+            font: &font,
+            player_health: 100.0,
+            enemy_health: 100.0,
+            name_text_map: &names,
+            attack_map: &textures,
+            effect_map: &textures,
+            monster_text_map: &textures,
+            monsters: &monsters,
+            moves: &moves,
+            items: &items,
+            weather: None,
+            double: None,
+            transcript: transcript::Transcript::new(),
+            rng: StdRng::seed_from_u64(replay.seed),
+            searches: ai::SearchLog::new(),
+            replay: Replay::new(),
+            level_ups: Vec::new(),
+            replaying: true,
+            headless: true,
+            money: 0,
+            prize: 0,
+            difficulty: replay.difficulty,
+            profile: replay.profile,
+            weights: ai::load_weights()[ai::profile_weights(replay.profile)],
+            lead: 0,
+        };
+        play(&mut wincan, &mut battle_state, &mut battle_draw, &monsters, &items, replay).unwrap()
+    }
+
+    #[test]
+    fn verifier_reaches_the_recorded_result() {
+        let mut replay = test_replay("Reusoon");
+        for _ in 0..100 {
+            replay.record(Action::Move(1));
+        }
+        let result = verify(&replay);
+        assert!(result.is_some(), "the battle should end within the recorded moves");
+
+        // The replay saved at the end of the battle holds its result, and plays back to it
+        replay.result = result;
+        let loaded = parse(&replay.lines()).unwrap();
+        assert_eq!(verify(&loaded), loaded.result);
+    }
+}
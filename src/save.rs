@@ -0,0 +1,165 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+use crate::flags::{self, Flags};
+use crate::inventory::Inventory;
+use crate::monster;
+use crate::ActiveMons;
+
+const SAVE_PATH: &str = "./save.txt";
+
+pub struct SaveData {
+    pub team: Vec<ActiveMons>,
+    pub inventory: Inventory,
+    pub flags: Flags,
+    // Saves from before money existed don't have any recorded
+    pub money: Option<u32>,
+}
+
+// Each line of the save is a record type followed by its fields, e.g. "item,Potion,3"
+pub fn save_game(
+    team: &[ActiveMons],
+    inventory: &Inventory,
+    game_flags: &Flags,
+    money: u32,
+) -> Result<(), String> {
+    let mut file = File::create(SAVE_PATH).map_err(|e| e.to_string())?;
+    writeln!(file, "money,{}", money).map_err(|e| e.to_string())?;
+    for mon in team.iter() {
+        let held = match &mon.held_item {
+            Some(item) => item.clone(),
+            None => String::new(),
+        };
+        writeln!(
+            file,
+            "monster,{},{},{},{},{},{},{},{}",
+            mon.name,
+            mon.hp,
+            held,
+            mon.level,
+            mon.exp,
+            mon.attack_iv,
+            mon.defense_iv,
+            monster::nature_name(mon.nature)
+        ).map_err(|e| e.to_string())?;
+    }
+    for (name, count) in inventory.items.iter() {
+        writeln!(file, "item,{},{}", name, count).map_err(|e| e.to_string())?;
+    }
+    for flag in game_flags.sorted() {
+        writeln!(file, "flag,{}", flag).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Returns None if there is no save yet
+pub fn load_game() -> Option<SaveData> {
+    let file = File::open(SAVE_PATH).ok()?;
+    let reader = BufReader::new(file);
+    let lines = reader.lines().collect::<Result<Vec<String>, _>>().ok()?;
+    parse_save(&lines)
+}
+
+// Reads the lines of a save, or None if one of them is broken
+fn parse_save(lines: &[String]) -> Option<SaveData> {
+    let mut data = SaveData {
+        team: Vec::new(),
+        inventory: Inventory::new(),
+        flags: Flags::new(),
+        money: None,
+    };
+    for line in lines.iter() {
+        let v = line.split(",").collect::<Vec<&str>>();
+        match v[0] {
+            "monster" => data.team.push(ActiveMons {
+                name: String::from(*v.get(1)?),
+                hp: v.get(2)?.parse::<f32>().ok()?,
+                held_item: v.get(3).filter(|d| !d.is_empty()).map(|d| String::from(*d)),
+                // Saves from before levels existed start everyone at the starting level
+                level: v
+                    .get(4)
+                    .and_then(|d| d.parse::<u32>().ok())
+                    .unwrap_or(monster::STARTING_LEVEL),
+                exp: v.get(5).and_then(|d| d.parse::<u32>().ok()).unwrap_or(0),
+                // Older saves didn't roll individual values, so those monsters are average
+                attack_iv: v
+                    .get(6)
+                    .and_then(|d| d.parse::<u32>().ok())
+                    .unwrap_or(monster::IV_MAX / 2),
+                defense_iv: v
+                    .get(7)
+                    .and_then(|d| d.parse::<u32>().ok())
+                    .unwrap_or(monster::IV_MAX / 2),
+                nature: v
+                    .get(8)
+                    .and_then(|d| monster::parse_nature(d).ok())
+                    .unwrap_or(monster::Nature::Hardy),
+            }),
+            "item" => data.inventory.add(v.get(1)?, v.get(2)?.parse::<u32>().ok()?),
+            // Saves from before story flags only listed the trainers beaten
+            "trainer" => {
+                data.flags.set(&flags::defeated(v.get(1)?));
+            }
+            "flag" => {
+                data.flags.set(&v[1..].join(","));
+            }
+            "money" => data.money = Some(v.get(1)?.parse::<u32>().ok()?),
+            _ => {}
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Option<SaveData> {
+        parse_save(&lines.iter().map(|d| String::from(*d)).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn current_saves_load_every_field() {
+        let data = parse(&[
+            "money,250",
+            "monster,Reusoon,42.5,Leftovers,9,120,3,28,Lonely",
+            "item,Potion,3",
+            "flag,beat,Gym Leader",
+        ])
+        .unwrap();
+        assert_eq!(data.money, Some(250));
+        let mon = &data.team[0];
+        assert_eq!(mon.name, "Reusoon");
+        assert_eq!(mon.hp, 42.5);
+        assert_eq!(mon.held_item, Some(String::from("Leftovers")));
+        assert_eq!((mon.level, mon.exp), (9, 120));
+        assert_eq!((mon.attack_iv, mon.defense_iv), (3, 28));
+        assert!(mon.nature == monster::Nature::Lonely);
+        assert_eq!(data.inventory.count("Potion"), 3);
+        assert!(data.flags.has("beat,Gym Leader"));
+    }
+
+    #[test]
+    fn old_saves_take_defaults() {
+        let data = parse(&["monster,Reusoon,100", "trainer,Bug Catcher"]).unwrap();
+        assert_eq!(data.money, None);
+        let mon = &data.team[0];
+        assert_eq!(mon.held_item, None);
+        assert_eq!((mon.level, mon.exp), (monster::STARTING_LEVEL, 0));
+        assert_eq!((mon.attack_iv, mon.defense_iv), (monster::IV_MAX / 2, monster::IV_MAX / 2));
+        assert!(mon.nature == monster::Nature::Hardy);
+        assert!(data.flags.has(&flags::defeated("Bug Catcher")));
+    }
+
+    #[test]
+    fn broken_saves_do_not_load() {
+        assert!(parse(&["monster,Reusoon,full"]).is_none());
+        assert!(parse(&["item,Potion,lots"]).is_none());
+        assert!(parse(&["money,rich"]).is_none());
+        // Lines cut off before their fields
+        assert!(parse(&["monster,Reusoon"]).is_none());
+        assert!(parse(&["item,Potion"]).is_none());
+        assert!(parse(&["trainer"]).is_none());
+        assert!(parse(&["money"]).is_none());
+    }
+}
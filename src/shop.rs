@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::battle;
+use crate::inventory;
+
+// What a new game starts with
+pub const STARTING_MONEY: u32 = 1000;
+// The most of one item that can be bought or sold at once
+const MAX_QUANTITY: u32 = 99;
+const SHOP_ROWS: usize = 10;
+
+pub struct Shop {
+    // Whether the player is selling from their bag rather than buying
+    pub selling: bool,
+    pub choice: usize,
+    // How many of the selected item to buy or sell, once an item has been picked
+    pub quantity: Option<u32>,
+    pub message: Option<String>,
+}
+
+// Items go back to the shop for half what they cost
+pub fn sell_price(item: &inventory::Item) -> u32 {
+    item.price / 2
+}
+
+impl Shop {
+    pub fn new() -> Shop {
+        Shop {
+            selling: false,
+            choice: 0,
+            quantity: None,
+            message: None,
+        }
+    }
+
+    // The names of the items on the current list, cheapest first when buying
+    pub fn listing(&self, bag: &inventory::Inventory, items: &HashMap<String, inventory::Item>) -> Vec<String> {
+        if self.selling {
+            bag.items
+                .iter()
+                .filter(|d| sell_price(&items[&d.0]) > 0)
+                .map(|d| d.0.clone())
+                .collect()
+        } else {
+            let mut stock: Vec<&inventory::Item> = items.values().filter(|d| d.price > 0).collect();
+            stock.sort_by(|a, b| a.price.cmp(&b.price).then(a.name.cmp(&b.name)));
+            stock.iter().map(|d| d.name.clone()).collect()
+        }
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.selling = !self.selling;
+        self.choice = 0;
+        self.quantity = None;
+        self.message = None;
+    }
+
+    // The most of an item the player can afford, or has to sell
+    pub fn max_quantity(&self, bag: &inventory::Inventory, item: &inventory::Item, money: u32) -> u32 {
+        if self.selling {
+            bag.count(&item.name).min(MAX_QUANTITY)
+        } else {
+            (money / item.price).min(MAX_QUANTITY)
+        }
+    }
+
+    /// Buys or sells the chosen quantity of the selected item
+    ///
+    /// * `bag` - The player's inventory
+    /// * `items` - Maps item names onto their definitions
+    /// * `money` - The player's money, updated with the cost or earnings
+    pub fn confirm(
+        &mut self,
+        bag: &mut inventory::Inventory,
+        items: &HashMap<String, inventory::Item>,
+        money: &mut u32,
+    ) {
+        let listing = self.listing(bag, items);
+        let (name, quantity) = match (listing.get(self.choice), self.quantity) {
+            (Some(name), Some(quantity)) if quantity > 0 => (name.clone(), quantity),
+            _ => return,
+        };
+        let item = &items[&name];
+        // Never more than the player can afford, or has to sell
+        let quantity = quantity.min(self.max_quantity(bag, item, *money));
+        if quantity == 0 {
+            return;
+        }
+        if self.selling {
+            let earned = sell_price(item) * quantity;
+            for _ in 0..quantity {
+                bag.consume(&name);
+            }
+            *money += earned;
+            self.message = Some(format!("Sold {} {} for ${}.", quantity, name, earned));
+            // The sold item may have left the bag entirely
+            self.choice = self.choice.min(self.listing(bag, items).len().max(1) - 1);
+        } else {
+            let cost = item.price * quantity;
+            *money -= cost;
+            bag.add(&name, quantity);
+            self.message = Some(format!("Bought {} {} for ${}.", quantity, name, cost));
+        }
+        self.quantity = None;
+    }
+}
+
+impl Default for Shop {
+    fn default() -> Shop {
+        Shop::new()
+    }
+}
+
+/// Draws the shop's buy or sell list with the quantity being chosen
+///
+/// * `battle_init` - Holds the font
+/// * `shop` - The shop's current selection
+/// * `bag` - The player's inventory
+/// * `items` - Maps item names onto their definitions
+/// * `money` - How much the player has to spend
+pub fn draw_shop(
+    wincan: &mut sdl2::render::WindowCanvas,
+    battle_init: &battle::Battle,
+    shop: &Shop,
+    bag: &inventory::Inventory,
+    items: &HashMap<String, inventory::Item>,
+    money: u32,
+) -> Result<(), String> {
+    let outline_size = 5;
+    let light = Color::RGB(0xbd, 0xcd, 0xde);
+
+    wincan.set_draw_color(Color::RGB(0x10, 0x18, 0x30));
+    wincan.fill_rect(Rect::new(0, 0, 1280, 720))?;
+    wincan.set_draw_color(Color::RGB(0x20, 0x41, 0x6a));
+    wincan.fill_rect(Rect::new(100, 80, 640, 560))?;
+    wincan.fill_rect(Rect::new(760, 80, 420, 560))?;
+
+    let title = if shop.selling { "Sell   (A/D: Buy)" } else { "Buy   (A/D: Sell)" };
+    battle::draw_text(wincan, battle_init.font, title, light, Rect::new(120, 90, 600, 40))?;
+
+    let listing = shop.listing(bag, items);
+    if listing.is_empty() {
+        battle::draw_text(wincan, battle_init.font, "Nothing to sell", light, Rect::new(120, 140, 600, 38))?;
+    }
+    // Scroll the list once the selection goes past the last visible slot
+    let first = if shop.choice >= SHOP_ROWS { shop.choice + 1 - SHOP_ROWS } else { 0 };
+    for (index, name) in listing.iter().enumerate().skip(first).take(SHOP_ROWS) {
+        let r = Rect::new(120, 140 + ((index - first) as i32) * 44, 600, 38);
+        if index == shop.choice {
+            wincan.set_draw_color(Color::RGB(0xf6, 0x52, 0x41));
+            wincan.fill_rect(Rect::new(
+                r.x() - outline_size,
+                r.y() - outline_size,
+                r.width() + (2 * outline_size) as u32,
+                r.height() + (2 * outline_size) as u32,
+            ))?;
+        }
+        wincan.set_draw_color(Color::RGB(0x39, 0x7B, 0xB4));
+        wincan.fill_rect(r)?;
+
+        let item = &items[name];
+        let f = if shop.selling {
+            format!("{} x{}   ${}", name, bag.count(name), sell_price(item))
+        } else {
+            format!("{}   ${}", name, item.price)
+        };
+        battle::draw_text(wincan, battle_init.font, &f, Color::BLACK, r)?;
+    }
+
+    // Money, the selected item and how many of it are being bought or sold
+    let f = format!("Money: ${}", money);
+    battle::draw_text(wincan, battle_init.font, &f, light, Rect::new(780, 100, 380, 40))?;
+    if let Some(name) = listing.get(shop.choice) {
+        let item = &items[name];
+        battle::draw_text(wincan, battle_init.font, &item.description, light, Rect::new(780, 160, 380, 35))?;
+        let f = format!("In bag: {}", bag.count(name));
+        battle::draw_text(wincan, battle_init.font, &f, light, Rect::new(780, 210, 380, 35))?;
+        if let Some(quantity) = shop.quantity {
+            let price = if shop.selling { sell_price(item) } else { item.price };
+            wincan.set_draw_color(Color::RGB(0x39, 0x7B, 0xB4));
+            wincan.fill_rect(Rect::new(780, 280, 380, 120))?;
+            let f = format!("< x{} >", quantity);
+            battle::draw_text(wincan, battle_init.font, &f, Color::BLACK, Rect::new(790, 290, 360, 50))?;
+            let f = format!("Total: ${}", price * quantity);
+            battle::draw_text(wincan, battle_init.font, &f, Color::BLACK, Rect::new(790, 345, 360, 45))?;
+        }
+    }
+
+    let f = match &shop.message {
+        Some(text) => text.clone(),
+        None => match shop.quantity {
+            Some(_) => String::from("W/S to change, Return to confirm, Backspace to cancel"),
+            None => String::from("Return to choose, Backspace to leave"),
+        },
+    };
+    battle::draw_text(wincan, battle_init.font, &f, light, Rect::new(120, 595, 1040, 35))?;
+
+    wincan.present();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_items() -> HashMap<String, inventory::Item> {
+        let mut items = HashMap::new();
+        for (name, price) in [("Potion", 200), ("Ball", 300), ("Gift", 0)].iter() {
+            items.insert(
+                String::from(*name),
+                inventory::Item {
+                    name: String::from(*name),
+                    kind: inventory::ItemKind::Heal,
+                    amount: 10,
+                    param: String::from("0"),
+                    price: *price,
+                    description: String::new(),
+                },
+            );
+        }
+        items
+    }
+
+    #[test]
+    fn listings_leave_out_priceless_items() {
+        let items = test_items();
+        let mut bag = inventory::Inventory::new();
+        bag.add("Gift", 1);
+        bag.add("Ball", 2);
+        let mut shop = Shop::new();
+        assert_eq!(shop.listing(&bag, &items), vec![String::from("Potion"), String::from("Ball")]);
+        shop.toggle_mode();
+        assert_eq!(shop.listing(&bag, &items), vec![String::from("Ball")]);
+    }
+
+    #[test]
+    fn quantities_stop_at_money_and_stock() {
+        let items = test_items();
+        let mut bag = inventory::Inventory::new();
+        bag.add("Potion", 150);
+        let mut shop = Shop::new();
+        assert_eq!(shop.max_quantity(&bag, &items["Potion"], 650), 3);
+        assert_eq!(shop.max_quantity(&bag, &items["Potion"], 199), 0);
+        assert_eq!(shop.max_quantity(&bag, &items["Potion"], 1_000_000), MAX_QUANTITY);
+        shop.toggle_mode();
+        assert_eq!(shop.max_quantity(&bag, &items["Potion"], 0), MAX_QUANTITY);
+        assert_eq!(shop.max_quantity(&bag, &items["Ball"], 0), 0);
+    }
+
+    #[test]
+    fn buying_costs_the_full_price() {
+        let items = test_items();
+        let mut bag = inventory::Inventory::new();
+        let mut shop = Shop::new();
+        let mut money = 700;
+        shop.quantity = Some(3);
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!((money, bag.count("Potion")), (100, 3));
+        assert_eq!(shop.quantity, None);
+
+        // Asking for more than the money covers buys what it can, without going into debt
+        shop.choice = 1;
+        shop.quantity = Some(5);
+        money = 650;
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!((money, bag.count("Ball")), (50, 2));
+        shop.quantity = Some(1);
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!((money, bag.count("Ball")), (50, 2));
+    }
+
+    #[test]
+    fn selling_pays_half_and_keeps_the_choice_on_the_list() {
+        let items = test_items();
+        let mut bag = inventory::Inventory::new();
+        bag.add("Potion", 2);
+        bag.add("Ball", 1);
+        let mut shop = Shop::new();
+        shop.toggle_mode();
+        let mut money = 0;
+
+        shop.choice = 1;
+        shop.quantity = Some(4);
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!((money, bag.count("Ball")), (150, 0));
+        assert_eq!(shop.choice, 0);
+
+        shop.quantity = Some(2);
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!((money, bag.count("Potion")), (350, 0));
+        assert!(shop.listing(&bag, &items).is_empty());
+        assert_eq!(shop.choice, 0);
+
+        // Nothing left to sell does nothing
+        shop.quantity = Some(1);
+        shop.confirm(&mut bag, &items, &mut money);
+        assert_eq!(money, 350);
+    }
+}
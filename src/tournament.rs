@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::ai;
+use crate::monster;
+
+// Turns, by either side, before a battle is called a draw
+const MAX_TURNS: u32 = 300;
+// Monsters on each side of a randomly generated battle
+const TEAM_SIZE: usize = 3;
+// Standard score for a 95% confidence interval
+const Z_95: f64 = 1.96;
+// MCTS in the tournament searches like the hard difficulty, apart from its iterations
+const MCTS_EXPLORATION: f64 = 1.4;
+const MCTS_ROLLOUT_DEPTH: u32 = 20;
+
+// A way of picking actions that can be entered into the tournament
+#[derive(Clone, Copy, PartialEq)]
+pub enum Policy {
+    // Any action, at random
+    Random,
+    // The move expected to deal the most damage
+    Greedy,
+    // α-β searching this many actions ahead
+    AlphaBeta(i32),
+    // MCTS with this many iterations
+    Mcts(u32),
+}
+
+// Policies are written as "random", "greedy", "alphabeta:<depth>" or "mcts:<iterations>"
+pub fn parse_policy(policy: &str) -> Result<Policy, String> {
+    let v = policy.split(":").collect::<Vec<&str>>();
+    match (v[0], v.get(1)) {
+        ("random", None) => Ok(Policy::Random),
+        ("greedy", None) => Ok(Policy::Greedy),
+        ("alphabeta", Some(depth)) => depth
+            .parse::<i32>()
+            .map(Policy::AlphaBeta)
+            .map_err(|_| format!("Depth of {} should be a number", policy)),
+        ("mcts", Some(iterations)) => iterations
+            .parse::<u32>()
+            .map(Policy::Mcts)
+            .map_err(|_| format!("Iterations of {} should be a number", policy)),
+        _ => Err(format!("Unknown policy {}", policy)),
+    }
+}
+
+pub fn policy_name(policy: Policy) -> String {
+    match policy {
+        Policy::Random => String::from("random"),
+        Policy::Greedy => String::from("greedy"),
+        Policy::AlphaBeta(depth) => format!("alphabeta:{}", depth),
+        Policy::Mcts(iterations) => format!("mcts:{}", iterations),
+    }
+}
+
+/// Picks an action for either side with the given policy
+///
+/// * `maximizing_player` - Whether to pick for the enemy's side, rather than the player's
+/// * `weights` - How the searches evaluate states
+/// * `rng` - Picks random actions and drives MCTS
+fn choose(
+    policy: Policy,
+    monsters: &HashMap<String, monster::Monster>,
+    state: &monster::BattleState,
+    maximizing_player: bool,
+    weights: &ai::Weights,
+    rng: &mut StdRng,
+) -> usize {
+    match policy {
+        Policy::Random => rng.gen_range(0..ai::num_actions(state, maximizing_player)),
+        Policy::Greedy => ai::greedy_action(monsters, state, maximizing_player),
+        Policy::AlphaBeta(depth) => {
+            let mut new_state = state.clone();
+            ai::alphabeta(
+                monsters,
+                &mut new_state,
+                depth,
+                -f64::INFINITY,
+                f64::INFINITY,
+                maximizing_player,
+                weights,
+            )
+            .1
+            .unwrap_or(0)
+        }
+        Policy::Mcts(iterations) => {
+            let config = ai::MctsConfig {
+                budget: ai::Budget::Iterations(iterations),
+                rollout: ai::Rollout::Greedy,
+                exploration: MCTS_EXPLORATION,
+                rollout_depth: MCTS_ROLLOUT_DEPTH,
+            };
+            ai::mcts(monsters, state, maximizing_player, &config, weights, rng).unwrap_or(0)
+        }
+    }
+}
+
+// How one policy did against another over every game between them
+pub struct Pairing {
+    pub first: Policy,
+    pub second: Policy,
+    pub first_wins: u32,
+    pub second_wins: u32,
+    pub draws: u32,
+    // Summed over every game, for the average
+    pub turns: u64,
+}
+
+impl Pairing {
+    pub fn games(&self) -> u32 {
+        self.first_wins + self.second_wins + self.draws
+    }
+
+    // The fraction of games the first policy won; draws count as not winning
+    pub fn win_rate(&self) -> f64 {
+        self.first_wins as f64 / self.games().max(1) as f64
+    }
+
+    // The 95% Wilson score interval around the first policy's win rate
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let n = self.games().max(1) as f64;
+        let p = self.win_rate();
+        let z2 = Z_95 * Z_95;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+        ((center - half).max(0.0), (center + half).min(1.0))
+    }
+
+    pub fn average_turns(&self) -> f64 {
+        self.turns as f64 / self.games().max(1) as f64
+    }
+}
+
+// The trainers' teams to battle with, each as its species and level
+//
+// Only reads the columns it needs from trainers.txt, since the rest of a trainer is for the overworld
+pub fn trainer_teams() -> Vec<(Vec<String>, u32)> {
+    let reader = BufReader::new(File::open("./data/trainers.txt").expect("Cannot open trainers.txt"));
+    let mut teams = Vec::new();
+    for line in reader.lines().skip(1) {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        let level = v[6].parse::<u32>().expect("Trainer level should be a number");
+        let team: Vec<String> = v[9].split(";").filter(|d| !d.is_empty()).map(String::from).collect();
+        if !team.is_empty() {
+            teams.push((team, level));
+        }
+    }
+    teams
+}
+
+/// Plays every policy against every other over seeded battles
+///
+/// Each battle is played twice with the sides swapped, since the player's and the enemy's
+/// monsters aren't quite even. Battles use two of the given teams, or random teams if
+/// there are none.
+///
+/// * `monsters` - Maps strings onto their Monster objects
+/// * `policies` - The policies to enter
+/// * `teams` - Teams to pick from, as their species and level
+/// * `battles` - Battles to play per pairing, each twice
+/// * `seed` - Seeds the first battle; the rest count up from it
+/// * `weights` - How the searches evaluate states
+pub fn run_tournament(
+    monsters: &HashMap<String, monster::Monster>,
+    policies: &[Policy],
+    teams: &[(Vec<String>, u32)],
+    battles: u32,
+    seed: u64,
+    weights: &ai::Weights,
+) -> Vec<Pairing> {
+    let mut names: Vec<String> = monsters.keys().cloned().collect();
+    names.sort();
+
+    let mut results = Vec::new();
+    for (index, first) in policies.iter().enumerate() {
+        for second in policies.iter().skip(index + 1) {
+            let mut pairing = Pairing {
+                first: *first,
+                second: *second,
+                first_wins: 0,
+                second_wins: 0,
+                draws: 0,
+                turns: 0,
+            };
+            for battle in 0..battles as u64 {
+                let mut rng = StdRng::seed_from_u64(seed + battle);
+                let state = if teams.is_empty() {
+                    ai::random_battle(&names, TEAM_SIZE, monster::STARTING_LEVEL, &mut rng)
+                } else {
+                    let player = &teams[rng.gen_range(0..teams.len())];
+                    let enemy = &teams[rng.gen_range(0..teams.len())];
+                    ai::team_battle(&player.0, player.1, &enemy.0, enemy.1, &mut rng)
+                };
+
+                for (enemy, player, first_is_enemy) in [(*first, *second, true), (*second, *first, false)].iter() {
+                    // Both games of a battle get the same rolls
+                    let mut rng = StdRng::seed_from_u64(seed + battle);
+                    let mut player_policy = |state: &monster::BattleState, rng: &mut StdRng| {
+                        choose(*player, monsters, state, false, weights, rng)
+                    };
+                    let mut enemy_policy = |state: &monster::BattleState, rng: &mut StdRng| {
+                        choose(*enemy, monsters, state, true, weights, rng)
+                    };
+                    let (result, turns) = ai::play_match(
+                        monsters,
+                        &state,
+                        &mut player_policy,
+                        &mut enemy_policy,
+                        &mut rng,
+                        MAX_TURNS,
+                    );
+                    pairing.turns += turns as u64;
+                    match result {
+                        Some(enemy_won) if enemy_won == *first_is_enemy => pairing.first_wins += 1,
+                        Some(_) => pairing.second_wins += 1,
+                        None => pairing.draws += 1,
+                    }
+                }
+            }
+            results.push(pairing);
+        }
+    }
+    results
+}
+
+pub fn print_table(results: &[Pairing]) {
+    println!(
+        "{:<16} {:<16} {:>7} {:>6} {:>6} {:>6} {:>9} {:>17} {:>9}",
+        "first", "second", "games", "wins", "losses", "draws", "win rate", "95% interval", "avg turns"
+    );
+    for pairing in results.iter() {
+        let (low, high) = pairing.confidence_interval();
+        println!(
+            "{:<16} {:<16} {:>7} {:>6} {:>6} {:>6} {:>8.1}% {:>7.1}% - {:>5.1}% {:>9.1}",
+            policy_name(pairing.first),
+            policy_name(pairing.second),
+            pairing.games(),
+            pairing.first_wins,
+            pairing.second_wins,
+            pairing.draws,
+            100.0 * pairing.win_rate(),
+            100.0 * low,
+            100.0 * high,
+            pairing.average_turns()
+        );
+    }
+}
+
+pub fn write_csv(results: &[Pairing], path: &str) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    writeln!(
+        file,
+        "first,second,games,first_wins,second_wins,draws,win_rate,ci_low,ci_high,average_turns"
+    )
+    .map_err(|e| e.to_string())?;
+    for pairing in results.iter() {
+        let (low, high) = pairing.confidence_interval();
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.2}",
+            policy_name(pairing.first),
+            policy_name(pairing.second),
+            pairing.games(),
+            pairing.first_wins,
+            pairing.second_wins,
+            pairing.draws,
+            pairing.win_rate(),
+            low,
+            high,
+            pairing.average_turns()
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairing(first_wins: u32, second_wins: u32, draws: u32) -> Pairing {
+        Pairing {
+            first: Policy::Greedy,
+            second: Policy::AlphaBeta(2),
+            first_wins,
+            second_wins,
+            draws,
+            turns: 40 * (first_wins + second_wins + draws) as u64,
+        }
+    }
+
+    #[test]
+    fn policies_round_trip_through_their_names() {
+        for policy in [Policy::Random, Policy::Greedy, Policy::AlphaBeta(3), Policy::Mcts(200)].iter() {
+            assert!(parse_policy(&policy_name(*policy)) == Ok(*policy));
+        }
+        assert!(parse_policy("alphabeta").is_err());
+        assert!(parse_policy("mcts:lots").is_err());
+        assert!(parse_policy("minimax:2").is_err());
+    }
+
+    #[test]
+    fn intervals_are_wilson_scores() {
+        // 8 wins in 10 games, worked out by hand
+        let (low, high) = pairing(8, 2, 0).confidence_interval();
+        assert!((low - 0.4902).abs() < 1e-4);
+        assert!((high - 0.9433).abs() < 1e-4);
+
+        // Even results sit in the middle, and more games narrow the interval
+        let (low, high) = pairing(5, 5, 0).confidence_interval();
+        assert!((low + high - 1.0).abs() < 1e-9);
+        let (more_low, more_high) = pairing(50, 50, 0).confidence_interval();
+        assert!(more_high - more_low < high - low);
+
+        // Draws count against the first policy, and the interval stays within 0 and 1
+        let (low, high) = pairing(0, 0, 10).confidence_interval();
+        assert!(low == 0.0 && high > 0.0 && high < 1.0);
+        let (low, high) = pairing(10, 0, 0).confidence_interval();
+        assert!(low > 0.0 && high == 1.0);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_pairing() {
+        let path = std::env::temp_dir().join("sdl_rust_tournament.csv");
+        let path = path.to_str().unwrap();
+        write_csv(&[pairing(8, 2, 0), pairing(3, 4, 3)], path).unwrap();
+        let csv = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "first,second,games,first_wins,second_wins,draws,win_rate,ci_low,ci_high,average_turns",
+                "greedy,alphabeta:2,10,8,2,0,0.8000,0.4902,0.9433,40.00",
+                "greedy,alphabeta:2,10,3,4,3,0.3000,0.1078,0.6032,40.00",
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+
+use crate::ai;
+use crate::flags;
+
+// Trainers are drawn at the same size as the player
+const TRAINER_SIZE: u32 = 32;
+// How far a trainer moves each frame when walking up to the player
+const WALK_SPEED: i32 = 3;
+// How close the player has to stand to talk to a trainer
+const REACH: i32 = 4;
+// Prize money for each level of each monster on a trainer's team
+const PRIZE_PER_LEVEL: u32 = 20;
+// Gym leaders pay out this many times more
+const GYM_LEADER_PRIZE: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Facing {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+pub struct Trainer {
+    pub name: String,
+    // Where the trainer stands or starts their patrol
+    pub home: (i32, i32),
+    pub rect: Rect,
+    pub facing: Facing,
+    // How many pixels ahead of them the trainer can see
+    pub sight: i32,
+    // How far to the right of home the trainer walks back and forth, 0 if they stand still
+    pub patrol: i32,
+    pub level: u32,
+    pub double: bool,
+    pub gym_leader: bool,
+    pub team: Vec<String>,
+    pub before: String,
+    pub after: String,
+    // How the trainer picks their actions in battle
+    pub profile: ai::Profile,
+}
+
+fn parse_facing(facing: &str) -> Facing {
+    match facing {
+        "Up" => Facing::Up,
+        "Down" => Facing::Down,
+        "Left" => Facing::Left,
+        "Right" => Facing::Right,
+        _ => panic!("Unknown facing {}", facing),
+    }
+}
+
+pub fn load_trainers() -> Vec<Trainer> {
+    let reader = BufReader::new(File::open("./data/trainers.txt").expect("Cannot open trainers.txt"));
+    let mut trainers = Vec::new();
+    for (n, line) in reader.lines().skip(1).enumerate() {
+        let v = line.unwrap();
+        let v = v.split(",").collect::<Vec<&str>>();
+
+        let x = v[1].parse::<i32>().expect("Trainer x should be a number");
+        let y = v[2].parse::<i32>().expect("Trainer y should be a number");
+        trainers.push(Trainer {
+            name: String::from(v[0]),
+            home: (x, y),
+            rect: Rect::new(x, y, TRAINER_SIZE, TRAINER_SIZE),
+            facing: parse_facing(v[3]),
+            sight: v[4].parse::<i32>().expect("Trainer sight should be a number"),
+            patrol: v[5].parse::<i32>().expect("Trainer patrol should be a number"),
+            level: v[6].parse::<u32>().expect("Trainer level should be a number"),
+            double: v[7] == "true",
+            gym_leader: v[8] == "true",
+            team: v[9].split(";").map(String::from).collect(),
+            before: String::from(v[10]),
+            after: String::from(v[11]),
+            // Line numbers count the header
+            profile: ai::parse_profile(v[12]).unwrap_or_else(|e| panic!("trainers.txt line {}: {}", n + 2, e)),
+        });
+    }
+    trainers
+}
+
+impl Trainer {
+    // Trainer teams always start at full health
+    pub fn enemy_team(&self) -> Vec<(String, f32)> {
+        self.team.iter().map(|d| (d.clone(), 100.0)).collect()
+    }
+
+    // What beating the trainer pays
+    pub fn prize(&self) -> u32 {
+        let prize = PRIZE_PER_LEVEL * self.level * self.team.len() as u32;
+        if self.gym_leader {
+            prize * GYM_LEADER_PRIZE
+        } else {
+            prize
+        }
+    }
+
+    pub fn title(&self) -> String {
+        if self.gym_leader {
+            format!("Gym Leader {}", self.name)
+        } else {
+            format!("Trainer {}", self.name)
+        }
+    }
+
+    // Walks one pixel along the patrol, turning around at either end
+    pub fn patrol_step(&mut self) {
+        if self.patrol == 0 {
+            return;
+        }
+        let x = self.rect.x();
+        if x >= self.home.0 + self.patrol {
+            self.facing = Facing::Left;
+        } else if x <= self.home.0 {
+            self.facing = Facing::Right;
+        }
+        let step = if self.facing == Facing::Left { -1 } else { 1 };
+        self.rect.set_x((x + step).clamp(self.home.0, self.home.0 + self.patrol));
+    }
+
+    // The strip of ground in front of the trainer that they can see
+    pub fn sight_box(&self) -> Rect {
+        let (x, y) = (self.rect.x(), self.rect.y());
+        let sight = self.sight.max(1) as u32;
+        match self.facing {
+            Facing::Up => Rect::new(x, y - self.sight, TRAINER_SIZE, sight),
+            Facing::Down => Rect::new(x, self.rect.bottom(), TRAINER_SIZE, sight),
+            Facing::Left => Rect::new(x - self.sight, y, sight, TRAINER_SIZE),
+            Facing::Right => Rect::new(self.rect.right(), y, sight, TRAINER_SIZE),
+        }
+    }
+
+    // The area the player has to be in to talk to, or bump into, the trainer
+    pub fn reach(&self) -> Rect {
+        Rect::new(
+            self.rect.x() - REACH,
+            self.rect.y() - REACH,
+            TRAINER_SIZE + 2 * REACH as u32,
+            TRAINER_SIZE + 2 * REACH as u32,
+        )
+    }
+
+    // Whether the trainer notices the player, either by seeing them or being bumped into
+    pub fn spots(&self, player: &Rect) -> bool {
+        (self.sight > 0 && self.sight_box().has_intersection(*player)) || self.reach().has_intersection(*player)
+    }
+
+    // Walks towards the player along the direction the trainer faces, returning true once next to them
+    pub fn step_toward(&mut self, player: &Rect) -> bool {
+        if self.reach().has_intersection(*player) {
+            return true;
+        }
+        let gap = match self.facing {
+            Facing::Up => self.rect.top() - player.bottom(),
+            Facing::Down => player.top() - self.rect.bottom(),
+            Facing::Left => self.rect.left() - player.right(),
+            Facing::Right => player.left() - self.rect.right(),
+        };
+        let step = WALK_SPEED.min(gap.max(0));
+        match self.facing {
+            Facing::Up => self.rect.set_y(self.rect.y() - step),
+            Facing::Down => self.rect.set_y(self.rect.y() + step),
+            Facing::Left => self.rect.set_x(self.rect.x() - step),
+            Facing::Right => self.rect.set_x(self.rect.x() + step),
+        }
+        step == 0
+    }
+
+    // Sends the trainer back to where they started, e.g. after the player blacked out
+    pub fn return_home(&mut self) {
+        self.rect.set_x(self.home.0);
+        self.rect.set_y(self.home.1);
+    }
+}
+
+// Each gym leader beaten earns a badge
+pub fn badges(trainers: &[Trainer], game_flags: &flags::Flags) -> usize {
+    trainers
+        .iter()
+        .filter(|d| d.gym_leader && game_flags.has(&flags::defeated(&d.name)))
+        .count()
+}
+
+/// Draws every trainer on the overworld
+///
+/// * `standing` - Texture for trainers who stand still
+/// * `walking` - Sprite sheet for trainers who patrol; the first frame faces right
+pub fn draw_trainers(
+    wincan: &mut sdl2::render::WindowCanvas,
+    trainers: &[Trainer],
+    standing: &Texture,
+    walking: &Texture,
+) -> Result<(), String> {
+    for trainer in trainers.iter() {
+        if trainer.patrol == 0 {
+            wincan.copy(standing, None, trainer.rect)?;
+        } else {
+            wincan.copy_ex(
+                walking,
+                Rect::new(0, 0, TRAINER_SIZE, TRAINER_SIZE),
+                trainer.rect,
+                0.0,
+                None,
+                trainer.facing == Facing::Left,
+                false,
+            )?;
+        }
+    }
+    Ok(())
+}
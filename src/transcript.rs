@@ -0,0 +1,322 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::monster;
+
+const LOG_DIR: &str = "./logs";
+
+pub const PLAYER_WON: &str = "Player won";
+pub const BLACKED_OUT: &str = "Player blacked out";
+
+pub enum Event {
+    Move {
+        user: String,
+        attack: String,
+        target: String,
+        damage: f32,
+        effectiveness: f32,
+    },
+    StatChange {
+        monster: String,
+        stat: String,
+        stages: i32,
+    },
+    Switch {
+        withdrawn: String,
+        sent_out: String,
+    },
+    Faint(String),
+    Message(String),
+}
+
+// Turn 0 holds everything that happens as the battle starts
+pub struct Turn {
+    pub number: u32,
+    pub events: Vec<Event>,
+}
+
+pub struct Transcript {
+    pub opponent: String,
+    pub player_team: Vec<String>,
+    pub enemy_team: Vec<String>,
+    pub turns: Vec<Turn>,
+    pub result: Option<String>,
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript {
+            opponent: String::new(),
+            player_team: Vec::new(),
+            enemy_team: Vec::new(),
+            turns: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn start(battle_state: &monster::BattleState) -> Transcript {
+        let opponent = if battle_state.wild {
+            "Wild monster"
+        } else if battle_state.double {
+            "Trainer (double battle)"
+        } else {
+            "Trainer"
+        };
+        Transcript {
+            opponent: String::from(opponent),
+            player_team: battle_state.player_team.iter().map(|d| d.name.clone()).collect(),
+            enemy_team: battle_state.enemy_team.iter().map(|d| d.0.clone()).collect(),
+            turns: vec![Turn {
+                number: 0,
+                events: Vec::new(),
+            }],
+            result: None,
+        }
+    }
+
+    pub fn log(&mut self, event: Event) {
+        match self.turns.last_mut() {
+            Some(turn) => turn.events.push(event),
+            None => self.turns.push(Turn {
+                number: 0,
+                events: vec![event],
+            }),
+        }
+    }
+
+    pub fn next_turn(&mut self) {
+        let number = self.turns.len() as u32;
+        self.turns.push(Turn {
+            number,
+            events: Vec::new(),
+        });
+    }
+
+    pub fn finish(&mut self, result: &str) {
+        self.result = Some(String::from(result));
+    }
+
+    // The transcript as plain text, one line per event
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(format!("Player vs {}", self.opponent));
+        lines.push(format!("Player team: {}", self.player_team.join(", ")));
+        lines.push(format!("Enemy team: {}", self.enemy_team.join(", ")));
+        for turn in self.turns.iter() {
+            if turn.events.is_empty() {
+                continue;
+            }
+            if turn.number == 0 {
+                lines.push(String::from("-- Start --"));
+            } else {
+                lines.push(format!("-- Turn {} --", turn.number));
+            }
+            for event in turn.events.iter() {
+                lines.push(describe(event));
+            }
+        }
+        match &self.result {
+            Some(result) => lines.push(format!("Result: {}", result)),
+            None => lines.push(String::from("Result: in progress")),
+        }
+        lines
+    }
+
+    pub fn to_json(&self) -> String {
+        let turns: Vec<String> = self
+            .turns
+            .iter()
+            .map(|turn| {
+                let events: Vec<String> = turn.events.iter().map(event_json).collect();
+                format!("{{\"turn\":{},\"events\":[{}]}}", turn.number, events.join(","))
+            })
+            .collect();
+        let result = match &self.result {
+            Some(result) => quote(result),
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"opponent\":{},\"player_team\":[{}],\"enemy_team\":[{}],\"turns\":[{}],\"result\":{}}}\n",
+            quote(&self.opponent),
+            self.player_team.iter().map(|d| quote(d)).collect::<Vec<String>>().join(","),
+            self.enemy_team.iter().map(|d| quote(d)).collect::<Vec<String>>().join(","),
+            turns.join(","),
+            result
+        )
+    }
+
+    // Writes the transcript as both text and JSON, returning the path without its extension
+    pub fn export(&self) -> Result<String, String> {
+        fs::create_dir_all(LOG_DIR).map_err(|e| e.to_string())?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_secs();
+        let path = format!("{}/battle_{}", LOG_DIR, time);
+
+        let mut file = File::create(format!("{}.txt", path)).map_err(|e| e.to_string())?;
+        for line in self.lines() {
+            writeln!(file, "{}", line).map_err(|e| e.to_string())?;
+        }
+        let mut file = File::create(format!("{}.json", path)).map_err(|e| e.to_string())?;
+        write!(file, "{}", self.to_json()).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+impl Default for Transcript {
+    fn default() -> Transcript {
+        Transcript::new()
+    }
+}
+
+fn describe(event: &Event) -> String {
+    match event {
+        Event::Move {
+            user,
+            attack,
+            target,
+            damage,
+            effectiveness,
+        } => {
+            if *damage > 0.0 || *effectiveness != 1.0 {
+                format!(
+                    "{} used {} on {}: {:.1} damage (x{})",
+                    user, attack, target, damage, effectiveness
+                )
+            } else {
+                format!("{} used {}", user, attack)
+            }
+        }
+        Event::StatChange {
+            monster,
+            stat,
+            stages,
+        } => format!("{}'s {} {:+}", monster, stat, stages),
+        Event::Switch {
+            withdrawn,
+            sent_out,
+        } => format!("{} was replaced by {}", withdrawn, sent_out),
+        Event::Faint(monster) => format!("{} fainted", monster),
+        Event::Message(text) => text.clone(),
+    }
+}
+
+fn event_json(event: &Event) -> String {
+    match event {
+        Event::Move {
+            user,
+            attack,
+            target,
+            damage,
+            effectiveness,
+        } => format!(
+            "{{\"type\":\"move\",\"user\":{},\"move\":{},\"target\":{},\"damage\":{:.2},\"effectiveness\":{}}}",
+            quote(user),
+            quote(attack),
+            quote(target),
+            damage,
+            effectiveness
+        ),
+        Event::StatChange {
+            monster,
+            stat,
+            stages,
+        } => format!(
+            "{{\"type\":\"stat_change\",\"monster\":{},\"stat\":{},\"stages\":{}}}",
+            quote(monster),
+            quote(stat),
+            stages
+        ),
+        Event::Switch {
+            withdrawn,
+            sent_out,
+        } => format!(
+            "{{\"type\":\"switch\",\"withdrawn\":{},\"sent_out\":{}}}",
+            quote(withdrawn),
+            quote(sent_out)
+        ),
+        Event::Faint(monster) => format!("{{\"type\":\"faint\",\"monster\":{}}}", quote(monster)),
+        Event::Message(text) => format!("{{\"type\":\"message\",\"text\":{}}}", quote(text)),
+    }
+}
+
+// A JSON string literal
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Every other control character has to be written as a unicode escape
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_transcript() -> Transcript {
+        let mut transcript = Transcript::new();
+        transcript.opponent = String::from("Trainer");
+        transcript.player_team = vec![String::from("Hero")];
+        transcript.enemy_team = vec![String::from("Foe")];
+        transcript.log(Event::Message(String::from("Go!")));
+        transcript.next_turn();
+        transcript.log(Event::Move {
+            user: String::from("Hero"),
+            attack: String::from("Tackle"),
+            target: String::from("Foe"),
+            damage: 12.5,
+            effectiveness: 2.0,
+        });
+        transcript.log(Event::Faint(String::from("Foe")));
+        transcript.finish(PLAYER_WON);
+        transcript
+    }
+
+    #[test]
+    fn quotes_escape_json() {
+        assert_eq!(quote("Hero"), "\"Hero\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote("a\\b"), "\"a\\\\b\"");
+        assert_eq!(quote("one\ntwo\tthree\r"), "\"one\\ntwo\\tthree\\r\"");
+        assert_eq!(quote("\u{1}"), "\"\\u0001\"");
+        assert_eq!(quote("Flabébé"), "\"Flabébé\"");
+    }
+
+    #[test]
+    fn events_are_logged_by_turn() {
+        let transcript = test_transcript();
+        assert_eq!(transcript.turns.len(), 2);
+        assert_eq!(transcript.turns[0].events.len(), 1);
+        assert_eq!(transcript.turns[1].events.len(), 2);
+
+        let lines = transcript.lines();
+        assert_eq!(lines[0], "Player vs Trainer");
+        assert!(lines.contains(&String::from("-- Turn 1 --")));
+        assert!(lines.contains(&String::from("Hero used Tackle on Foe: 12.5 damage (x2)")));
+        assert_eq!(lines.last().unwrap(), "Result: Player won");
+    }
+
+    #[test]
+    fn json_holds_every_event() {
+        let json = test_transcript().to_json();
+        assert!(json.starts_with("{\"opponent\":\"Trainer\",\"player_team\":[\"Hero\"],\"enemy_team\":[\"Foe\"]"));
+        assert!(json.contains("{\"type\":\"move\",\"user\":\"Hero\",\"move\":\"Tackle\",\"target\":\"Foe\",\"damage\":12.50,\"effectiveness\":2}"));
+        assert!(json.contains("{\"type\":\"faint\",\"monster\":\"Foe\"}"));
+        assert!(json.ends_with("\"result\":\"Player won\"}\n"));
+        // Braces and brackets stay balanced
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+    }
+}
@@ -0,0 +1,118 @@
+// Damage taken at the end of each turn by monsters the weather hurts
+const RESIDUAL_DAMAGE: f32 = 6.0;
+
+#[derive(Clone, Copy, PartialEq, Hash)]
+pub enum Weather {
+    Rain,
+    Sun,
+    Sandstorm,
+    Storm,
+}
+
+pub fn parse_weather(weather: &str) -> Result<Weather, String> {
+    match weather {
+        "Rain" => Ok(Weather::Rain),
+        "Sun" => Ok(Weather::Sun),
+        "Sandstorm" => Ok(Weather::Sandstorm),
+        "Storm" => Ok(Weather::Storm),
+        _ => Err(format!("Unknown weather {}", weather)),
+    }
+}
+
+// Multiplier the weather applies to moves of the given type
+pub fn type_multiplier(weather: Weather, attack_type: &str) -> f32 {
+    match weather {
+        Weather::Rain => match attack_type {
+            "Water" => 1.5,
+            "Fire" => 0.5,
+            _ => 1.0,
+        },
+        Weather::Sun => match attack_type {
+            "Fire" => 1.5,
+            "Water" => 0.5,
+            _ => 1.0,
+        },
+        Weather::Sandstorm => match attack_type {
+            "Ground" => 1.5,
+            "Flying" => 0.5,
+            _ => 1.0,
+        },
+        Weather::Storm => match attack_type {
+            "Electric" => 1.5,
+            "Flying" => 0.5,
+            _ => 1.0,
+        },
+    }
+}
+
+// Damage dealt to a monster of the given type at the end of a turn
+pub fn residual_damage(weather: Weather, monster_type: &String) -> f32 {
+    let immune = match weather {
+        Weather::Rain | Weather::Sun => return 0.0,
+        Weather::Sandstorm => monster_type == "Ground",
+        Weather::Storm => monster_type == "Electric" || monster_type == "Ground",
+    };
+    if immune {
+        0.0
+    } else {
+        RESIDUAL_DAMAGE
+    }
+}
+
+pub fn start_message(weather: Weather) -> String {
+    String::from(match weather {
+        Weather::Rain => "It started to rain!",
+        Weather::Sun => "The sunlight turned harsh!",
+        Weather::Sandstorm => "A sandstorm kicked up!",
+        Weather::Storm => "A thunderstorm rolled in!",
+    })
+}
+
+pub fn end_message(weather: Weather) -> String {
+    String::from(match weather {
+        Weather::Rain => "The rain stopped.",
+        Weather::Sun => "The sunlight faded.",
+        Weather::Sandstorm => "The sandstorm subsided.",
+        Weather::Storm => "The thunderstorm passed.",
+    })
+}
+
+pub fn damage_message(weather: Weather, monster: &str) -> String {
+    match weather {
+        Weather::Storm => format!("{} was struck by lightning!", monster),
+        _ => format!("{} is buffeted by the sandstorm!", monster),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sand_and_storms_hurt() {
+        let normal = String::from("Normal");
+        assert_eq!(residual_damage(Weather::Rain, &normal), 0.0);
+        assert_eq!(residual_damage(Weather::Sun, &normal), 0.0);
+        assert_eq!(residual_damage(Weather::Sandstorm, &normal), RESIDUAL_DAMAGE);
+        assert_eq!(residual_damage(Weather::Storm, &normal), RESIDUAL_DAMAGE);
+    }
+
+    #[test]
+    fn some_types_are_immune() {
+        let ground = String::from("Ground");
+        let electric = String::from("Electric");
+        assert_eq!(residual_damage(Weather::Sandstorm, &ground), 0.0);
+        assert_eq!(residual_damage(Weather::Storm, &ground), 0.0);
+        assert_eq!(residual_damage(Weather::Storm, &electric), 0.0);
+        assert_eq!(residual_damage(Weather::Sandstorm, &electric), RESIDUAL_DAMAGE);
+    }
+
+    #[test]
+    fn weather_boosts_and_weakens_types() {
+        assert_eq!(type_multiplier(Weather::Rain, &String::from("Water")), 1.5);
+        assert_eq!(type_multiplier(Weather::Rain, &String::from("Fire")), 0.5);
+        assert_eq!(type_multiplier(Weather::Sun, &String::from("Grass")), 1.0);
+        assert!(parse_weather("Storm").unwrap() == Weather::Storm);
+        assert!(parse_weather("Fog").is_err());
+    }
+}